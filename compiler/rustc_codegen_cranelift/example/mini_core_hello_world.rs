@@ -289,7 +289,7 @@ struct ExternTypeWrapper {
 
     from_decimal_string();
 
-    #[cfg(not(any(jit, windows)))]
+    #[cfg(not(any(jit, target_env = "msvc")))]
     test_tls();
 
     #[cfg(all(not(jit), target_os = "linux"))]
@@ -355,13 +355,13 @@ fn pthread_join(
 #[cfg(not(jit))]
 static mut TLS: u8 = 42;
 
-#[cfg(not(jit))]
+#[cfg(not(any(jit, windows)))]
 extern "C" fn mutate_tls(_: *mut c_void) -> *mut c_void {
     unsafe { TLS = 0; }
     0 as *mut c_void
 }
 
-#[cfg(not(jit))]
+#[cfg(not(any(jit, windows)))]
 fn test_tls() {
     unsafe {
         let mut attr: pthread_attr_t = zeroed();
@@ -387,6 +387,47 @@ fn test_tls() {
     }
 }
 
+#[cfg(all(not(jit), windows, target_env = "gnu"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateThread(
+        attrs: *mut c_void,
+        stack_size: usize,
+        start: extern "system" fn(_: *mut c_void) -> u32,
+        param: *mut c_void,
+        flags: u32,
+        thread_id: *mut u32,
+    ) -> *mut c_void;
+
+    fn WaitForSingleObject(handle: *mut c_void, milliseconds: u32) -> u32;
+}
+
+#[cfg(all(not(jit), windows, target_env = "gnu"))]
+extern "system" fn mutate_tls(_: *mut c_void) -> u32 {
+    unsafe { TLS = 0; }
+    0
+}
+
+// MinGW doesn't support native TLS, so this goes through `__emutls_get_address`.
+#[cfg(all(not(jit), windows, target_env = "gnu"))]
+fn test_tls() {
+    unsafe {
+        assert_eq!(TLS, 42);
+
+        let thread =
+            CreateThread(0 as *mut c_void, 0, mutate_tls, 0 as *mut c_void, 0, 0 as *mut u32);
+        if thread as usize == 0 {
+            assert!(false);
+        }
+        WaitForSingleObject(thread, 0xFFFFFFFF /* INFINITE */);
+
+        // TLS of main thread must not have been changed by the other thread.
+        assert_eq!(TLS, 42);
+
+        puts("TLS works!\n\0" as *const str as *const i8);
+    }
+}
+
 // Copied ui/issues/issue-61696.rs
 
 pub enum Infallible {}
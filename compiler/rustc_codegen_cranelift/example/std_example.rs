@@ -1,4 +1,4 @@
-#![feature(core_intrinsics, generators, generator_trait, is_sorted)]
+#![feature(core_intrinsics, intrinsics, generators, generator_trait, is_sorted, bench_black_box, asm, naked_functions, c_variadic, abi_thiscall, abi_vectorcall, c_unwind, global_asm)]
 
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
@@ -41,15 +41,43 @@ fn main() {
     println!("{}", 2.3f32.sqrt());
     println!("{}", 2.3f32.floor());
     println!("{}", 2.3f32.ceil());
+    assert_eq!((-2.5f64).floor(), -3.0);
+    assert_eq!((-2.5f32).ceil(), -2.0);
+    assert_eq!((-2.7f64).trunc(), -2.0);
+    assert_eq!(2.5f32.round(), 3.0);
+    assert_eq!(unsafe { core::intrinsics::rintf64(2.5) }, 2.0);
+    assert_eq!(unsafe { core::intrinsics::nearbyintf32(3.5) }, 4.0);
     println!("{}", 2.3f32.min(1.0));
     println!("{}", 2.3f32.max(1.0));
+    assert_eq!(f32::NAN.min(1.0), 1.0);
+    assert_eq!(1.0f64.min(f64::NAN), 1.0);
+    assert_eq!(f64::NAN.max(-1.0), -1.0);
+    assert_eq!((-1.0f32).max(f32::NAN), -1.0);
+    assert!(f32::NAN.max(f32::NAN).is_nan());
     println!("{}", 2.3f32.powi(2));
+    assert_eq!(2.0f64.powi(-2), 0.25);
+    assert_eq!(2.0f32.powi(0), 1.0);
+    let exp = std::env::args().count().min(1) as i32 + 10;
+    assert_eq!(2.0f64.powi(exp), 2048.0);
+    assert_eq!(2.0f32.powi(-exp), 1.0 / 2048.0);
     println!("{}", 2.3f32.log2());
     assert_eq!(2.3f32.copysign(-1.0), -2.3f32);
     println!("{}", 2.3f32.powf(2.0));
 
+    unsafe {
+        assert_eq!(core::intrinsics::fadd_fast(1.5f32, 2.0), 3.5);
+        assert_eq!(core::intrinsics::fsub_fast(1.5f64, 2.0), -0.5);
+        assert_eq!(core::intrinsics::fmul_fast(1.5f32, 2.0), 3.0);
+        assert_eq!(core::intrinsics::fdiv_fast(1.5f64, 2.0), 0.75);
+        assert_eq!(core::intrinsics::frem_fast(7.5f32, 2.0), 1.5);
+    }
+
     assert_eq!(i64::MAX.checked_mul(2), None);
 
+    assert_eq!(std::hint::black_box(42u8), 42);
+    assert_eq!(std::hint::black_box((1u16, 2u64)), (1, 2));
+    assert_eq!(std::hint::black_box([3u32; 16])[15], 3);
+
     assert_eq!(-128i8, (-128i8).saturating_sub(1));
     assert_eq!(127i8, 127i8.saturating_sub(-128));
     assert_eq!(-128i8, (-128i8).saturating_add(-128));
@@ -88,6 +116,14 @@ fn main() {
     assert_eq!(houndred_f64 as i128, 100);
     assert_eq!(1u128.rotate_left(2), 4);
 
+    // 128bit integers are passed in a register pair, or on the stack if there is only a single
+    // register left.
+    assert_eq!(
+        i128_after_five_regs(1, 2, 3, 4, 5, 0x1234_5678_9ABC_DEF0_0FED_CBA9_8765_4321),
+        0x1234_5678_9ABC_DEF0_0FED_CBA9_8765_4321 + 15,
+    );
+    assert_eq!(u128_div(u128::MAX, 3), u128::MAX / 3);
+
     // Test signed 128bit comparing
     let max = usize::MAX as i128;
     if 100i128 < 0i128 || 100i128 > max {
@@ -96,6 +132,22 @@ fn main() {
 
     test_checked_mul();
 
+    // Zero variant enums like `Infallible` need special handling in the debuginfo.
+    let parsed: Result<String, std::convert::Infallible> = "abc".parse();
+    assert_eq!(parsed.unwrap(), "abc");
+
+    test_copy_nonoverlapping();
+    test_write_bytes();
+    test_volatile();
+    test_variadic_call();
+    #[cfg(all(target_arch = "aarch64", target_os = "macos", not(jit)))]
+    test_variadic_call_sret();
+    // FIXME support variadic function definitions in JIT mode and on other targets
+    #[cfg(all(target_arch = "x86_64", not(windows), not(target_os = "macos"), not(jit)))]
+    test_variadic_def();
+    test_c_unwind_call();
+    test_thread_locals();
+
     let _a = 1u32 << 2u8;
 
     let empty: [i32; 0] = [];
@@ -103,6 +155,11 @@ fn main() {
 
     println!("{:?}", std::intrinsics::caller_location());
 
+    test_extern_c_aggregates();
+
+    #[cfg(target_os = "linux")]
+    test_asm_reg_classes();
+
     #[cfg(target_arch = "x86_64")]
     unsafe {
         test_simd();
@@ -128,6 +185,18 @@ enum Nums {
     };
 }
 
+#[allow(improper_ctypes_definitions)]
+#[inline(never)]
+extern "C" fn i128_after_five_regs(a: u64, b: u64, c: u64, d: u64, e: u64, f: i128) -> i128 {
+    f + (a + b + c + d + e) as i128
+}
+
+#[allow(improper_ctypes_definitions)]
+#[inline(never)]
+extern "C" fn u128_div(a: u128, b: u128) -> u128 {
+    a / b
+}
+
 fn panic(_: u128) {
     panic!();
 }
@@ -158,10 +227,21 @@ unsafe fn test_simd() {
     test_mm_extract_epi8();
     test_mm_insert_epi16();
 
+    let sum = add_m128i_extern_c(_mm_set1_epi8(1), _mm_set1_epi8(2));
+    assert_eq_m128i(sum, _mm_set1_epi8(3));
+
     let mask1 = _mm_movemask_epi8(dbg!(_mm_setr_epi8(255u8 as i8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0)));
     assert_eq!(mask1, 1);
 }
 
+// Vectors are passed in vector registers by the SysV calling convention.
+#[cfg(target_arch = "x86_64")]
+#[allow(improper_ctypes_definitions)]
+#[inline(never)]
+extern "C" fn add_m128i_extern_c(a: __m128i, b: __m128i) -> __m128i {
+    unsafe { _mm_add_epi8(a, b) }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "sse2")]
 unsafe fn test_mm_slli_si128() {
@@ -309,6 +389,359 @@ unsafe fn test_mm_insert_epi16() {
     assert_eq_m128i(r, e);
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct IntFloat {
+    a: i32,
+    b: f32,
+    c: f64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ThreeFloats {
+    a: f32,
+    b: f32,
+    c: f32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct OddTail {
+    a: u64,
+    b: [u8; 3],
+}
+
+#[inline(never)]
+extern "C" fn swap_int_float(v: IntFloat) -> IntFloat {
+    IntFloat { a: v.c as i32, b: v.b * 2.0, c: v.a as f64 }
+}
+
+#[inline(never)]
+extern "C" fn rotate_three_floats(v: ThreeFloats) -> ThreeFloats {
+    ThreeFloats { a: v.b, b: v.c, c: v.a }
+}
+
+#[inline(never)]
+extern "C" fn reverse_odd_tail(v: OddTail) -> OddTail {
+    let b = v.b;
+    OddTail { a: v.a + 1, b: [b[2], b[1], b[0]] }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct FourFloats {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+}
+
+#[inline(never)]
+extern "C" fn make_four_floats(x: f32) -> FourFloats {
+    FourFloats { a: x, b: x + 1.0, c: x + 2.0, d: x + 3.0 }
+}
+
+#[inline(never)]
+extern "C" fn make_three_bytes(x: u8) -> [u8; 3] {
+    [x, x + 1, x + 2]
+}
+
+#[inline(never)]
+extern "C" fn make_twelve_bytes(x: u32) -> [u32; 3] {
+    [x, x * 2, x * 3]
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Vec3 {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+// Homogeneous float aggregate with a nested array member.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Vec4Nested {
+    xy: [f32; 2],
+    z: f32,
+    w: f32,
+}
+
+#[inline(never)]
+extern "C" fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3 { x: a.y * b.z - a.z * b.y, y: a.z * b.x - a.x * b.z, z: a.x * b.y - a.y * b.x }
+}
+
+#[inline(never)]
+extern "C" fn scale_vec4(v: Vec4Nested, s: f32) -> Vec4Nested {
+    Vec4Nested { xy: [v.xy[0] * s, v.xy[1] * s], z: v.z * s, w: v.w * s }
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#[inline(never)]
+extern "aapcs" fn aapcs_three_floats(v: ThreeFloats) -> ThreeFloats {
+    ThreeFloats { a: v.c, b: v.b, c: v.a }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "stdcall" fn stdcall_sum(a: u32, v: IntFloat) -> f64 {
+    a as f64 + v.a as f64 + v.b as f64 + v.c
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "fastcall" fn fastcall_sum(a: u32, b: f64, c: u64) -> f64 {
+    a as f64 + b + c as f64
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "thiscall" fn thiscall_sum(this: &IntFloat, a: u32) -> f64 {
+    this.a as f64 + this.b as f64 + this.c + a as f64
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "vectorcall" fn vectorcall_sum(a: u32, b: f64, c: __m128d, d: f32, e: u64) -> f64 {
+    let mut c_lanes = [0.0; 2];
+    unsafe { _mm_storeu_pd(c_lanes.as_mut_ptr(), c) };
+    a as f64 + b + c_lanes[0] + c_lanes[1] + d as f64 + e as f64
+}
+
+// Arrays are passed like structs with the same size and alignment, not as a decayed pointer.
+#[allow(improper_ctypes_definitions)]
+#[inline(never)]
+extern "C" fn sum_array_args(a: [u8; 3], b: [u16; 5], c: [f32; 4], d: [u64; 3], e: [u8; 11]) -> f64 {
+    a.iter().map(|&x| x as f64).sum::<f64>()
+        + b.iter().map(|&x| x as f64).sum::<f64>()
+        + c.iter().map(|&x| x as f64).sum::<f64>()
+        + d.iter().map(|&x| x as f64).sum::<f64>()
+        + e.iter().map(|&x| x as f64).sum::<f64>()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct U32Pair {
+    a: u32,
+    b: u32,
+}
+
+#[inline(never)]
+extern "C" fn swap_u32_pair(v: U32Pair) -> U32Pair {
+    U32Pair { a: v.b, b: v.a }
+}
+
+// Both conventions must be honored independent of the target default. Use enough integer and float
+// arguments to run out of argument registers for both.
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "sysv64" fn sysv64_many_args(
+    a: u64, b: f64, c: u64, d: f64, e: u64, f: f64, g: u64, h: f64, i: u64, j: f64,
+    k: u64, l: f64, m: u64, n: f64, o: u64, p: f64, q: u64, r: f64, s: IntFloat,
+) -> f64 {
+    (a + c + e + g + i + k + m + o + q) as f64 + b + d + f + h + j + l + n + p + r + s.c
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+extern "win64" fn win64_many_args(a: u64, b: f64, c: u64, d: f64, e: u64, f: f64, g: IntFloat) -> f64 {
+    (a + c + e) as f64 + b + d + f + g.c
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Empty {}
+
+// Empty structs are ignored by most C ABIs, but passed by reference on `x86_64-pc-windows-gnu` for
+// example. Make sure the surrounding arguments are not shifted either way.
+#[allow(improper_ctypes_definitions)]
+#[inline(never)]
+extern "C" fn around_empty(a: u8, _b: Empty, c: u16, _d: Empty, e: u32) -> Empty {
+    assert_eq!((a, c, e), (1, 2, 3));
+    Empty {}
+}
+
+#[repr(C, align(64))]
+#[derive(Copy, Clone)]
+struct OverAligned {
+    a: [u64; 3],
+}
+
+#[inline(never)]
+fn check_over_aligned(v: OverAligned) -> u64 {
+    assert_eq!(&v as *const OverAligned as usize % 64, 0);
+    v.a.iter().sum()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Big {
+    a: [u64; 4],
+}
+
+#[inline(never)]
+extern "C" fn overwrite_big(mut v: Big) -> u64 {
+    v.a[0] = 100;
+    v.a[0] + v.a[1]
+}
+
+#[inline(never)]
+fn overwrite_big_rust(mut v: Big) -> u64 {
+    v.a[0] = 100;
+    v.a[0] + v.a[1]
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn test_asm_reg_classes() {
+    let (a, b) = (std::hint::black_box(40u64), std::hint::black_box(2u64));
+    let sum: u64;
+    unsafe {
+        asm!("lea {0}, [{1} + {2}]", out(reg) sum, in(reg) a, in(reg) b);
+    }
+    assert_eq!(sum, 42);
+
+    let mut x = std::hint::black_box(41u32);
+    unsafe {
+        asm!("inc {0:e}", inout(reg) x);
+    }
+    assert_eq!(x, 42);
+
+    assert_eq!(naked_add_one(41), 42);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[naked]
+extern "C" fn naked_add_one(_x: u64) -> u64 {
+    unsafe {
+        asm!("lea rax, [rdi + 1]", "ret", options(noreturn));
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+fn test_asm_reg_classes() {
+    let (a, b) = (std::hint::black_box(40u64), std::hint::black_box(2u64));
+    let sum: u64;
+    unsafe {
+        asm!("add {0}, {1}, {2}", out(reg) sum, in(reg) a, in(reg) b);
+    }
+    assert_eq!(sum, 42);
+
+    let mut x = std::hint::black_box(41u32);
+    unsafe {
+        asm!("add {0:w}, {0:w}, #1", inout(reg) x);
+    }
+    assert_eq!(x, 42);
+
+    let (c, d) = (std::hint::black_box(1.5f64), std::hint::black_box(2.25f64));
+    let product: f64;
+    unsafe {
+        asm!("fmul {0:d}, {1:d}, {2:d}", out(vreg) product, in(vreg) c, in(vreg) d);
+    }
+    assert_eq!(product, 3.375);
+
+    // Explicit registers and clobbers
+    let y: u64;
+    unsafe {
+        asm!("mov x9, #7", "mov {0}, x9", out(reg) y, out("x9") _);
+    }
+    assert_eq!(y, 7);
+
+    assert_eq!(naked_add_one(41), 42);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+#[naked]
+extern "C" fn naked_add_one(_x: u64) -> u64 {
+    unsafe {
+        asm!("add x0, x0, #1", "ret", options(noreturn));
+    }
+}
+
+#[cfg(all(target_os = "linux", not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn test_asm_reg_classes() {}
+
+fn test_extern_c_aggregates() {
+    assert_eq!(
+        swap_int_float(IntFloat { a: 1, b: 2.5, c: 3.0 }),
+        IntFloat { a: 3, b: 5.0, c: 1.0 },
+    );
+    assert_eq!(
+        rotate_three_floats(ThreeFloats { a: 1.0, b: 2.0, c: 3.0 }),
+        ThreeFloats { a: 2.0, b: 3.0, c: 1.0 },
+    );
+    let odd_tail = reverse_odd_tail(OddTail { a: 41, b: [1, 2, 3] });
+    assert_eq!({ odd_tail.a }, 42);
+    assert_eq!({ odd_tail.b }, [3, 2, 1]);
+
+    assert_eq!(make_four_floats(1.0), FourFloats { a: 1.0, b: 2.0, c: 3.0, d: 4.0 });
+    assert_eq!(make_three_bytes(7), [7, 8, 9]);
+    assert_eq!(make_twelve_bytes(5), [5, 10, 15]);
+    assert_eq!(
+        cross(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 }),
+        Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+    );
+    assert_eq!(
+        scale_vec4(Vec4Nested { xy: [1.0, 2.0], z: 3.0, w: 4.0 }, 2.0),
+        Vec4Nested { xy: [2.0, 4.0], z: 6.0, w: 8.0 },
+    );
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    assert_eq!(
+        aapcs_three_floats(ThreeFloats { a: 1.0, b: 2.0, c: 3.0 }),
+        ThreeFloats { a: 3.0, b: 2.0, c: 1.0 },
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        assert_eq!(stdcall_sum(1, IntFloat { a: 2, b: 3.0, c: 4.0 }), 10.0);
+        assert_eq!(fastcall_sum(1, 2.0, 3), 6.0);
+        assert_eq!(thiscall_sum(&IntFloat { a: 1, b: 2.0, c: 3.0 }, 4), 10.0);
+        let c = unsafe { _mm_set_pd(4.0, 3.0) };
+        assert_eq!(vectorcall_sum(1, 2.0, c, 5.0, 6), 21.0);
+    }
+
+    assert_eq!(
+        sum_array_args([1, 2, 3], [4, 5, 6, 7, 8], [9.0, 10.0, 11.0, 12.0], [13, 14, 15], [1; 11]),
+        131.0,
+    );
+
+    let mut pair = U32Pair { a: 1, b: 2 };
+    for _ in 0..3 {
+        pair = swap_u32_pair(pair);
+    }
+    assert_eq!(pair, U32Pair { a: 2, b: 1 });
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let s = IntFloat { a: 0, b: 0.0, c: 19.0 };
+        assert_eq!(
+            sysv64_many_args(
+                1, 2.0, 3, 4.0, 5, 6.0, 7, 8.0, 9, 10.0, 11, 12.0, 13, 14.0, 15, 16.0, 17, 18.0, s,
+            ),
+            190.0,
+        );
+        assert_eq!(win64_many_args(1, 2.0, 3, 4.0, 5, 6.0, s), 40.0);
+        let win64_fn_ptr: extern "win64" fn(u64, f64, u64, f64, u64, f64, IntFloat) -> f64 =
+            win64_many_args;
+        assert_eq!(win64_fn_ptr(1, 2.0, 3, 4.0, 5, 6.0, s), 40.0);
+    }
+
+    assert_eq!(around_empty(1, Empty {}, 2, Empty {}, 3), Empty {});
+
+    let over_aligned = OverAligned { a: [1, 2, 3] };
+    assert_eq!(&over_aligned as *const OverAligned as usize % 64, 0);
+    assert_eq!(check_over_aligned(over_aligned), 6);
+
+    // Indirectly passed arguments may be modified by the callee, so they must be copied unless
+    // they are moved.
+    let big = Big { a: [1, 2, 3, 4] };
+    assert_eq!(overwrite_big(big), 102);
+    assert_eq!(overwrite_big_rust(big), 102);
+    assert_eq!(big.a, [1, 2, 3, 4]);
+}
+
 fn test_checked_mul() {
     let u: Option<u8> = u8::from_str_radix("1000", 10).ok();
     assert_eq!(u, None);
@@ -330,6 +763,267 @@ fn test_checked_mul() {
     assert_eq!(i64::MIN.checked_mul(i64::MIN), None);
 }
 
+fn test_copy_nonoverlapping() {
+    use std::hint::black_box;
+    use std::intrinsics::copy_nonoverlapping;
+
+    let src = [1u16, 2, 3, 4, 5];
+    let mut dst = [0u16; 5];
+    unsafe {
+        // constant count
+        copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), 3);
+        assert_eq!(dst, [1, 2, 3, 0, 0]);
+        copy_nonoverlapping(src.as_ptr().add(3), dst.as_mut_ptr(), 0);
+        assert_eq!(dst, [1, 2, 3, 0, 0]);
+
+        // non-constant count
+        copy_nonoverlapping(src.as_ptr().add(3), dst.as_mut_ptr().add(3), black_box(2));
+        assert_eq!(dst, src);
+        copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), black_box(0));
+        assert_eq!(dst, src);
+    }
+
+    // too big for the inline small memory copy
+    let src = [0x0102_0304_0506_0708u64; 64];
+    let mut dst = [0u64; 64];
+    unsafe {
+        copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), 64);
+        assert_eq!(dst, src);
+        dst = [0u64; 64];
+        copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), black_box(64));
+        assert_eq!(dst, src);
+    }
+}
+
+fn test_write_bytes() {
+    use std::hint::black_box;
+
+    // `std::ptr::write_bytes` is a wrapper, so call the intrinsic directly to get constant
+    // arguments.
+    extern "rust-intrinsic" {
+        fn write_bytes<T>(dst: *mut T, val: u8, count: usize);
+    }
+
+    let mut dst = [0u32; 4];
+    unsafe {
+        // constant value and count
+        write_bytes(dst.as_mut_ptr(), 0xab, 2);
+        assert_eq!(dst, [0xabababab, 0xabababab, 0, 0]);
+        write_bytes(dst.as_mut_ptr(), 0xff, 0);
+        assert_eq!(dst, [0xabababab, 0xabababab, 0, 0]);
+
+        // non-constant value
+        write_bytes(dst.as_mut_ptr().add(2), black_box(0x12), 1);
+        assert_eq!(dst, [0xabababab, 0xabababab, 0x12121212, 0]);
+
+        // non-constant count
+        write_bytes(dst.as_mut_ptr().add(3), 0x34, black_box(1));
+        assert_eq!(dst, [0xabababab, 0xabababab, 0x12121212, 0x34343434]);
+        write_bytes(dst.as_mut_ptr(), 0x56, black_box(0));
+        assert_eq!(dst, [0xabababab, 0xabababab, 0x12121212, 0x34343434]);
+
+        // non-constant value and count
+        write_bytes(dst.as_mut_ptr(), black_box(0), black_box(4));
+        assert_eq!(dst, [0; 4]);
+    }
+
+    // too big for the inline small memset
+    let mut dst = [0u64; 64];
+    unsafe {
+        write_bytes(dst.as_mut_ptr(), 0x01, 64);
+        assert_eq!(dst, [0x0101_0101_0101_0101; 64]);
+        write_bytes(dst.as_mut_ptr(), black_box(0x02), black_box(64));
+        assert_eq!(dst, [0x0202_0202_0202_0202; 64]);
+    }
+}
+
+fn test_volatile() {
+    use std::hint::black_box;
+    use std::intrinsics::{
+        unaligned_volatile_load, unaligned_volatile_store, volatile_copy_memory,
+        volatile_copy_nonoverlapping_memory, volatile_load, volatile_set_memory, volatile_store,
+    };
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Aggregate {
+        a: u8,
+        b: u64,
+        c: [u16; 3],
+    }
+
+    let mut agg = Aggregate { a: 0, b: 0, c: [0; 3] };
+    unsafe {
+        volatile_store(&mut agg, Aggregate { a: 1, b: 2, c: [3, 4, 5] });
+        assert_eq!(agg, Aggregate { a: 1, b: 2, c: [3, 4, 5] });
+        assert_eq!(volatile_load(&agg), Aggregate { a: 1, b: 2, c: [3, 4, 5] });
+    }
+
+    let mut buf = [0u8; 9];
+    unsafe {
+        let ptr = buf.as_mut_ptr().add(1) as *mut u64;
+        unaligned_volatile_store(ptr, 0x0102_0304_0506_0708);
+        assert_eq!(buf[0], 0);
+        assert_eq!(&buf[1..], &0x0102_0304_0506_0708u64.to_ne_bytes());
+        assert_eq!(unaligned_volatile_load(ptr), 0x0102_0304_0506_0708);
+    }
+
+    let mut buf = [0u8; 1 + std::mem::size_of::<Aggregate>()];
+    unsafe {
+        let ptr = buf.as_mut_ptr().add(1) as *mut Aggregate;
+        unaligned_volatile_store(ptr, Aggregate { a: 6, b: 7, c: [8, 9, 10] });
+        assert_eq!(unaligned_volatile_load(ptr), Aggregate { a: 6, b: 7, c: [8, 9, 10] });
+    }
+
+    let mut buf = [0u32; 8];
+    let x = 0xabab_abab;
+    unsafe {
+        volatile_set_memory(buf.as_mut_ptr().add(1), 0xab, black_box(6));
+        assert_eq!(buf, [0, x, x, x, x, x, x, 0]);
+
+        let src = [1u32, 2, 3, 4];
+        volatile_copy_nonoverlapping_memory(buf.as_mut_ptr(), src.as_ptr(), black_box(4));
+        assert_eq!(buf, [1, 2, 3, 4, x, x, x, 0]);
+
+        // Overlapping copies in both directions
+        volatile_copy_memory(buf.as_mut_ptr().add(2), buf.as_ptr(), black_box(4));
+        assert_eq!(buf, [1, 2, 1, 2, 3, 4, x, 0]);
+        volatile_copy_memory(buf.as_mut_ptr(), buf.as_ptr().add(3), black_box(5));
+        assert_eq!(buf, [2, 3, 4, x, 0, 4, x, 0]);
+    }
+}
+
+fn test_variadic_call() {
+    extern "C" {
+        fn snprintf(buf: *mut u8, len: usize, format: *const u8, ...) -> i32;
+    }
+
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        snprintf(
+            buf.as_mut_ptr(),
+            buf.len(),
+            "%d %lld %u %lld %d\0".as_ptr(),
+            -1i32,
+            -2i64,
+            3u32,
+            i64::MAX,
+            i32::MIN,
+        )
+    };
+    assert_eq!(&buf[..len as usize], &b"-1 -2 3 9223372036854775807 -2147483648"[..]);
+}
+
+// Returns `Big { a, b, c }` where `b` and `c` are the variadic arguments. They are passed on the
+// stack, while the return place pointer is passed in x8.
+#[cfg(all(target_arch = "aarch64", target_os = "macos", not(jit)))]
+global_asm! {
+    "
+    .global _va_make_big
+    _va_make_big:
+    ldp x9, x10, [sp]
+    stp x0, x9, [x8]
+    str x10, [x8, #16]
+    ret
+    "
+}
+
+#[cfg(all(target_arch = "aarch64", target_os = "macos", not(jit)))]
+fn test_variadic_call_sret() {
+    #[repr(C)]
+    #[derive(Debug, PartialEq)]
+    struct Big {
+        a: u64,
+        b: u64,
+        c: u64,
+    }
+
+    extern "C" {
+        fn va_make_big(a: u64, ...) -> Big;
+    }
+
+    assert_eq!(unsafe { va_make_big(1, 2u64, 3u64) }, Big { a: 1, b: 2, c: 3 });
+}
+
+#[cfg(all(target_arch = "x86_64", not(windows), not(target_os = "macos"), not(jit)))]
+fn test_variadic_def() {
+    unsafe extern "C" fn sum(count: usize, mut args: ...) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..count {
+            sum += args.arg::<i32>() as f64;
+            sum += args.arg::<f64>();
+        }
+        sum
+    }
+
+    unsafe extern "C" fn sum_twice(count: usize, mut args: ...) -> i64 {
+        let mut copy = args.clone();
+        let mut sum = 0;
+        for _ in 0..count {
+            sum += args.arg::<i64>();
+        }
+        for _ in 0..count {
+            sum += copy.arg::<i64>();
+        }
+        sum
+    }
+
+    #[repr(C)]
+    #[derive(Debug, PartialEq)]
+    struct Big {
+        a: u64,
+        b: u64,
+        c: u64,
+    }
+
+    // The return place pointer takes the first argument register.
+    unsafe extern "C" fn make_big(a: u64, mut args: ...) -> Big {
+        Big { a, b: args.arg::<u64>(), c: args.arg::<u64>() }
+    }
+
+    unsafe {
+        // Some of the integer arguments are passed on the stack.
+        assert_eq!(sum(8, 1, 0.5, 2, 0.5, 3, 0.5, 4, 0.5, 5, 0.5, 6, 0.5, 7, 0.5, 8, 0.5), 40.0);
+        // Some of the float arguments are passed on the stack.
+        assert_eq!(sum(9, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0, 1, 1.0), 18.0);
+        assert_eq!(sum_twice(7, 1i64, 2i64, 3i64, 4i64, 5i64, 6i64, 7i64), 56);
+        assert_eq!(make_big(1, 2u64, 3u64), Big { a: 1, b: 2, c: 3 });
+    }
+}
+
+fn test_c_unwind_call() {
+    extern "C-unwind" fn double(a: u32) -> u32 {
+        a * 2
+    }
+
+    // A foreign unwind out of this call would abort, but returning normally must still work.
+    let double: extern "C-unwind" fn(u32) -> u32 = std::hint::black_box(double);
+    assert_eq!(double(21), 42);
+}
+
+fn test_thread_locals() {
+    use std::cell::Cell;
+
+    thread_local! {
+        static COUNTER: Cell<u32> = Cell::new(1);
+    }
+
+    COUNTER.with(|counter| counter.set(counter.get() + 1));
+    assert_eq!(COUNTER.with(|counter| counter.get()), 2);
+
+    // The thread locals of std are only accessed inside std itself here.
+    let main_thread_id = std::thread::current().id();
+    assert_eq!(std::thread::current().id(), main_thread_id);
+
+    // FIXME support lazy jit when multi threading
+    #[cfg(not(lazy_jit))]
+    std::thread::spawn(move || {
+        assert_eq!(COUNTER.with(|counter| counter.get()), 1);
+        assert_ne!(std::thread::current().id(), main_thread_id);
+    })
+    .join()
+    .unwrap();
+}
+
 #[derive(PartialEq)]
 enum LoopState {
     Continue(()),
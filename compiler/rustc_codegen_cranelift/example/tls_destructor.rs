@@ -0,0 +1,38 @@
+// Check that the destructors of thread locals run when a thread exits. For the main thread this
+// happens after `main` returned, so scripts/tests.sh checks the output for it.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SPAWNED_THREAD_DESTRUCTOR_RAN: AtomicBool = AtomicBool::new(false);
+
+struct Guard(bool);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.0 {
+            println!("main thread destructor ran");
+        } else {
+            SPAWNED_THREAD_DESTRUCTOR_RAN.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+thread_local! {
+    static GUARD: RefCell<Option<Guard>> = RefCell::new(None);
+}
+
+fn main() {
+    // FIXME support lazy jit when multi threading
+    #[cfg(not(lazy_jit))]
+    {
+        std::thread::spawn(|| {
+            GUARD.with(|guard| *guard.borrow_mut() = Some(Guard(false)));
+        })
+        .join()
+        .unwrap();
+        assert!(SPAWNED_THREAD_DESTRUCTOR_RAN.load(Ordering::SeqCst));
+    }
+
+    GUARD.with(|guard| *guard.borrow_mut() = Some(Guard(true)));
+}
@@ -17,6 +17,20 @@ fn nested_tracked() -> &'static Location<'static> {
     tracked()
 }
 
+#[track_caller]
+fn tracked_tracked() -> &'static Location<'static> {
+    tracked()
+}
+
+trait Tracked {
+    #[track_caller]
+    fn location(&self) -> &'static Location<'static> {
+        Location::caller()
+    }
+}
+
+impl Tracked for () {}
+
 fn main() {
     let location = Location::caller();
     assert_eq!(location.file(), file!());
@@ -37,4 +51,19 @@ fn main() {
     assert_eq!(contained.file(), file!());
     assert_eq!(contained.line(), 17);
     assert_eq!(contained.column(), 5);
+
+    let propagated = tracked_tracked();
+    assert_eq!(propagated.file(), file!());
+    assert_eq!(propagated.line(), 55);
+    assert_eq!(propagated.column(), 22);
+
+    let method = ().location();
+    assert_eq!(method.file(), file!());
+    assert_eq!(method.line(), 60);
+    assert_eq!(method.column(), 18);
+
+    // Calling through a function pointer goes through a reify shim, which passes the location of
+    // the shim itself.
+    let reified = (tracked as fn() -> &'static Location<'static>)();
+    assert_eq!(reified.file(), file!());
 }
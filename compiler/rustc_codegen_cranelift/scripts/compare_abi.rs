@@ -0,0 +1,293 @@
+#!/bin/bash
+#![forbid(unsafe_code)]/* This line is ignored by bash
+# This block is ignored by rustc
+pushd $(dirname "$0")/../
+source build/config.sh
+popd
+INPUT=$1 exec $RUSTC $RUSTFLAGS -Cllvm-args=mode=jit -Cprefer-dynamic $0
+#*/
+
+//! This program compares the signatures cg_clif uses for all functions with a non-Rust abi in a
+//! source file against the signatures the LLVM backend uses for them. Every mismatch is printed as
+//! a diff and results in a non-zero exit code.
+//!
+//! Usage: ./compare_abi.rs <rust source file>
+//!
+//! This file is specially crafted to be both a valid bash script and valid rust source file. If
+//! executed as bash script this will run the rust source using cg_clif in JIT mode. The rustc in
+//! `PATH` is used as LLVM backend.
+//!
+//! Both sides are normalized to a flat list of Cranelift types with their extension and `sret` or
+//! `byval` purpose. LLVM aggregates are flattened and pointers are replaced by pointer sized ints.
+
+use std::collections::BTreeMap;
+use std::process::Command;
+
+type Signatures = BTreeMap<String, (Vec<String>, Vec<String>)>;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let input = std::env::var("INPUT").unwrap();
+    if input.is_empty() {
+        println!("Usage: ./compare_abi.rs <rust source file>");
+        std::process::exit(1);
+    }
+
+    let out_dir = std::env::temp_dir().join(format!("cg_clif_compare_abi_{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir)?;
+
+    let cg_clif = Command::new(std::env::var("RUSTC")?)
+        .args(std::env::var("RUSTFLAGS").unwrap_or_default().split_whitespace())
+        .args(&["-Cllvm-args=dump_abi=true", "--emit", "obj", "--crate-name", "compare_abi"])
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .arg(&input)
+        .output()?;
+    if !cg_clif.status.success() {
+        return Err(format!(
+            "cg_clif failed to compile {}:\n{}",
+            input,
+            String::from_utf8_lossy(&cg_clif.stderr)
+        )
+        .into());
+    }
+
+    let llvm_ir = out_dir.join("compare_abi.ll");
+    let llvm = Command::new("rustc")
+        .args(&["-Cpanic=abort", "-Ccodegen-units=1", "--emit", "llvm-ir", "-o"])
+        .arg(&llvm_ir)
+        .arg(&input)
+        .output()?;
+    if !llvm.status.success() {
+        return Err(format!(
+            "rustc failed to compile {}:\n{}",
+            input,
+            String::from_utf8_lossy(&llvm.stderr)
+        )
+        .into());
+    }
+
+    let clif_sigs = parse_abi_dump(&String::from_utf8_lossy(&cg_clif.stderr));
+    let llvm_sigs = parse_llvm_ir(&std::fs::read_to_string(&llvm_ir)?);
+    std::fs::remove_dir_all(&out_dir)?;
+
+    let mut mismatches = 0;
+    for (symbol, clif_sig) in &clif_sigs {
+        // Functions can be missing from the LLVM ir when they have been inlined.
+        let llvm_sig = match llvm_sigs.get(symbol) {
+            Some(llvm_sig) => llvm_sig,
+            None => continue,
+        };
+        if clif_sig != llvm_sig {
+            mismatches += 1;
+            println!("--- {}", symbol);
+            println!("-llvm    ({}) -> ({})", llvm_sig.0.join(", "), llvm_sig.1.join(", "));
+            println!("+cg_clif ({}) -> ({})", clif_sig.0.join(", "), clif_sig.1.join(", "));
+        }
+    }
+
+    println!("compared {} signatures, {} mismatches", clif_sigs.len(), mismatches);
+    if mismatches != 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Parse the output of `-Cllvm-args=dump_abi=true`.
+fn parse_abi_dump(dump: &str) -> Signatures {
+    fn clif_params(params: &str) -> Vec<String> {
+        params
+            .split(", ")
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let mut parts = param.split(' ');
+                let mut normalized = parts.next().unwrap().to_owned();
+                for flag in parts {
+                    match flag {
+                        "uext" | "sext" | "sret" => normalized.push_str(&format!(" {}", flag)),
+                        _ if flag.starts_with("sarg(") => normalized.push_str(" byval"),
+                        _ => {}
+                    }
+                }
+                normalized
+            })
+            .collect()
+    }
+
+    let mut sigs = Signatures::new();
+    let mut current: Option<(String, Vec<String>, Vec<String>)> = None;
+    for line in dump.lines() {
+        // Every function is dumped as a single note. rustc indents all lines after the first one.
+        let line = line.trim_start();
+        if let Some(header) = line.strip_prefix("note: abi ") {
+            if let Some((symbol, params, returns)) = current.take() {
+                sigs.insert(symbol, (params, returns));
+            }
+            let mut parts = header.split(' ');
+            let symbol = parts.next().unwrap().to_owned();
+            // The Rust abi is not stable, so there is nothing to compare against.
+            if parts.next() != Some("conv=Rust") {
+                current = Some((symbol, vec![], vec![]));
+            }
+        } else if let Some((_, params, returns)) = &mut current {
+            let values = match line.rfind(" => [") {
+                Some(index) => &line[index + " => [".len()..],
+                None => continue,
+            };
+            if line.starts_with("ret ") {
+                let (ret_values, ret_ptr) = values.split_at(values.find("] ret_ptr=[").unwrap());
+                params.splice(0..0, clif_params(&ret_ptr["] ret_ptr=[".len()..ret_ptr.len() - 1]));
+                returns.extend(clif_params(ret_values));
+            } else if line.starts_with("arg") {
+                params.extend(clif_params(&values[..values.len() - 1]));
+            }
+        }
+    }
+    if let Some((symbol, params, returns)) = current.take() {
+        sigs.insert(symbol, (params, returns));
+    }
+    sigs
+}
+
+/// Parse the signatures of all function definitions in textual LLVM ir.
+fn parse_llvm_ir(ir: &str) -> Signatures {
+    let mut sigs = Signatures::new();
+    for line in ir.lines() {
+        let line = match line.strip_prefix("define ") {
+            Some(line) => line,
+            None => continue,
+        };
+        let at = line.find(" @").unwrap();
+        let name_and_params = &line[at + 2..];
+        let open_paren = name_and_params.find('(').unwrap();
+        let symbol = name_and_params[..open_paren].trim_matches('"').to_owned();
+
+        let params_str = &name_and_params[open_paren + 1..];
+        let params_str = &params_str[..matching_close(params_str)];
+        let mut params = vec![];
+        for param in split_top_level(params_str) {
+            let (types, attrs) = parse_type(param.trim());
+            params.extend(with_flags(types, attrs));
+        }
+
+        // The return type is the last type before the symbol name. Everything before it are
+        // linkage, visibility and attributes.
+        let prefix = &line[..at];
+        let mut returns = vec![];
+        let mut start = 0;
+        while start < prefix.len() {
+            let (types, rest) = parse_type(&prefix[start..]);
+            if !types.is_empty() && rest.trim().is_empty() {
+                if types != ["void"] {
+                    returns = with_flags(types, &prefix[..start]);
+                }
+                break;
+            }
+            start += prefix[start..].find(' ').map(|i| i + 1).unwrap_or(prefix.len() - start);
+        }
+
+        sigs.insert(symbol, (params, returns));
+    }
+    sigs
+}
+
+fn with_flags(types: Vec<String>, attrs: &str) -> Vec<String> {
+    let mut flags = String::new();
+    for attr in attrs.split(|c: char| c == ' ' || c == '(') {
+        match attr {
+            "zeroext" => flags.push_str(" uext"),
+            "signext" => flags.push_str(" sext"),
+            "sret" => flags.push_str(" sret"),
+            "byval" => flags.push_str(" byval"),
+            _ => {}
+        }
+    }
+    types.into_iter().map(|ty| ty + &flags).collect()
+}
+
+/// Parse an LLVM type at the start of `s` and return it flattened to Cranelift types together
+/// with the remaining text. Returns no types if `s` doesn't start with a type.
+fn parse_type(s: &str) -> (Vec<String>, &str) {
+    let s = s.trim_start();
+    let (mut types, mut rest) =
+        if let Some(inner) = s.strip_prefix("<{").or_else(|| s.strip_prefix('{')) {
+            let close = matching_close(inner);
+            let types = split_top_level(&inner[..close])
+                .into_iter()
+                .flat_map(|field| parse_type(field).0)
+                .collect();
+            (types, inner[close + 1..].trim_start_matches('>'))
+        } else if let Some(inner) = s.strip_prefix('[') {
+            let close = matching_close(inner);
+            let (count, elem) = inner[..close].split_at(inner.find(" x ").unwrap());
+            let elem = parse_type(&elem[" x ".len()..]).0;
+            let count: usize = count.trim().parse().unwrap();
+            (elem.iter().cycle().take(elem.len() * count).cloned().collect(), &inner[close + 1..])
+        } else if let Some(inner) = s.strip_prefix('<') {
+            let close = matching_close(inner);
+            let (count, elem) = inner[..close].split_at(inner.find(" x ").unwrap());
+            let elem = parse_type(&elem[" x ".len()..]).0;
+            (vec![format!("{}x{}", elem[0], count.trim())], &inner[close + 1..])
+        } else {
+            let end = s
+                .find(|c: char| !(c.is_alphanumeric() || c == '%' || c == '.' || c == '_'))
+                .unwrap_or(s.len());
+            let (word, rest) = s.split_at(end);
+            let ty = match word {
+                "i1" => "i8".to_owned(),
+                "float" => "f32".to_owned(),
+                "double" => "f64".to_owned(),
+                "void" => "void".to_owned(),
+                "ptr" => pointer(),
+                _ if word.starts_with('i') && word[1..].parse::<u32>().is_ok() => word.to_owned(),
+                _ if word.starts_with('%') && word.len() > 1 => "opaque".to_owned(),
+                _ => return (vec![], s),
+            };
+            (vec![ty], rest)
+        };
+
+    while let Some(after_star) = rest.strip_prefix('*') {
+        types = vec![pointer()];
+        rest = after_star;
+    }
+    (types, rest)
+}
+
+fn pointer() -> String {
+    format!("i{}", std::mem::size_of::<usize>() * 8)
+}
+
+/// Find the index of the bracket closing the one right before the start of `s`.
+fn matching_close(s: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' | '[' | '<' => depth += 1,
+            ')' | '}' | ']' | '>' if depth == 0 => return i,
+            ')' | '}' | ']' | '>' => depth -= 1,
+            _ => {}
+        }
+    }
+    panic!("unbalanced brackets in `{}`", s);
+}
+
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' | '[' | '<' => depth += 1,
+            ')' | '}' | ']' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !s[start..].trim().is_empty() {
+        parts.push(&s[start..]);
+    }
+    parts
+}
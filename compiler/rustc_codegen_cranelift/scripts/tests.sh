@@ -26,6 +26,18 @@ function no_sysroot_tests() {
     echo "[AOT] mini_core_hello_world"
     $MY_RUSTC example/mini_core_hello_world.rs --crate-name mini_core_hello_world --crate-type bin -g --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/mini_core_hello_world abc bcd
+
+    if [[ "$TARGET_TRIPLE" == "x86_64-pc-windows-gnu" ]]; then
+        echo "[OBJ] mini_core_hello_world unwind info"
+        $MY_RUSTC example/mini_core_hello_world.rs --crate-name mini_core_hello_world --crate-type bin --emit obj -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        # Every function needs a `RUNTIME_FUNCTION` in `.pdata` referencing its `UNWIND_INFO` in `.xdata`.
+        x86_64-w64-mingw32-objdump -r -j .pdata target/out/mini_core_hello_world.o > target/out/pdata_relocs.txt
+        grep -q "IMAGE_REL_AMD64_ADDR32NB *main$" target/out/pdata_relocs.txt
+        grep -q "IMAGE_REL_AMD64_ADDR32NB *\.xdata" target/out/pdata_relocs.txt
+        # The linker must have turned them into the exception directory of the executable.
+        x86_64-w64-mingw32-objdump -p target/out/mini_core_hello_world.exe | grep -q "The Function Table"
+    fi
+
     # (echo "break set -n main"; echo "run"; sleep 1; echo "si -c 10"; sleep 1; echo "frame variable") | lldb -- ./target/out/mini_core_hello_world abc bcd
 }
 
@@ -43,14 +55,30 @@ function base_sysroot_tests() {
 
     if [[ "$JIT_SUPPORTED" = "1" ]]; then
         echo "[JIT] std_example"
-        $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/std_example.rs --target "$HOST_TRIPLE"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/std_example.rs --cfg jit --target "$HOST_TRIPLE"
 
         echo "[JIT-lazy] std_example"
-        $MY_RUSTC -Cllvm-args=mode=jit-lazy -Cprefer-dynamic example/std_example.rs --cfg lazy_jit --target "$HOST_TRIPLE"
+        $MY_RUSTC -Cllvm-args=mode=jit-lazy -Cprefer-dynamic example/std_example.rs --cfg jit --cfg lazy_jit --target "$HOST_TRIPLE"
     else
         echo "[JIT] std_example (skipped)"
     fi
 
+    echo "[AOT] tls_destructor"
+    $MY_RUSTC example/tls_destructor.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/tls_destructor | grep "main thread destructor ran"
+
+    if [[ "$JIT_SUPPORTED" = "1" ]]; then
+        echo "[JIT] tls_destructor"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/tls_destructor.rs --target "$HOST_TRIPLE" \
+            | grep "main thread destructor ran"
+
+        echo "[JIT-lazy] tls_destructor"
+        $MY_RUSTC -Cllvm-args=mode=jit-lazy -Cprefer-dynamic example/tls_destructor.rs --cfg lazy_jit --target "$HOST_TRIPLE" \
+            | grep "main thread destructor ran"
+    else
+        echo "[JIT] tls_destructor (skipped)"
+    fi
+
     echo "[AOT] dst_field_align"
     # FIXME Re-add -Zmir-opt-level=2 once rust-lang/rust#67529 is fixed.
     $MY_RUSTC example/dst-field-align.rs --crate-name dst_field_align --crate-type bin --target "$TARGET_TRIPLE"
@@ -60,6 +88,17 @@ function base_sysroot_tests() {
     $MY_RUSTC example/std_example.rs --crate-type bin --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/std_example arg
 
+    if [[ "$JIT_SUPPORTED" = "1" ]] && [[ "$HOST_TRIPLE" = "$TARGET_TRIPLE" ]]; then
+        echo "[ABI] std_example"
+        ./scripts/compare_abi.rs example/std_example.rs
+    else
+        echo "[ABI] std_example (skipped)"
+    fi
+
+    echo "[AOT] std_example (cross_cgu_inline)"
+    $MY_RUSTC example/std_example.rs --crate-name std_example_inline --crate-type bin -Copt-level=3 -Cllvm-args=cross_cgu_inline=true -Cllvm-args=cross_cgu_inline_threshold=100 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/std_example_inline arg
+
     echo "[AOT] subslice-patterns-const-eval"
     $MY_RUSTC example/subslice-patterns-const-eval.rs --crate-type bin -Cpanic=abort --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/subslice-patterns-const-eval
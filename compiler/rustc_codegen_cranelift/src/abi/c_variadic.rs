@@ -0,0 +1,219 @@
+//! Support for defining C-variadic functions and reading their variadic arguments.
+//!
+//! Cranelift can't spill the argument registers into a register save area or get the address of
+//! the stack arguments. Instead the symbol of a C-variadic function is an asm shim which does this
+//! and initializes the `VaListImpl`. It then calls the actual function body with a pointer to this
+//! `VaListImpl` as extra argument after the fixed arguments.
+//!
+//! FIXME support targets other than x86_64 SysV, fixed arguments passed on the stack and JIT mode,
+//! which doesn't support asm referencing other symbols.
+
+use std::fmt::Write;
+
+use cranelift_codegen::ir::ArgumentPurpose;
+use cranelift_module::FuncOrDataId;
+
+use crate::prelude::*;
+
+/// Argument registers in the order they are assigned by the x86_64 SysV calling convention.
+const X86_64_INT_ARG_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+const X86_64_FLOAT_ARG_REG_COUNT: usize = 8;
+
+/// Size of the register save area. It holds all integer argument registers followed by all float
+/// argument registers.
+const X86_64_REG_SAVE_AREA_SIZE: usize = 6 * 8 + 8 * 16;
+
+/// Returns whether `instance` is a C-variadic function, whose symbol is defined by an asm shim.
+pub(crate) fn is_c_variadic(tcx: TyCtxt<'_>, instance: Instance<'_>) -> bool {
+    let ty = instance.ty(tcx, ParamEnv::reveal_all());
+    matches!(ty.kind(), ty::FnDef(..)) && ty.fn_sig(tcx).c_variadic()
+}
+
+/// The linkage with which the symbol of a C-variadic function is declared. Cranelift only
+/// references it, so it can't have local linkage. Instead every codegen unit with a local copy of
+/// the function emits a weak shim.
+pub(crate) fn c_variadic_linkage(linkage: Linkage) -> Linkage {
+    match linkage {
+        Linkage::Local => Linkage::Preemptible,
+        _ => linkage,
+    }
+}
+
+/// Emit the asm shim for the C-variadic function `symbol_name` and declare the function containing
+/// its body. `sig` is the signature of the fixed arguments.
+pub(crate) fn codegen_c_variadic_shim<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    cx: &mut crate::CodegenCx<'tcx>,
+    module: &mut dyn Module,
+    span: Span,
+    symbol_name: &str,
+    mut sig: Signature,
+) -> (FuncId, Signature) {
+    let target = &tcx.sess.target;
+    if target.arch != "x86_64" || target.is_like_windows || target.is_like_osx {
+        tcx.sess.span_fatal(
+            span,
+            &format!("Defining variadic functions is not yet supported on {}", target.llvm_target),
+        );
+    }
+
+    let mut int_regs = 0;
+    let mut float_regs = 0;
+    for param in &sig.params {
+        if let ArgumentPurpose::StructArgument(_) = param.purpose {
+            int_regs = usize::MAX;
+            break;
+        }
+        let ty = param.value_type;
+        if ty.is_float() || ty.is_vector() {
+            float_regs += 1;
+        } else {
+            int_regs += (ty.bits() as usize + 63) / 64;
+        }
+    }
+    // The body needs a free integer register for the `VaListImpl` pointer.
+    if int_regs >= X86_64_INT_ARG_REGS.len() || float_regs > X86_64_FLOAT_ARG_REG_COUNT {
+        tcx.sess.span_fatal(
+            span,
+            "Defining variadic functions with fixed arguments passed on the stack is not yet \
+            supported by Cranelift",
+        );
+    }
+
+    let linkage = match module.get_name(symbol_name) {
+        Some(FuncOrDataId::Func(func_id)) => {
+            module.declarations().get_function_decl(func_id).linkage
+        }
+        _ => Linkage::Export,
+    };
+    let body_name = format!("{}__va_body", symbol_name);
+
+    // Frame layout relative to rsp after the prologue:
+    // 0x00: register save area
+    // 0xb0: `VaListImpl`
+    // 0xc8: padding to keep the stack 16 byte aligned for the call
+    let va_list_offset = X86_64_REG_SAVE_AREA_SIZE;
+    let frame_size = va_list_offset + 32;
+
+    let mut generated_asm = String::new();
+    crate::inline_asm::begin_asm_function(&mut generated_asm, symbol_name, false);
+    let body_linkage = match linkage {
+        Linkage::Export => Linkage::Hidden,
+        Linkage::Hidden => {
+            writeln!(generated_asm, ".hidden {}", symbol_name).unwrap();
+            Linkage::Hidden
+        }
+        Linkage::Local | Linkage::Preemptible => {
+            writeln!(generated_asm, ".weak {}", symbol_name).unwrap();
+            Linkage::Preemptible
+        }
+        Linkage::Import => unreachable!(),
+    };
+    generated_asm.push_str(".cfi_startproc\n");
+    generated_asm.push_str(".intel_syntax noprefix\n");
+    generated_asm.push_str("    push rbp\n");
+    generated_asm.push_str(".cfi_def_cfa_offset 16\n");
+    generated_asm.push_str(".cfi_offset rbp, -16\n");
+    generated_asm.push_str("    mov rbp, rsp\n");
+    generated_asm.push_str(".cfi_def_cfa_register rbp\n");
+    writeln!(generated_asm, "    sub rsp, 0x{:x}", frame_size).unwrap();
+
+    // Spill all argument registers. The float registers are saved even when `al` is zero.
+    for (i, reg) in X86_64_INT_ARG_REGS.iter().enumerate() {
+        writeln!(generated_asm, "    mov [rsp+0x{:x}], {}", i * 8, reg).unwrap();
+    }
+    for i in 0..X86_64_FLOAT_ARG_REG_COUNT {
+        writeln!(generated_asm, "    movaps [rsp+0x{:x}], xmm{}", 6 * 8 + i * 16, i).unwrap();
+    }
+
+    // Initialize the `VaListImpl` to skip the registers used by the fixed arguments.
+    writeln!(generated_asm, "    mov dword ptr [rsp+0x{:x}], 0x{:x}", va_list_offset, int_regs * 8)
+        .unwrap();
+    writeln!(
+        generated_asm,
+        "    mov dword ptr [rsp+0x{:x}], 0x{:x}",
+        va_list_offset + 4,
+        6 * 8 + float_regs * 16
+    )
+    .unwrap();
+    generated_asm.push_str("    lea rax, [rbp+0x10]\n");
+    writeln!(generated_asm, "    mov [rsp+0x{:x}], rax", va_list_offset + 8).unwrap();
+    writeln!(generated_asm, "    mov [rsp+0x{:x}], rsp", va_list_offset + 16).unwrap();
+
+    // All fixed arguments are still in their registers.
+    writeln!(
+        generated_asm,
+        "    lea {}, [rsp+0x{:x}]",
+        X86_64_INT_ARG_REGS[int_regs], va_list_offset
+    )
+    .unwrap();
+    writeln!(generated_asm, "    call {}", body_name).unwrap();
+
+    generated_asm.push_str("    leave\n");
+    generated_asm.push_str(".cfi_def_cfa rsp, 8\n");
+    generated_asm.push_str("    ret\n");
+    generated_asm.push_str(".att_syntax\n");
+    generated_asm.push_str(".cfi_endproc\n");
+    crate::inline_asm::end_asm_function(&mut generated_asm, symbol_name, false);
+    cx.global_asm.push_str(&generated_asm);
+
+    sig.params.push(AbiParam::new(pointer_ty(tcx)));
+    let func_id = module.declare_function(&body_name, body_linkage, &sig).unwrap();
+    (func_id, sig)
+}
+
+/// Returns whether `va_arg` is supported for the `VaListImpl` of the current target.
+fn va_arg_supported(tcx: TyCtxt<'_>) -> bool {
+    tcx.sess.target.arch == "x86_64" && !tcx.sess.target.is_like_windows
+}
+
+/// Codegen `va_arg` for the `VaListImpl` pointed to by `va_list`.
+pub(crate) fn codegen_va_arg<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    span: Span,
+    va_list: Pointer,
+    layout: TyAndLayout<'tcx>,
+) -> CValue<'tcx> {
+    if !va_arg_supported(fx.tcx) {
+        fx.tcx.sess.span_fatal(
+            span,
+            &format!("va_arg is not yet supported on {}", fx.tcx.sess.target.llvm_target),
+        );
+    }
+
+    // All `VaArgSafe` types are scalars of at most 8 bytes, which take a single register or stack
+    // slot.
+    let clif_ty = fx.clif_type(layout.ty).unwrap();
+    let (offset_field, max_offset, reg_size) =
+        if clif_ty.is_float() { (4, X86_64_REG_SAVE_AREA_SIZE, 16) } else { (0, 6 * 8, 8) };
+
+    let in_regs_block = fx.bcx.create_block();
+    let on_stack_block = fx.bcx.create_block();
+    let done_block = fx.bcx.create_block();
+    let arg_addr = fx.bcx.append_block_param(done_block, fx.pointer_type);
+
+    let offset_ptr = va_list.offset_i64(fx, offset_field);
+    let offset = offset_ptr.load(fx, types::I32, MemFlags::trusted());
+    let in_regs = fx.bcx.ins().icmp_imm(IntCC::UnsignedLessThan, offset, max_offset as i64);
+    fx.bcx.ins().brnz(in_regs, in_regs_block, &[]);
+    fx.bcx.ins().jump(on_stack_block, &[]);
+
+    fx.bcx.switch_to_block(in_regs_block);
+    let reg_save_area = va_list.offset_i64(fx, 16).load(fx, fx.pointer_type, MemFlags::trusted());
+    let offset_ext = fx.bcx.ins().uextend(fx.pointer_type, offset);
+    let addr = fx.bcx.ins().iadd(reg_save_area, offset_ext);
+    let next_offset = fx.bcx.ins().iadd_imm(offset, reg_size);
+    offset_ptr.store(fx, next_offset, MemFlags::trusted());
+    fx.bcx.ins().jump(done_block, &[addr]);
+
+    fx.bcx.switch_to_block(on_stack_block);
+    let overflow_arg_area_ptr = va_list.offset_i64(fx, 8);
+    let addr = overflow_arg_area_ptr.load(fx, fx.pointer_type, MemFlags::trusted());
+    let next_addr = fx.bcx.ins().iadd_imm(addr, 8);
+    overflow_arg_area_ptr.store(fx, next_addr, MemFlags::trusted());
+    fx.bcx.ins().jump(done_block, &[addr]);
+
+    fx.bcx.switch_to_block(done_block);
+    let val = Pointer::new(arg_addr).load(fx, clif_ty, MemFlags::trusted());
+    CValue::by_val(val, layout)
+}
@@ -1,13 +1,17 @@
 //! Handling of everything related to the calling convention. Also fills `fx.local_map`.
 
+mod c_variadic;
 mod comments;
 mod pass_mode;
 mod returning;
 
+use std::borrow::Cow;
+
 use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::ty::layout::FnAbiExt;
 use rustc_target::abi::call::{Conv, FnAbi};
 use rustc_target::spec::abi::Abi;
+use rustc_target::spec::PanicStrategy;
 
 use cranelift_codegen::ir::AbiParam;
 use smallvec::smallvec;
@@ -15,6 +19,9 @@
 use self::pass_mode::*;
 use crate::prelude::*;
 
+pub(crate) use self::c_variadic::{
+    c_variadic_linkage, codegen_c_variadic_shim, codegen_va_arg, is_c_variadic,
+};
 pub(crate) use self::returning::{can_return_to_ssa_var, codegen_return};
 
 fn clif_sig_from_fn_abi<'tcx>(
@@ -26,15 +33,34 @@ fn clif_sig_from_fn_abi<'tcx>(
         Conv::Rust | Conv::C => CallConv::triple_default(triple),
         Conv::X86_64SysV => CallConv::SystemV,
         Conv::X86_64Win64 => CallConv::WindowsFastcall,
-        Conv::ArmAapcs
-        | Conv::CCmseNonSecureCall
+        // `extern "aapcs"` is only accepted on ARM targets, where AAPCS (or AAPCS64) already is the
+        // default calling convention. The composite type rules are handled by `FnAbi`.
+        Conv::ArmAapcs => CallConv::triple_default(triple),
+        // On non-x86 Windows targets these are already turned into `Conv::C` by rustc. On other
+        // x86_64 targets LLVM ignores stdcall, fastcall and thiscall.
+        Conv::X86Fastcall | Conv::X86Stdcall | Conv::X86ThisCall
+            if triple.architecture == target_lexicon::Architecture::X86_64 =>
+        {
+            CallConv::triple_default(triple)
+        }
+        // On x86_64 vectorcall assigns registers like the Windows x64 calling convention.
+        // FIXME pass homogeneous vector aggregates in the remaining vector registers.
+        Conv::X86VectorCall if triple.architecture == target_lexicon::Architecture::X86_64 => {
+            CallConv::WindowsFastcall
+        }
+        // On 32bit x86 these need callee-popped stack arguments and (for fastcall, thiscall and
+        // vectorcall) a different register assignment, neither of which Cranelift supports.
+        // FIXME implement them once Cranelift gains 32bit x86 calling conventions.
+        Conv::X86Fastcall | Conv::X86Stdcall | Conv::X86ThisCall | Conv::X86VectorCall => {
+            tcx.sess.fatal(&format!(
+                "Calling convention {:?} is not yet supported by Cranelift",
+                fn_abi.conv
+            ));
+        }
+        Conv::CCmseNonSecureCall
         | Conv::Msp430Intr
         | Conv::PtxKernel
-        | Conv::X86Fastcall
         | Conv::X86Intr
-        | Conv::X86Stdcall
-        | Conv::X86ThisCall
-        | Conv::X86VectorCall
         | Conv::AmdGpuKernel
         | Conv::AvrInterrupt
         | Conv::AvrNonBlockingInterrupt => todo!("{:?}", fn_abi.conv),
@@ -48,13 +74,78 @@ fn clif_sig_from_fn_abi<'tcx>(
     Signature { params, returns, call_conv }
 }
 
+/// Emit a note with the pass mode and Cranelift parameters of the arguments and return value of a
+/// function. Used for `-Cllvm-args=dump_abi=true`.
+pub(crate) fn dump_fn_abi<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    symbol_name: &str,
+    fn_abi: &FnAbi<'tcx, Ty<'tcx>>,
+) {
+    use std::fmt::Write;
+
+    fn clif_params(params: impl IntoIterator<Item = AbiParam>) -> String {
+        params.into_iter().map(|param| param.to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    let mut out = String::new();
+    writeln!(out, "abi {} conv={:?} c_variadic={}", symbol_name, fn_abi.conv, fn_abi.c_variadic)
+        .unwrap();
+    let (ret_ptr, returns) = fn_abi.ret.get_abi_return(tcx);
+    writeln!(
+        out,
+        "  ret    {:?} {:?} => [{}] ret_ptr=[{}]",
+        fn_abi.ret.layout.ty,
+        fn_abi.ret.mode,
+        clif_params(returns),
+        clif_params(ret_ptr),
+    )
+    .unwrap();
+    for (i, arg_abi) in fn_abi.args.iter().enumerate() {
+        writeln!(
+            out,
+            "  arg{:<3} {:?} {:?} => [{}]",
+            i,
+            arg_abi.layout.ty,
+            arg_abi.mode,
+            clif_params(arg_abi.get_abi_param(tcx)),
+        )
+        .unwrap();
+    }
+
+    // Emit a single note to prevent interleaving when codegening in parallel.
+    tcx.sess.note_without_error(out.trim_end());
+}
+
+/// Emit a proper error for arguments and return values which can't be lowered to Cranelift params,
+/// rather than ICE-ing while lowering them. This is the only place where this is checked. It has
+/// to be called for every `FnAbi` before it is lowered, which happens in [`get_function_sig`] for
+/// all declared functions and in `codegen_terminator_call` for the signature at the call site.
+fn check_fn_abi<'tcx>(tcx: TyCtxt<'tcx>, span: Span, fn_abi: &FnAbi<'tcx, Ty<'tcx>>) {
+    let args =
+        fn_abi.args.iter().enumerate().map(|(i, arg_abi)| (format!("argument {}", i), arg_abi));
+    for (what, arg_abi) in std::iter::once(("return value".to_string(), &fn_abi.ret)).chain(args) {
+        if let Some(reason) = unsupported_arg_abi_reason(arg_abi) {
+            tcx.sess.span_fatal(
+                span,
+                &format!(
+                    "the {} of type `{}` can't be passed using the {:?} calling convention by \
+                     Cranelift: {}",
+                    what, arg_abi.layout.ty, fn_abi.conv, reason,
+                ),
+            );
+        }
+    }
+}
+
 pub(crate) fn get_function_sig<'tcx>(
     tcx: TyCtxt<'tcx>,
     triple: &target_lexicon::Triple,
     inst: Instance<'tcx>,
 ) -> Signature {
     assert!(!inst.substs.needs_infer());
-    clif_sig_from_fn_abi(tcx, triple, &FnAbi::of_instance(&RevealAllLayoutCx(tcx), inst, &[]))
+    let fn_abi = FnAbi::of_instance(&RevealAllLayoutCx(tcx), inst, &[]);
+    check_fn_abi(tcx, tcx.def_span(inst.def_id()), &fn_abi);
+    clif_sig_from_fn_abi(tcx, triple, &fn_abi)
 }
 
 /// Instance must be monomorphized
@@ -87,6 +178,57 @@ pub(crate) fn lib_call(
         params: Vec<AbiParam>,
         returns: Vec<AbiParam>,
         args: &[Value],
+    ) -> Cow<'_, [Value]> {
+        if self.tcx.sess.target.is_like_windows {
+            // The Windows x64 ABI passes 128bit integers by reference. Compiler-builtins returns
+            // them in xmm0 like LLVM expects for its libcalls.
+            let (params, args): (Vec<_>, Vec<_>) = params
+                .into_iter()
+                .zip(args)
+                .map(|(param, &arg)| {
+                    if param.value_type == types::I128 {
+                        let arg_ptr = self.create_i128_stack_slot();
+                        arg_ptr.store(self, arg, MemFlags::trusted());
+                        (AbiParam::new(self.pointer_type), arg_ptr.get_addr(self))
+                    } else {
+                        (param, arg)
+                    }
+                })
+                .unzip();
+
+            if returns.len() == 1 && returns[0].value_type == types::I128 {
+                let ret = self.lib_call_unadjusted(
+                    name,
+                    params,
+                    vec![AbiParam::new(types::I64X2)],
+                    &args,
+                )[0];
+                // FIXME use bitcast instead of store to get from i64x2 to i128
+                let ret_ptr = self.create_i128_stack_slot();
+                ret_ptr.store(self, ret, MemFlags::trusted());
+                return Cow::Owned(vec![ret_ptr.load(self, types::I128, MemFlags::trusted())]);
+            }
+
+            return Cow::Borrowed(self.lib_call_unadjusted(name, params, returns, &args));
+        }
+
+        Cow::Borrowed(self.lib_call_unadjusted(name, params, returns, args))
+    }
+
+    fn create_i128_stack_slot(&mut self) -> Pointer {
+        Pointer::stack_slot(self.bcx.create_stack_slot(StackSlotData {
+            kind: StackSlotKind::ExplicitSlot,
+            size: 16,
+            offset: None,
+        }))
+    }
+
+    fn lib_call_unadjusted(
+        &mut self,
+        name: &str,
+        params: Vec<AbiParam>,
+        returns: Vec<AbiParam>,
+        args: &[Value],
     ) -> &[Value] {
         let sig = Signature { params, returns, call_conv: CallConv::triple_default(self.triple()) };
         let func_id = self.module.declare_function(name, Linkage::Import, &sig).unwrap();
@@ -172,9 +314,13 @@ pub(crate) fn codegen_fn_prelude<'tcx>(fx: &mut FunctionCx<'_, '_, 'tcx>, start_
     enum ArgKind<'tcx> {
         Normal(Option<CValue<'tcx>>),
         Spread(Vec<Option<CValue<'tcx>>>),
+        /// Pointer to the `VaListImpl` of a C-variadic function. See `codegen_c_variadic_shim`.
+        VaList(Value),
     }
 
+    // Already checked by `check_fn_abi` when declaring the function.
     let fn_abi = fx.fn_abi.take().unwrap();
+
     let mut arg_abis_iter = fn_abi.args.iter();
 
     let func_params = fx
@@ -204,6 +350,8 @@ enum ArgKind<'tcx> {
                 }
 
                 (local, ArgKind::Spread(params), arg_ty)
+            } else if fn_abi.c_variadic && local.as_usize() == fn_abi.args.len() + 1 {
+                (local, ArgKind::VaList(block_params_iter.next().unwrap()), arg_ty)
             } else {
                 let arg_abi = arg_abis_iter.next().unwrap();
                 let param =
@@ -232,24 +380,40 @@ enum ArgKind<'tcx> {
 
         let is_ssa = ssa_analyzed[local] == crate::analyze::SsaKind::Ssa;
 
+        if let ArgKind::VaList(va_list) = arg_kind {
+            // The `VaListImpl` lives in the frame of the asm shim, which outlives this function.
+            let place = CPlace::for_ptr(Pointer::new(va_list), layout);
+            self::comments::add_local_place_comments(fx, place, local);
+            assert_eq!(fx.local_map.push(place), local);
+            continue;
+        }
+
+        let borrowable_val = match arg_kind {
+            ArgKind::Normal(val) => val,
+            // A tuple with a single field has the same layout as this field, so the backing
+            // storage of the field can be used for the whole spread argument. This is the common
+            // case for closures taking a single argument.
+            ArgKind::Spread(ref params) if params.len() == 1 => params[0],
+            ArgKind::Spread(_) => None,
+            ArgKind::VaList(_) => unreachable!(),
+        };
+
         // While this is normally an optimization to prevent an unnecessary copy when an argument is
         // not mutated by the current function, this is necessary to support unsized arguments.
-        if let ArgKind::Normal(Some(val)) = arg_kind {
+        if let Some(val) = borrowable_val {
             if let Some((addr, meta)) = val.try_to_ptr() {
                 let local_decl = &fx.mir.local_decls[local];
                 //                       v this ! is important
-                let internally_mutable = !val
-                    .layout()
-                    .ty
-                    .is_freeze(fx.tcx.at(local_decl.source_info.span), ParamEnv::reveal_all());
+                let internally_mutable =
+                    !ty.is_freeze(fx.tcx.at(local_decl.source_info.span), ParamEnv::reveal_all());
                 if local_decl.mutability == mir::Mutability::Not && !internally_mutable {
                     // We wont mutate this argument, so it is fine to borrow the backing storage
                     // of this argument, to prevent a copy.
 
                     let place = if let Some(meta) = meta {
-                        CPlace::for_ptr_with_extra(addr, meta, val.layout())
+                        CPlace::for_ptr_with_extra(addr, meta, layout)
                     } else {
-                        CPlace::for_ptr(addr, val.layout())
+                        CPlace::for_ptr(addr, layout)
                     };
 
                     self::comments::add_local_place_comments(fx, place, local);
@@ -276,6 +440,7 @@ enum ArgKind<'tcx> {
                     }
                 }
             }
+            ArgKind::VaList(_) => unreachable!(),
         }
     }
 
@@ -292,12 +457,29 @@ enum ArgKind<'tcx> {
     fx.bcx.ins().jump(*fx.block_map.get(START_BLOCK).unwrap(), &[]);
 }
 
+struct CallArgument<'tcx> {
+    value: CValue<'tcx>,
+    is_owned: bool,
+}
+
+// FIXME avoid intermediate `CValue` before calling `adjust_arg_for_abi`
+fn codegen_call_argument_operand<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    operand: &Operand<'tcx>,
+) -> CallArgument<'tcx> {
+    CallArgument {
+        value: codegen_operand(fx, operand),
+        is_owned: matches!(operand, Operand::Move(_)),
+    }
+}
+
 pub(crate) fn codegen_terminator_call<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
-    span: Span,
+    source_info: mir::SourceInfo,
     func: &Operand<'tcx>,
     args: &[Operand<'tcx>],
     destination: Option<(Place<'tcx>, BasicBlock)>,
+    cleanup: Option<BasicBlock>,
 ) {
     let fn_ty = fx.monomorphize(func.ty(fx.mir, fx.tcx));
     let fn_sig =
@@ -325,7 +507,13 @@ pub(crate) fn codegen_terminator_call<'tcx>(
 
         match instance.def {
             InstanceDef::Intrinsic(_) => {
-                crate::intrinsics::codegen_intrinsic_call(fx, instance, args, destination, span);
+                crate::intrinsics::codegen_intrinsic_call(
+                    fx,
+                    instance,
+                    args,
+                    destination,
+                    source_info,
+                );
                 return;
             }
             InstanceDef::DropGlue(_, None) => {
@@ -351,6 +539,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
     } else {
         FnAbi::of_fn_ptr(&RevealAllLayoutCx(fx.tcx), fn_ty.fn_sig(fx.tcx), &extra_args)
     };
+    check_fn_abi(fx.tcx, source_info.span, &fn_abi);
 
     let is_cold = instance
         .map(|inst| fx.tcx.codegen_fn_attrs(inst.def_id()).flags.contains(CodegenFnAttrFlags::COLD))
@@ -359,13 +548,41 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         // FIXME Mark current_block block as cold once Cranelift supports it
     }
 
+    // RFC 2945 requires a foreign unwind out of an `extern "C-unwind"` call to abort when compiling
+    // with `-Cpanic=abort`. As Cranelift doesn't support landing pads yet, this is done using the
+    // personality function of the whole caller.
+    if matches!(
+        fn_sig.abi,
+        Abi::C { unwind: true }
+            | Abi::Stdcall { unwind: true }
+            | Abi::Thiscall { unwind: true }
+            | Abi::System { unwind: true }
+    ) && fx.tcx.sess.panic_strategy() == PanicStrategy::Abort
+    {
+        fx.abort_on_unwind = true;
+    }
+
+    // `fn_abi.can_unwind` is false for `extern "C"` and friends, but true for the Rust ABI.
+    if fn_abi.can_unwind {
+        // FIXME Cranelift doesn't support exception edges yet, so the cleanup block is never run.
+        // This is fine as only `-Cpanic=abort` is supported, in which case Rust functions never
+        // unwind.
+        if fx.clif_comments.enabled() {
+            let nop_inst = fx.bcx.ins().nop();
+            fx.add_comment(
+                nop_inst,
+                format!("call may unwind ({:?}); cleanup block {:?} ignored", fn_sig.abi, cleanup),
+            );
+        }
+    }
+
     // Unpack arguments tuple for closures
     let args = if fn_sig.abi == Abi::RustCall {
         assert_eq!(args.len(), 2, "rust-call abi requires two arguments");
-        let self_arg = codegen_operand(fx, &args[0]);
-        let pack_arg = codegen_operand(fx, &args[1]);
+        let self_arg = codegen_call_argument_operand(fx, &args[0]);
+        let pack_arg = codegen_call_argument_operand(fx, &args[1]);
 
-        let tupled_arguments = match pack_arg.layout().ty.kind() {
+        let tupled_arguments = match pack_arg.value.layout().ty.kind() {
             ty::Tuple(ref tupled_arguments) => tupled_arguments,
             _ => bug!("argument to function with \"rust-call\" ABI is not a tuple"),
         };
@@ -373,11 +590,14 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         let mut args = Vec::with_capacity(1 + tupled_arguments.len());
         args.push(self_arg);
         for i in 0..tupled_arguments.len() {
-            args.push(pack_arg.value_field(fx, mir::Field::new(i)));
+            args.push(CallArgument {
+                value: pack_arg.value.value_field(fx, mir::Field::new(i)),
+                is_owned: pack_arg.is_owned,
+            });
         }
         args
     } else {
-        args.iter().map(|arg| codegen_operand(fx, arg)).collect::<Vec<_>>()
+        args.iter().map(|arg| codegen_call_argument_operand(fx, arg)).collect::<Vec<_>>()
     };
 
     //   | indirect call target
@@ -393,7 +613,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
                     format!("virtual call; self arg pass mode: {:?}", &fn_abi.args[0],),
                 );
             }
-            let (ptr, method) = crate::vtable::get_ptr_and_method_ref(fx, args[0], idx);
+            let (ptr, method) = crate::vtable::get_ptr_and_method_ref(fx, args[0].value, idx);
             (Some(method), smallvec![ptr])
         }
 
@@ -401,7 +621,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         Some(_) => (
             None,
             args.get(0)
-                .map(|arg| adjust_arg_for_abi(fx, *arg, &fn_abi.args[0]))
+                .map(|arg| adjust_arg_for_abi(fx, arg.value, &fn_abi.args[0], arg.is_owned))
                 .unwrap_or(smallvec![]),
         ),
 
@@ -415,7 +635,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
             (
                 Some(func),
                 args.get(0)
-                    .map(|arg| adjust_arg_for_abi(fx, *arg, &fn_abi.args[0]))
+                    .map(|arg| adjust_arg_for_abi(fx, arg.value, &fn_abi.args[0], arg.is_owned))
                     .unwrap_or(smallvec![]),
             )
         }
@@ -428,6 +648,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         ret_place,
         |fx, return_ptr| {
             let regular_args_count = args.len();
+            let return_ptr_count = return_ptr.is_some() as usize;
             let mut call_args: Vec<Value> = return_ptr
                 .into_iter()
                 .chain(first_arg.into_iter())
@@ -435,23 +656,69 @@ pub(crate) fn codegen_terminator_call<'tcx>(
                     args.into_iter()
                         .enumerate()
                         .skip(1)
-                        .map(|(i, arg)| adjust_arg_for_abi(fx, arg, &fn_abi.args[i]).into_iter())
+                        .map(|(i, arg)| {
+                            adjust_arg_for_abi(fx, arg.value, &fn_abi.args[i], arg.is_owned)
+                                .into_iter()
+                        })
                         .flatten(),
                 )
                 .collect::<Vec<_>>();
 
             if instance.map(|inst| inst.def.requires_caller_location(fx.tcx)).unwrap_or(false) {
                 // Pass the caller location for `#[track_caller]`.
-                let caller_location = fx.get_caller_location(span);
+                let caller_location = fx.get_caller_location(source_info);
                 call_args.extend(
-                    adjust_arg_for_abi(fx, caller_location, &fn_abi.args[regular_args_count])
-                        .into_iter(),
+                    adjust_arg_for_abi(
+                        fx,
+                        caller_location,
+                        &fn_abi.args[regular_args_count],
+                        false,
+                    )
+                    .into_iter(),
                 );
                 assert_eq!(fn_abi.args.len(), regular_args_count + 1);
             } else {
                 assert_eq!(fn_abi.args.len(), regular_args_count);
             }
 
+            if fn_sig.c_variadic
+                && fx.tcx.sess.target.is_like_osx
+                && fx.tcx.sess.target.arch == "aarch64"
+            {
+                // On Apple AArch64 all variadic arguments are passed on the stack. Fill the
+                // remaining integer argument registers with dummy values to force them onto the
+                // stack. Variadic arguments are always integers here, see below.
+                let variadic_count = regular_args_count - fn_sig.inputs().len();
+                let fixed_count = call_args.len() - variadic_count;
+
+                // Every variadic argument occupies a full 8 byte stack slot.
+                for (arg, &ty) in call_args[fixed_count..].iter_mut().zip(extra_args.iter()) {
+                    let clif_ty = fx.bcx.func.dfg.value_type(*arg);
+                    if clif_ty.is_int() && clif_ty.bits() < 64 {
+                        *arg = if ty.is_signed() {
+                            fx.bcx.ins().sextend(types::I64, *arg)
+                        } else {
+                            fx.bcx.ins().uextend(types::I64, *arg)
+                        };
+                    }
+                }
+
+                // AAPCS64 passes the return pointer in x8 rather than in one of x0-x7.
+                let mut used_int_regs = 0;
+                for &arg in &call_args[return_ptr_count..fixed_count] {
+                    match fx.bcx.func.dfg.value_type(arg) {
+                        // AAPCS64 passes 128bit integers in an even numbered register pair.
+                        types::I128 => used_int_regs = (used_int_regs + 1) / 2 * 2 + 2,
+                        ty if ty.is_int() => used_int_regs += 1,
+                        _ => {}
+                    }
+                }
+                let padding = (used_int_regs.min(8)..8)
+                    .map(|_| fx.bcx.ins().iconst(types::I64, 0))
+                    .collect::<Vec<_>>();
+                call_args.splice(fixed_count..fixed_count, padding);
+            }
+
             let call_inst = if let Some(func_ref) = func_ref {
                 let sig = clif_sig_from_fn_abi(fx.tcx, fx.triple(), &fn_abi);
                 let sig = fx.bcx.import_signature(sig);
@@ -469,7 +736,10 @@ pub(crate) fn codegen_terminator_call<'tcx>(
     // FIXME find a cleaner way to support varargs
     if fn_sig.c_variadic {
         if !matches!(fn_sig.abi, Abi::C { .. }) {
-            fx.tcx.sess.span_fatal(span, &format!("Variadic call for non-C abi {:?}", fn_sig.abi));
+            fx.tcx.sess.span_fatal(
+                source_info.span,
+                &format!("Variadic call for non-C abi {:?}", fn_sig.abi),
+            );
         }
         let sig_ref = fx.bcx.func.dfg.call_signature(call_inst).unwrap();
         let abi_params = call_args
@@ -478,7 +748,10 @@ pub(crate) fn codegen_terminator_call<'tcx>(
                 let ty = fx.bcx.func.dfg.value_type(arg);
                 if !ty.is_int() {
                     // FIXME set %al to upperbound on float args once floats are supported
-                    fx.tcx.sess.span_fatal(span, &format!("Non int ty {:?} for variadic call", ty));
+                    fx.tcx.sess.span_fatal(
+                        source_info.span,
+                        &format!("Non int ty {:?} for variadic call", ty),
+                    );
                 }
                 AbiParam::new(ty)
             })
@@ -496,7 +769,7 @@ pub(crate) fn codegen_terminator_call<'tcx>(
 
 pub(crate) fn codegen_drop<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
-    span: Span,
+    source_info: mir::SourceInfo,
     drop_place: CPlace<'tcx>,
 ) {
     let ty = drop_place.layout().ty;
@@ -535,15 +808,15 @@ pub(crate) fn codegen_drop<'tcx>(
                         TypeAndMut { ty, mutbl: crate::rustc_hir::Mutability::Mut },
                     )),
                 );
-                let arg_value = adjust_arg_for_abi(fx, arg_value, &fn_abi.args[0]);
+                let arg_value = adjust_arg_for_abi(fx, arg_value, &fn_abi.args[0], true);
 
                 let mut call_args: Vec<Value> = arg_value.into_iter().collect::<Vec<_>>();
 
                 if drop_instance.def.requires_caller_location(fx.tcx) {
                     // Pass the caller location for `#[track_caller]`.
-                    let caller_location = fx.get_caller_location(span);
+                    let caller_location = fx.get_caller_location(source_info);
                     call_args.extend(
-                        adjust_arg_for_abi(fx, caller_location, &fn_abi.args[1]).into_iter(),
+                        adjust_arg_for_abi(fx, caller_location, &fn_abi.args[1], false).into_iter(),
                     );
                 }
 
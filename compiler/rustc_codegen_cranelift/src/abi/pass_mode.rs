@@ -18,9 +18,11 @@ fn reg_to_abi_param(reg: Reg) -> AbiParam {
     let clif_ty = match (reg.kind, reg.size.bytes()) {
         (RegKind::Integer, 1) => types::I8,
         (RegKind::Integer, 2) => types::I16,
-        (RegKind::Integer, 4) => types::I32,
-        (RegKind::Integer, 8) => types::I64,
-        (RegKind::Integer, 16) => types::I128,
+        // The last eightbyte of a SysV aggregate like `struct { u64, u8, u8, u8 }` can have an odd
+        // size. It is passed in a full register.
+        (RegKind::Integer, 3..=4) => types::I32,
+        (RegKind::Integer, 5..=8) => types::I64,
+        (RegKind::Integer, 9..=16) => types::I128,
         (RegKind::Float, 4) => types::F32,
         (RegKind::Float, 8) => types::F64,
         (RegKind::Vector, size) => types::I8.by(u16::try_from(size).unwrap()).unwrap(),
@@ -29,6 +31,50 @@ fn reg_to_abi_param(reg: Reg) -> AbiParam {
     AbiParam::new(clif_ty)
 }
 
+fn is_supported_reg(reg: Reg) -> bool {
+    match (reg.kind, reg.size.bytes()) {
+        (RegKind::Integer, 1..=16) => true,
+        (RegKind::Float, 4) | (RegKind::Float, 8) => true,
+        (RegKind::Vector, 1..=16) => true,
+        _ => false,
+    }
+}
+
+/// Returns the reason why `arg_abi` can't be lowered to Cranelift params if that is the case.
+pub(super) fn unsupported_arg_abi_reason(arg_abi: &ArgAbi<'_, Ty<'_>>) -> Option<String> {
+    match arg_abi.mode {
+        PassMode::Ignore | PassMode::Pair(_, _) | PassMode::Indirect { .. } => None,
+        PassMode::Direct(_) => match arg_abi.layout.abi {
+            Abi::Vector { .. } if arg_abi.layout.size.bits() > 128 => Some(format!(
+                "passing {}bit vectors in a register is not yet supported",
+                arg_abi.layout.size.bits()
+            )),
+            _ => None,
+        },
+        PassMode::Cast(cast) => {
+            let mut regs = cast
+                .prefix
+                .iter()
+                .flatten()
+                .map(|&kind| Reg { kind, size: cast.prefix_chunk_size })
+                .chain(std::iter::once(cast.rest.unit).filter(|unit| unit.size.bytes() != 0));
+            if let Some(reg) = regs.find(|&reg| !is_supported_reg(reg)) {
+                return Some(format!("passing it in a {:?} register is not supported", reg));
+            }
+            if cast.rest.unit.kind != RegKind::Integer
+                && cast.rest.total > cast.rest.unit.size
+                && cast.rest.total.bytes() % cast.rest.unit.size.bytes() != 0
+            {
+                return Some(format!(
+                    "only integer registers can be split, but it is passed in {:?} registers",
+                    cast.rest.unit.kind,
+                ));
+            }
+            None
+        }
+    }
+}
+
 fn apply_arg_attrs_to_abi_param(mut param: AbiParam, arg_attrs: ArgAttributes) -> AbiParam {
     match arg_attrs.arg_ext {
         RustcArgExtension::None => {}
@@ -38,6 +84,13 @@ fn apply_arg_attrs_to_abi_param(mut param: AbiParam, arg_attrs: ArgAttributes) -
     param
 }
 
+/// Vectors are passed directly in a vector register by foreign ABIs like SysV. (The Rust ABI always
+/// passes them indirectly.) This includes vectors smaller than 128bit like `f32x2`. Larger vectors
+/// have already been rejected by `check_fn_abi`.
+fn vector_to_abi_param(tcx: TyCtxt<'_>, element: Scalar, count: u64) -> AbiParam {
+    AbiParam::new(scalar_to_clif_type(tcx, element).by(u16::try_from(count).unwrap()).unwrap())
+}
+
 fn cast_target_to_abi_params(cast: CastTarget) -> SmallVec<[AbiParam; 2]> {
     let (rest_count, rem_bytes) = if cast.rest.unit.size.bytes() == 0 {
         (0, 0)
@@ -66,7 +119,9 @@ fn apply_arg_attrs_to_abi_param(mut param: AbiParam, arg_attrs: ArgAttributes) -
         }
     }
 
-    // Create list of fields in the main structure
+    // Create list of fields in the main structure. For homogeneous float aggregates on AArch64
+    // there is no prefix and `cast.rest.unit` is the float type, which results in one float
+    // param per member. Cranelift assigns those to consecutive float registers.
     let mut args = cast
         .prefix
         .iter()
@@ -97,9 +152,8 @@ impl<'tcx> ArgAbiExt<'tcx> for ArgAbi<'tcx, Ty<'tcx>> {
                     AbiParam::new(scalar_to_clif_type(tcx, scalar.clone())),
                     attrs
                 )],
-                Abi::Vector { .. } => {
-                    let vector_ty = crate::intrinsics::clif_vector_type(tcx, self.layout).unwrap();
-                    smallvec![AbiParam::new(vector_ty)]
+                Abi::Vector { element, count } => {
+                    smallvec![vector_to_abi_param(tcx, element.clone(), *count)]
                 }
                 _ => unreachable!("{:?}", self.layout.abi),
             },
@@ -143,9 +197,8 @@ fn get_abi_return(&self, tcx: TyCtxt<'tcx>) -> (Option<AbiParam>, Vec<AbiParam>)
                 Abi::Scalar(scalar) => {
                     (None, vec![AbiParam::new(scalar_to_clif_type(tcx, scalar.clone()))])
                 }
-                Abi::Vector { .. } => {
-                    let vector_ty = crate::intrinsics::clif_vector_type(tcx, self.layout).unwrap();
-                    (None, vec![AbiParam::new(vector_ty)])
+                Abi::Vector { element, count } => {
+                    (None, vec![vector_to_abi_param(tcx, element.clone(), *count)])
                 }
                 _ => unreachable!("{:?}", self.layout.abi),
             },
@@ -174,10 +227,30 @@ pub(super) fn to_casted_value<'tcx>(
     arg: CValue<'tcx>,
     cast: CastTarget,
 ) -> SmallVec<[Value; 2]> {
-    let (ptr, meta) = arg.force_stack(fx);
-    assert!(meta.is_none());
+    let abi_params = cast_target_to_abi_params(cast);
+    let abi_param_size: u32 = abi_params.iter().map(|param| param.value_type.bytes()).sum();
+    let layout_size = u32::try_from(arg.layout().size.bytes()).unwrap();
+    let ptr = if abi_param_size > layout_size {
+        // The registers cover more bytes than the value itself, for example for `[u8; 3]` which
+        // is packed into an `i32`. Copy it to a big enough stack slot to avoid reading past the
+        // end of the value.
+        let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
+            kind: StackSlotKind::ExplicitSlot,
+            // FIXME Don't force the size to a multiple of 16 bytes once Cranelift gets a way to
+            // specify stack slot alignment.
+            size: (abi_param_size + 15) / 16 * 16,
+            offset: None,
+        });
+        let ptr = Pointer::new(fx.bcx.ins().stack_addr(pointer_ty(fx.tcx), stack_slot, 0));
+        CPlace::for_ptr(ptr, arg.layout()).write_cvalue(fx, arg);
+        ptr
+    } else {
+        let (ptr, meta) = arg.force_stack(fx);
+        assert!(meta.is_none());
+        ptr
+    };
     let mut offset = 0;
-    cast_target_to_abi_params(cast)
+    abi_params
         .into_iter()
         .map(|param| {
             let val = ptr.offset_i64(fx, offset).load(fx, param.value_type, MemFlags::new());
@@ -223,10 +296,14 @@ pub(super) fn from_casted_value<'tcx>(
 }
 
 /// Get a set of values to be passed as function arguments.
+///
+/// `is_owned` tells whether the caller gives up the backing storage of `arg`. If it doesn't, an
+/// indirectly passed argument is copied first, as the callee is allowed to modify it.
 pub(super) fn adjust_arg_for_abi<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
     arg: CValue<'tcx>,
     arg_abi: &ArgAbi<'tcx, Ty<'tcx>>,
+    is_owned: bool,
 ) -> SmallVec<[Value; 2]> {
     assert_assignable(fx, arg.layout().ty, arg_abi.layout.ty);
     match arg_abi.mode {
@@ -237,10 +314,27 @@ pub(super) fn adjust_arg_for_abi<'tcx>(
             smallvec![a, b]
         }
         PassMode::Cast(cast) => to_casted_value(fx, arg, cast),
-        PassMode::Indirect { .. } => match arg.force_stack(fx) {
-            (ptr, None) => smallvec![ptr.get_addr(fx)],
-            (ptr, Some(meta)) => smallvec![ptr.get_addr(fx), meta],
-        },
+        PassMode::Indirect { attrs: _, extra_attrs: None, on_stack: _ } => {
+            if is_owned {
+                match arg.force_stack(fx) {
+                    (ptr, None) => smallvec![ptr.get_addr(fx)],
+                    _ => unreachable!(),
+                }
+            } else {
+                // For example the Windows x64 ABI passes all aggregates larger than 8 bytes by
+                // reference to a caller-owned copy, which the callee may freely write to. Make
+                // sure the argument local isn't changed behind our back unless it was moved.
+                let place = CPlace::new_stack_slot(fx, arg.layout());
+                place.write_cvalue(fx, arg);
+                smallvec![place.to_ptr().get_addr(fx)]
+            }
+        }
+        PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
+            match arg.force_stack(fx) {
+                (ptr, Some(meta)) => smallvec![ptr.get_addr(fx), meta],
+                _ => unreachable!(),
+            }
+        }
     }
 }
 
@@ -44,9 +44,13 @@ pub(crate) fn can_return_to_ssa_var<'tcx>(
         FnAbi::of_fn_ptr(&RevealAllLayoutCx(fx.tcx), fn_ty.fn_sig(fx.tcx), &extra_args)
     };
     match fn_abi.ret.mode {
-        PassMode::Ignore | PassMode::Direct(_) | PassMode::Pair(_, _) => true,
-        // FIXME Make it possible to return Cast and Indirect to an ssa var.
-        PassMode::Cast(_) | PassMode::Indirect { .. } => false,
+        // Cast return values are first stored to a stack slot by `from_casted_value` and then
+        // loaded again when writing them to the return place, so an ssa var is fine. For example
+        // `struct { u32, u32 }` has a scalar pair layout, but is returned as `i64` by the SysV
+        // ABI.
+        PassMode::Ignore | PassMode::Direct(_) | PassMode::Pair(_, _) | PassMode::Cast(_) => true,
+        // FIXME Make it possible to return Indirect to an ssa var.
+        PassMode::Indirect { .. } => false,
     }
 }
 
@@ -1,6 +1,6 @@
 //! Abstraction around the object writing crate
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 
 use rustc_data_structures::fx::FxHashMap;
 use rustc_session::Session;
@@ -10,11 +10,11 @@
 use cranelift_object::{ObjectBuilder, ObjectModule, ObjectProduct};
 
 use object::write::*;
-use object::{RelocationEncoding, SectionKind, SymbolFlags};
+use object::{RelocationEncoding, RelocationKind, SectionKind, SymbolFlags};
 
 use gimli::SectionId;
 
-use crate::debuginfo::{DebugReloc, DebugRelocName};
+use crate::debuginfo::{DebugReloc, DebugRelocName, DebugSymbol};
 
 pub(crate) trait WriteMetadata {
     fn add_rustc_section(&mut self, symbol_name: String, data: Vec<u8>);
@@ -50,6 +50,8 @@ fn add_debug_reloc(
         from: &Self::SectionId,
         reloc: &DebugReloc,
     );
+    /// Write the `.pdata` and `.xdata` sections used for SEH based unwinding on Windows.
+    fn add_seh_unwind_info(&mut self, functions: &[(FuncId, u32, Vec<u8>)]);
 }
 
 impl WriteDebugInfo for ObjectProduct {
@@ -60,14 +62,25 @@ fn add_debug_section(
         id: SectionId,
         data: Vec<u8>,
     ) -> (object::write::SectionId, object::write::SymbolId) {
-        let name = if self.object.format() == object::BinaryFormat::MachO {
-            id.name().replace('.', "__") // machO expects __debug_info instead of .debug_info
+        let is_macho = self.object.format() == object::BinaryFormat::MachO;
+        let name = if is_macho {
+            // machO expects __debug_info instead of .debug_info. Section names are limited to 16
+            // bytes, so for example .debug_str_offsets becomes __debug_str_offs.
+            let mut name = id.name().replace('.', "__");
+            name.truncate(16);
+            name
         } else {
             id.name().to_string()
         }
         .into_bytes();
 
-        let segment = self.object.segment_name(StandardSegment::Debug).to_vec();
+        // The unwind tables need to be loaded at runtime, so they can't be part of the __DWARF
+        // segment, which is stripped from the final executable.
+        let segment = if is_macho && id == SectionId::EhFrame {
+            self.object.segment_name(StandardSegment::Text).to_vec()
+        } else {
+            self.object.segment_name(StandardSegment::Debug).to_vec()
+        };
         // FIXME use SHT_X86_64_UNWIND for .eh_frame
         let section_id = self.object.add_section(
             segment,
@@ -89,8 +102,11 @@ fn add_debug_reloc(
     ) {
         let (symbol, symbol_offset) = match reloc.name {
             DebugRelocName::Section(id) => (section_map.get(&id).unwrap().1, 0),
-            DebugRelocName::Symbol(id) => {
-                let symbol_id = self.function_symbol(FuncId::from_u32(id.try_into().unwrap()));
+            DebugRelocName::Symbol(symbol) => {
+                let symbol_id = match symbol {
+                    DebugSymbol::Func(func_id) => self.function_symbol(func_id),
+                    DebugSymbol::Data(data_id) => self.data_symbol(data_id),
+                };
                 self.object
                     .symbol_section_and_offset(symbol_id)
                     .expect("Debug reloc for undef sym???")
@@ -110,6 +126,42 @@ fn add_debug_reloc(
             )
             .unwrap();
     }
+
+    fn add_seh_unwind_info(&mut self, functions: &[(FuncId, u32, Vec<u8>)]) {
+        let segment = self.object.segment_name(StandardSegment::Data).to_vec();
+        let xdata =
+            self.object.add_section(segment.clone(), b".xdata".to_vec(), SectionKind::ReadOnlyData);
+        let pdata = self.object.add_section(segment, b".pdata".to_vec(), SectionKind::ReadOnlyData);
+        let xdata_symbol = self.object.section_symbol(xdata);
+
+        for &(func_id, code_size, ref unwind_info) in functions {
+            let func_symbol = self.function_symbol(func_id);
+            let xdata_offset = self.object.append_section_data(xdata, unwind_info, 4);
+
+            // A `RUNTIME_FUNCTION` consists of the image relative begin and end address of the
+            // function and the image relative address of its `UNWIND_INFO`.
+            let pdata_offset = self.object.append_section_data(pdata, &[0; 12], 4);
+            for &(offset, symbol, addend) in &[
+                (0, func_symbol, 0),
+                (4, func_symbol, i64::from(code_size)),
+                (8, xdata_symbol, i64::try_from(xdata_offset).unwrap()),
+            ] {
+                self.object
+                    .add_relocation(
+                        pdata,
+                        Relocation {
+                            offset: pdata_offset + offset,
+                            symbol,
+                            kind: RelocationKind::ImageOffset,
+                            encoding: RelocationEncoding::Generic,
+                            size: 32,
+                            addend,
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+    }
 }
 
 pub(crate) fn with_object(sess: &Session, name: &str, f: impl FnOnce(&mut Object)) -> Vec<u8> {
@@ -2,6 +2,7 @@
 
 use cranelift_codegen::binemit::{NullStackMapSink, NullTrapSink};
 use rustc_index::vec::IndexVec;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::ty::adjustment::PointerCast;
 use rustc_middle::ty::layout::FnAbiExt;
 use rustc_target::abi::call::FnAbi;
@@ -22,10 +23,19 @@ pub(crate) fn codegen_fn<'tcx>(
 
     let mir = tcx.instance_mir(instance.def);
 
+    if tcx.codegen_fn_attrs(instance.def_id()).flags.contains(CodegenFnAttrFlags::NAKED) {
+        crate::inline_asm::codegen_naked_asm(tcx, cx, instance, mir);
+        return;
+    }
+
     // Declare function
     let symbol_name = tcx.symbol_name(instance);
     let sig = get_function_sig(tcx, module.isa().triple(), instance);
-    let func_id = module.declare_function(symbol_name.name, Linkage::Local, &sig).unwrap();
+    let (func_id, sig) = if crate::abi::is_c_variadic(tcx, instance) {
+        crate::abi::codegen_c_variadic_shim(tcx, cx, module, mir.span, symbol_name.name, sig)
+    } else {
+        (module.declare_function(symbol_name.name, Linkage::Local, &sig).unwrap(), sig)
+    };
 
     cx.cached_context.clear();
 
@@ -43,7 +53,13 @@ pub(crate) fn codegen_fn<'tcx>(
     let block_map: IndexVec<BasicBlock, Block> =
         (0..mir.basic_blocks().len()).map(|_| bcx.create_block()).collect();
 
+    let fn_abi = FnAbi::of_instance(&RevealAllLayoutCx(tcx), instance, &[]);
+    if cx.dump_abi {
+        crate::abi::dump_fn_abi(tcx, symbol_name.name, &fn_abi);
+    }
+
     // Make FunctionCx
+    let constants_cx = ConstantCx::new(cx.emulated_tls);
     let pointer_type = module.target_config().pointer_type();
     let clif_comments = crate::pretty_clif::CommentWriter::new(tcx, instance);
 
@@ -53,12 +69,12 @@ pub(crate) fn codegen_fn<'tcx>(
         tcx,
         pointer_type,
         vtables: FxHashMap::default(),
-        constants_cx: ConstantCx::new(),
+        constants_cx,
 
         instance,
         symbol_name,
         mir,
-        fn_abi: Some(FnAbi::of_instance(&RevealAllLayoutCx(tcx), instance, &[])),
+        fn_abi: Some(fn_abi),
 
         bcx,
         block_map,
@@ -70,6 +86,7 @@ pub(crate) fn codegen_fn<'tcx>(
         next_ssa_var: 0,
 
         inline_asm_index: 0,
+        abort_on_unwind: false,
     };
 
     let arg_uninhabited = fx
@@ -98,6 +115,7 @@ pub(crate) fn codegen_fn<'tcx>(
     let mut clif_comments = fx.clif_comments;
     let source_info_set = fx.source_info_set;
     let local_map = fx.local_map;
+    let abort_on_unwind = fx.abort_on_unwind;
 
     fx.constants_cx.finalize(fx.tcx, &mut *fx.module);
 
@@ -125,7 +143,7 @@ pub(crate) fn codegen_fn<'tcx>(
     // invalidate it when it would change.
     context.domtree.clear();
 
-    context.want_disasm = crate::pretty_clif::should_write_ir(tcx);
+    context.want_disasm = crate::pretty_clif::should_write_ir(tcx) || cx.asm_output.is_some();
 
     // Define function
     tcx.sess.time("define function", || {
@@ -144,7 +162,20 @@ pub(crate) fn codegen_fn<'tcx>(
         &clif_comments,
     );
 
+    if let Some(perf_map_entries) = &mut cx.perf_map_entries {
+        perf_map_entries.push(crate::debuginfo::PerfMapEntry::new(
+            tcx,
+            func_id,
+            context,
+            mir.span,
+            &source_info_set,
+        ));
+    }
+
     if let Some(disasm) = &context.mach_compile_result.as_ref().unwrap().disasm {
+        if let Some(asm_output) = &mut cx.asm_output {
+            asm_output.push_str(&format!("// {}\n{}:\n{}\n", instance, symbol_name.name, disasm));
+        }
         crate::pretty_clif::write_ir_file(
             tcx,
             || format!("{}.vcode", tcx.symbol_name(instance).name),
@@ -153,7 +184,6 @@ pub(crate) fn codegen_fn<'tcx>(
     }
 
     // Define debuginfo for function
-    let isa = module.isa();
     let debug_context = &mut cx.debug_context;
     let unwind_context = &mut cx.unwind_context;
     tcx.sess.time("generate debug info", || {
@@ -162,13 +192,17 @@ pub(crate) fn codegen_fn<'tcx>(
                 instance,
                 func_id,
                 symbol_name.name,
-                isa,
+                module.isa(),
                 context,
                 &source_info_set,
                 local_map,
             );
         }
-        unwind_context.add_function(func_id, &context, isa);
+        if abort_on_unwind {
+            unwind_context.add_function_abort_on_unwind(module, func_id, &context);
+        } else {
+            unwind_context.add_function(func_id, &context, module.isa());
+        }
     });
 
     // Clear context to make it usable for the next function
@@ -280,7 +314,7 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, '_>) {
                         let len = codegen_operand(fx, len).load_scalar(fx);
                         let index = codegen_operand(fx, index).load_scalar(fx);
                         let location = fx
-                            .get_caller_location(bb_data.terminator().source_info.span)
+                            .get_caller_location(bb_data.terminator().source_info)
                             .load_scalar(fx);
 
                         codegen_panic_inner(
@@ -292,7 +326,7 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, '_>) {
                     }
                     _ => {
                         let msg_str = msg.description();
-                        codegen_panic(fx, msg_str, bb_data.terminator().source_info.span);
+                        codegen_panic(fx, msg_str, bb_data.terminator().source_info);
                     }
                 }
             }
@@ -352,11 +386,18 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, '_>) {
                 args,
                 destination,
                 fn_span,
-                cleanup: _,
+                cleanup,
                 from_hir_call: _,
             } => {
                 fx.tcx.sess.time("codegen call", || {
-                    crate::abi::codegen_terminator_call(fx, *fn_span, func, args, *destination)
+                    crate::abi::codegen_terminator_call(
+                        fx,
+                        mir::SourceInfo { span: *fn_span, ..bb_data.terminator().source_info },
+                        func,
+                        args,
+                        *destination,
+                        *cleanup,
+                    )
                 });
             }
             TerminatorKind::InlineAsm {
@@ -402,7 +443,7 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, '_>) {
             }
             TerminatorKind::Drop { place, target, unwind: _ } => {
                 let drop_place = codegen_place(fx, *place);
-                crate::abi::codegen_drop(fx, bb_data.terminator().source_info.span, drop_place);
+                crate::abi::codegen_drop(fx, bb_data.terminator().source_info, drop_place);
 
                 let target_block = fx.get_block(*target);
                 fx.bcx.ins().jump(target_block, &[]);
@@ -644,6 +685,7 @@ fn is_fat_ptr<'tcx>(fx: &FunctionCx<'_, '_, 'tcx>, ty: Ty<'tcx>) -> bool {
                     lval.write_cvalue(fx, discr);
                 }
                 Rvalue::Repeat(ref operand, times) => {
+                    let const_operand = crate::constant::mir_operand_get_const_val(fx, operand);
                     let operand = codegen_operand(fx, operand);
                     let times = fx
                         .monomorphize(times)
@@ -654,11 +696,24 @@ fn is_fat_ptr<'tcx>(fx: &FunctionCx<'_, '_, 'tcx>, ty: Ty<'tcx>) -> bool {
                     if operand.layout().size.bytes() == 0 {
                         // Do nothing for ZST's
                     } else if fx.clif_type(operand.layout().ty) == Some(types::I8) {
-                        let times = fx.bcx.ins().iconst(fx.pointer_type, times as i64);
-                        // FIXME use emit_small_memset where possible
                         let addr = lval.to_ptr().get_addr(fx);
-                        let val = operand.load_scalar(fx);
-                        fx.bcx.call_memset(fx.module.target_config(), addr, val, times);
+                        if let Some(const_val) =
+                            const_operand.and_then(|val| val.try_to_bits(Size::from_bytes(1)))
+                        {
+                            let align =
+                                u8::try_from(lval.layout().align.abi.bytes().min(128)).unwrap();
+                            fx.bcx.emit_small_memset(
+                                fx.module.target_config(),
+                                addr,
+                                const_val as u8,
+                                u64::try_from(times).unwrap(),
+                                align,
+                            );
+                        } else {
+                            let times = fx.bcx.ins().iconst(fx.pointer_type, times as i64);
+                            let val = operand.load_scalar(fx);
+                            fx.bcx.call_memset(fx.module.target_config(), addr, val, times);
+                        }
                     } else {
                         let loop_block = fx.bcx.create_block();
                         let loop_block2 = fx.bcx.create_block();
@@ -746,7 +801,16 @@ fn is_fat_ptr<'tcx>(fx: &FunctionCx<'_, '_, 'tcx>, ty: Ty<'tcx>) -> bool {
         StatementKind::LlvmInlineAsm(asm) => {
             match asm.asm.asm.as_str().trim() {
                 "" => {
-                    // Black box
+                    // Black box, as used by `core::hint::black_box`. Spill all inputs to the
+                    // stack. This makes the values escape, so every input has to be fully
+                    // computed and everything reachable through a pointer input has to be
+                    // written to memory. Cranelift never removes or reorders memory accesses, so
+                    // this is enough to prevent it from optimizing across the black box.
+                    for (_span, input) in asm.inputs.iter() {
+                        let input = codegen_operand(fx, input);
+                        let spill_slot = CPlace::new_stack_slot(fx, input.layout());
+                        spill_slot.write_cvalue(fx, input);
+                    }
                 }
                 _ => fx.tcx.sess.span_fatal(
                     stmt.source_info.span,
@@ -764,15 +828,85 @@ fn is_fat_ptr<'tcx>(fx: &FunctionCx<'_, '_, 'tcx>, ty: Ty<'tcx>) -> bool {
                 .expect("Expected pointer");
             let dst = dst.load_scalar(fx);
             let src = codegen_operand(fx, &inner.src).load_scalar(fx);
-            let count = codegen_operand(fx, &inner.count).load_scalar(fx);
-            let elem_size: u64 = pointee.size.bytes();
-            let bytes =
-                if elem_size != 1 { fx.bcx.ins().imul_imm(count, elem_size as i64) } else { count };
-            fx.bcx.call_memcpy(fx.module.target_config(), dst, src, bytes);
+            codegen_copy_nonoverlapping(
+                fx,
+                dst,
+                src,
+                &inner.count,
+                pointee.size.bytes(),
+                pointee.align.bytes(),
+            );
         }
     }
 }
 
+/// Copy `count * elem_size` bytes from `src` to `dst`. When `count` is a constant the copy is
+/// expanded to a sequence of loads and stores if it is small enough. Otherwise `memcpy` is called.
+pub(crate) fn codegen_copy_nonoverlapping<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    dst: Value,
+    src: Value,
+    count: &Operand<'tcx>,
+    elem_size: u64,
+    elem_align: u64,
+) {
+    let const_count = crate::constant::mir_operand_get_const_val(fx, count)
+        .and_then(|count| count.try_to_machine_usize(fx.tcx));
+
+    if let Some(const_count) = const_count {
+        // Both pointers must be aligned for the element type, so we can use its alignment.
+        let align = u8::try_from(elem_align.min(128)).unwrap();
+        fx.bcx.emit_small_memory_copy(
+            fx.module.target_config(),
+            dst,
+            src,
+            const_count * elem_size,
+            align,
+            align,
+            true,
+        );
+    } else {
+        let count = codegen_operand(fx, count).load_scalar(fx);
+        let bytes =
+            if elem_size != 1 { fx.bcx.ins().imul_imm(count, elem_size as i64) } else { count };
+        fx.bcx.call_memcpy(fx.module.target_config(), dst, src, bytes);
+    }
+}
+
+/// Set `count * elem_size` bytes at `dst` to `val`. When both `val` and `count` are constants the
+/// memset is expanded to a sequence of stores as wide as the alignment allows if it is small
+/// enough. Otherwise `memset` is called.
+pub(crate) fn codegen_write_bytes<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    dst: Value,
+    val: &Operand<'tcx>,
+    count: &Operand<'tcx>,
+    elem_size: u64,
+    elem_align: u64,
+) {
+    let const_val = crate::constant::mir_operand_get_const_val(fx, val)
+        .and_then(|val| val.try_to_bits(Size::from_bytes(1)));
+    let const_count = crate::constant::mir_operand_get_const_val(fx, count)
+        .and_then(|count| count.try_to_machine_usize(fx.tcx));
+
+    if let (Some(const_val), Some(const_count)) = (const_val, const_count) {
+        let align = u8::try_from(elem_align.min(128)).unwrap();
+        fx.bcx.emit_small_memset(
+            fx.module.target_config(),
+            dst,
+            const_val as u8,
+            const_count * elem_size,
+            align,
+        );
+    } else {
+        let val = codegen_operand(fx, val).load_scalar(fx);
+        let count = codegen_operand(fx, count).load_scalar(fx);
+        let bytes =
+            if elem_size != 1 { fx.bcx.ins().imul_imm(count, elem_size as i64) } else { count };
+        fx.bcx.call_memset(fx.module.target_config(), dst, val, bytes);
+    }
+}
+
 fn codegen_array_len<'tcx>(fx: &mut FunctionCx<'_, '_, 'tcx>, place: CPlace<'tcx>) -> Value {
     match *place.layout().ty.kind() {
         ty::Array(_elem_ty, len) => {
@@ -867,14 +1001,18 @@ pub(crate) fn codegen_operand<'tcx>(
     }
 }
 
-pub(crate) fn codegen_panic<'tcx>(fx: &mut FunctionCx<'_, '_, 'tcx>, msg_str: &str, span: Span) {
-    let location = fx.get_caller_location(span).load_scalar(fx);
+pub(crate) fn codegen_panic<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    msg_str: &str,
+    source_info: mir::SourceInfo,
+) {
+    let location = fx.get_caller_location(source_info).load_scalar(fx);
 
     let msg_ptr = fx.anonymous_str("assert", msg_str);
     let msg_len = fx.bcx.ins().iconst(fx.pointer_type, i64::try_from(msg_str.len()).unwrap());
     let args = [msg_ptr, msg_len, location];
 
-    codegen_panic_inner(fx, rustc_hir::LangItem::Panic, &args, span);
+    codegen_panic_inner(fx, rustc_hir::LangItem::Panic, &args, source_info.span);
 }
 
 pub(crate) fn codegen_panic_inner<'tcx>(
@@ -46,6 +46,7 @@ fn config(&mut self, config: &mut interface::Config) {
 
         config.opts.cg.panic = Some(PanicStrategy::Abort);
         config.opts.debugging_opts.panic_abort_tests = true;
+        rustc_codegen_cranelift::configure_session_options(&mut config.opts);
         config.opts.maybe_sysroot =
             Some(std::env::current_exe().unwrap().parent().unwrap().parent().unwrap().to_owned());
     }
@@ -255,6 +255,10 @@ pub(crate) struct FunctionCx<'m, 'clif, 'tcx: 'm> {
     pub(crate) next_ssa_var: u32,
 
     pub(crate) inline_asm_index: u32,
+
+    /// Set when an unwind out of a call in this function has to abort. See
+    /// [`UnwindContext::add_function_abort_on_unwind`].
+    pub(crate) abort_on_unwind: bool,
 }
 
 impl<'tcx> LayoutOf for FunctionCx<'_, '_, 'tcx> {
@@ -325,12 +329,40 @@ pub(crate) fn set_debug_loc(&mut self, source_info: mir::SourceInfo) {
         self.bcx.set_srcloc(SourceLoc::new(index as u32));
     }
 
-    pub(crate) fn get_caller_location(&mut self, span: Span) -> CValue<'tcx> {
+    pub(crate) fn get_caller_location(&mut self, mut source_info: SourceInfo) -> CValue<'tcx> {
+        // Walk up the `SourceScope`s, in case some of them are from MIR inlining.
+        // If so, the starting `source_info.span` is in the innermost inlined
+        // function, and will be replaced with outer callsite spans as long
+        // as the inlined functions were `#[track_caller]`.
+        loop {
+            let scope_data = &self.mir.source_scopes[source_info.scope];
+
+            if let Some((callee, callsite_span)) = scope_data.inlined {
+                // Stop inside the most nested non-`#[track_caller]` function,
+                // before ever reaching its caller (which is irrelevant).
+                if !callee.def.requires_caller_location(self.tcx) {
+                    return self.span_to_caller_location(source_info.span);
+                }
+                source_info.span = callsite_span;
+            }
+
+            // Skip past all of the parents with `inlined: None`.
+            match scope_data.inlined_parent_scope {
+                Some(parent) => source_info.scope = parent,
+                None => break,
+            }
+        }
+
         if let Some(loc) = self.caller_location {
             // `#[track_caller]` is used; return caller location instead of current location.
             return loc;
         }
 
+        // No inlined `SourceScope`s, or all of them were `#[track_caller]`.
+        self.span_to_caller_location(source_info.span)
+    }
+
+    fn span_to_caller_location(&mut self, span: Span) -> CValue<'tcx> {
         let topmost = span.ctxt().outer_expn().expansion_cause().unwrap_or(span);
         let caller = self.tcx.sess.source_map().lookup_char_pos(topmost.lo());
         let const_loc = self.tcx.const_caller_location((
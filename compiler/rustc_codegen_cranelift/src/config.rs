@@ -1,5 +1,7 @@
 use std::env;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 fn bool_env_var(key: &str) -> bool {
     env::var(key).as_ref().map(|val| &**val) == Ok("1")
@@ -39,9 +41,57 @@ pub struct BackendConfig {
 
     /// When JIT mode is enable pass these arguments to the program.
     ///
-    /// Defaults to the value of `CG_CLIF_JIT_ARGS`.
+    /// Defaults to the value of `CG_CLIF_JIT_ARGS` split at spaces. More arguments can be added
+    /// using `-Cllvm-args=jit_arg=...`, which allows passing arguments containing spaces.
     pub jit_args: Vec<String>,
 
+    /// When JIT mode is enabled set these environment variables before running the program.
+    ///
+    /// Can be set using `-Cllvm-args=jit_env=NAME=VALUE`.
+    pub jit_env: Vec<(String, String)>,
+
+    /// When JIT mode is enabled run the program in this working directory.
+    ///
+    /// Defaults to the working directory of rustc. Can be set using `-Cllvm-args=jit_cwd=...`.
+    pub jit_cwd: Option<PathBuf>,
+
+    /// When JIT mode is enabled write the address, size and name of all jitted functions to
+    /// `/tmp/perf-<pid>.map` and their machine code and line tables to `jit-<pid>.dump` in the
+    /// temporary directory. This allows `perf` to attribute samples to jitted functions. The
+    /// jitdump file is only written on Linux and has to be merged into the profile recorded using
+    /// `perf record -k 1` with `perf inject --jit`.
+    ///
+    /// Defaults to true when the `CG_CLIF_JIT_PERF_MAP` env var is set to 1 or false otherwise.
+    /// Can be set using `-Cllvm-args=jit_perf_map=...`.
+    pub jit_perf_map: bool,
+
+    /// When JIT mode is enabled exit with an error once the program has been running for longer
+    /// than this. Lazily compiling functions counts towards the time limit.
+    ///
+    /// Defaults to no time limit. Can be set in seconds using `-Cllvm-args=jit_timeout=...`.
+    pub jit_timeout: Option<Duration>,
+
+    /// When JIT mode is enabled run the program on a thread with a stack of this many bytes.
+    ///
+    /// Defaults to running the program on the rustc thread in `jit` mode and to a stack of 8 MiB in
+    /// `jit-lazy` mode. Can be set using `-Cllvm-args=jit_stack_size=...`.
+    pub jit_stack_size: Option<usize>,
+
+    /// When JIT mode is enabled refuse to run the program if the jitted code references any of
+    /// these symbols. Only symbols referenced directly by the jitted code are checked, not those
+    /// used internally by already compiled dylibs like libstd. The program runs inside the rustc
+    /// process, so this is not a security boundary on its own.
+    ///
+    /// Can be set using `-Cllvm-args=jit_deny_symbol=...`.
+    pub jit_denied_symbols: Vec<String>,
+
+    /// When lazy JIT mode is enabled compile the functions called by already compiled functions
+    /// while the program doesn't wait for any function to be compiled. This hides part of the
+    /// compilation latency at the cost of compiling functions which may never be called.
+    ///
+    /// Defaults to false. Can be set using `-Cllvm-args=jit_speculative_compilation=...`.
+    pub jit_speculative_compilation: bool,
+
     /// Display the time it took to perform codegen for a crate.
     ///
     /// Defaults to true when the `CG_CLIF_DISPLAY_CG_TIME` env var is set to 1 or false otherwise.
@@ -63,6 +113,53 @@ pub struct BackendConfig {
     /// Defaults to true when the `CG_CLIF_DISABLE_INCR_CACHE` env var is set to 1 or false
     /// otherwise. Can be set using `-Cllvm-args=disable_incr_cache=...`.
     pub disable_incr_cache: bool,
+
+    /// Emit a note describing how the arguments and return value of every codegened function are
+    /// passed. It contains the pass mode computed by rustc together with the resulting Cranelift
+    /// parameters in a stable textual format, which makes it possible to diff them between
+    /// compilations. `scripts/compare_abi.rs` uses this to compare them against the LLVM backend.
+    /// This is not a `-Z` flag, as codegen backends can't add those.
+    ///
+    /// Defaults to true when the `CG_CLIF_DUMP_ABI` env var is set to 1 or false otherwise. Can be
+    /// set using `-Cllvm-args=dump_abi=...`.
+    pub dump_abi: bool,
+
+    /// The DWARF version to use for debuginfo. DWARF 5 puts the file names of the line table in
+    /// `.debug_line_str` and can record the md5 hash of source files. Other strings are still
+    /// referenced using `DW_FORM_strp` rather than `DW_FORM_strx`, as gimli doesn't yet support
+    /// writing `.debug_str_offsets`.
+    ///
+    /// Defaults to version 3 on macOS and version 4 otherwise. Can be set using
+    /// `-Cllvm-args=dwarf_version=...`.
+    pub dwarf_version: Option<u16>,
+
+    /// Emulate thread locals using `__emutls_get_address` even on targets with native TLS
+    /// support. This is necessary for some embedded and Android configurations whose C runtime
+    /// doesn't support native TLS.
+    ///
+    /// Defaults to false. Targets without native TLS support always use emulated TLS. JIT mode
+    /// uses it for the thread locals of the jitted crate. Can be set using
+    /// `-Cllvm-args=emulated_tls=...`.
+    pub emulated_tls: bool,
+
+    /// Inline small functions using the MIR inliner when optimizing. As Cranelift doesn't inline,
+    /// calls to `#[inline]` functions of other CGUs and crates are otherwise never inlined. This
+    /// only takes effect when the driver calls [`configure_session_options`].
+    ///
+    /// [`configure_session_options`]: crate::configure_session_options
+    ///
+    /// Defaults to true when the `CG_CLIF_CROSS_CGU_INLINE` env var is set to 1 or false
+    /// otherwise. Can be set using `-Cllvm-args=cross_cgu_inline=...`.
+    pub cross_cgu_inline: bool,
+
+    /// The maximum MIR size of a function inlined by [`cross_cgu_inline`]. Functions marked
+    /// `#[inline]` may be up to twice as large.
+    ///
+    /// [`cross_cgu_inline`]: Self::cross_cgu_inline
+    ///
+    /// Defaults to the threshold of the MIR inliner. Can be set using
+    /// `-Cllvm-args=cross_cgu_inline_threshold=...`.
+    pub cross_cgu_inline_threshold: Option<usize>,
 }
 
 impl Default for BackendConfig {
@@ -71,11 +168,23 @@ fn default() -> Self {
             codegen_mode: CodegenMode::Aot,
             jit_args: {
                 let args = std::env::var("CG_CLIF_JIT_ARGS").unwrap_or_else(|_| String::new());
-                args.split(' ').map(|arg| arg.to_string()).collect()
+                args.split(' ').filter(|arg| !arg.is_empty()).map(|arg| arg.to_string()).collect()
             },
+            jit_env: vec![],
+            jit_cwd: None,
+            jit_perf_map: bool_env_var("CG_CLIF_JIT_PERF_MAP"),
+            jit_timeout: None,
+            jit_stack_size: None,
+            jit_denied_symbols: vec![],
+            jit_speculative_compilation: false,
             display_cg_time: bool_env_var("CG_CLIF_DISPLAY_CG_TIME"),
             enable_verifier: cfg!(debug_assertions) || bool_env_var("CG_CLIF_ENABLE_VERIFIER"),
             disable_incr_cache: bool_env_var("CG_CLIF_DISABLE_INCR_CACHE"),
+            dump_abi: bool_env_var("CG_CLIF_DUMP_ABI"),
+            dwarf_version: None,
+            emulated_tls: false,
+            cross_cgu_inline: bool_env_var("CG_CLIF_CROSS_CGU_INLINE"),
+            cross_cgu_inline_threshold: None,
         }
     }
 }
@@ -87,14 +196,44 @@ fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
             value.parse().map_err(|_| format!("failed to parse value `{}` for {}", value, name))
         }
 
+        fn parse_usize(name: &str, value: &str) -> Result<usize, String> {
+            value.parse().map_err(|_| format!("failed to parse value `{}` for {}", value, name))
+        }
+
         let mut config = BackendConfig::default();
         for opt in opts {
             if let Some((name, value)) = opt.split_once('=') {
                 match name {
                     "mode" => config.codegen_mode = value.parse()?,
+                    "jit_arg" => config.jit_args.push(value.to_string()),
+                    "jit_env" => match value.split_once('=') {
+                        Some((var, val)) => config.jit_env.push((var.to_string(), val.to_string())),
+                        None => return Err(format!("Invalid environment variable `{}`", value)),
+                    },
+                    "jit_cwd" => config.jit_cwd = Some(PathBuf::from(value)),
+                    "jit_perf_map" => config.jit_perf_map = parse_bool(name, value)?,
+                    "jit_timeout" => {
+                        let secs = parse_usize(name, value)?;
+                        config.jit_timeout = Some(Duration::from_secs(secs as u64));
+                    }
+                    "jit_stack_size" => config.jit_stack_size = Some(parse_usize(name, value)?),
+                    "jit_deny_symbol" => config.jit_denied_symbols.push(value.to_string()),
+                    "jit_speculative_compilation" => {
+                        config.jit_speculative_compilation = parse_bool(name, value)?
+                    }
                     "display_cg_time" => config.display_cg_time = parse_bool(name, value)?,
                     "enable_verifier" => config.enable_verifier = parse_bool(name, value)?,
                     "disable_incr_cache" => config.disable_incr_cache = parse_bool(name, value)?,
+                    "dump_abi" => config.dump_abi = parse_bool(name, value)?,
+                    "dwarf_version" => match value.parse() {
+                        Ok(version @ 3..=5) => config.dwarf_version = Some(version),
+                        _ => return Err(format!("Unsupported DWARF version `{}`", value)),
+                    },
+                    "emulated_tls" => config.emulated_tls = parse_bool(name, value)?,
+                    "cross_cgu_inline" => config.cross_cgu_inline = parse_bool(name, value)?,
+                    "cross_cgu_inline_threshold" => {
+                        config.cross_cgu_inline_threshold = Some(parse_usize(name, value)?)
+                    }
                     _ => return Err(format!("Unknown option `{}`", name)),
                 }
             } else {
@@ -6,7 +6,8 @@
 use rustc_errors::ErrorReported;
 use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::mir::interpret::{
-    read_target_uint, AllocId, Allocation, ConstValue, ErrorHandled, GlobalAlloc, Pointer, Scalar,
+    read_target_uint, write_target_uint, AllocId, Allocation, ConstValue, ErrorHandled,
+    GlobalAlloc, Pointer, Scalar,
 };
 use rustc_middle::ty::ConstKind;
 
@@ -19,6 +20,29 @@ pub(crate) struct ConstantCx {
     todo: Vec<TodoItem>,
     done: FxHashSet<DataId>,
     anon_allocs: FxHashMap<AllocId, DataId>,
+    emulated_tls: EmulatedTls,
+}
+
+/// Which thread locals are emulated using `__emutls_get_address` instead of using native TLS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum EmulatedTls {
+    /// All thread locals use native TLS.
+    Native,
+    /// All thread locals are emulated, including those defined in other crates.
+    All,
+    /// Only thread locals defined in the local crate are emulated. This is used by the JIT, as the
+    /// dependencies are loaded from dylibs using native TLS, which jitted code can't access.
+    Local,
+}
+
+impl EmulatedTls {
+    fn is_emulated(self, def_id: DefId) -> bool {
+        match self {
+            EmulatedTls::Native => false,
+            EmulatedTls::All => true,
+            EmulatedTls::Local => def_id.is_local(),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -28,8 +52,13 @@ enum TodoItem {
 }
 
 impl ConstantCx {
-    pub(crate) fn new() -> Self {
-        ConstantCx { todo: vec![], done: FxHashSet::default(), anon_allocs: FxHashMap::default() }
+    pub(crate) fn new(emulated_tls: EmulatedTls) -> Self {
+        ConstantCx {
+            todo: vec![],
+            done: FxHashSet::default(),
+            anon_allocs: FxHashMap::default(),
+            emulated_tls,
+        }
     }
 
     pub(crate) fn finalize(mut self, tcx: TyCtxt<'_>, module: &mut dyn Module) {
@@ -78,10 +107,19 @@ pub(crate) fn check_constants(fx: &mut FunctionCx<'_, '_, '_>) -> bool {
     all_constants_ok
 }
 
-pub(crate) fn codegen_static(tcx: TyCtxt<'_>, module: &mut dyn Module, def_id: DefId) {
-    let mut constants_cx = ConstantCx::new();
+pub(crate) fn codegen_static(
+    cx: &mut crate::CodegenCx<'_>,
+    module: &mut dyn Module,
+    def_id: DefId,
+) {
+    let mut constants_cx = ConstantCx::new(cx.emulated_tls);
     constants_cx.todo.push(TodoItem::Static(def_id));
-    constants_cx.finalize(tcx, module);
+    constants_cx.finalize(cx.tcx, module);
+
+    if let Some(debug_context) = &mut cx.debug_context {
+        let data_id = data_id_for_static(cx.tcx, module, def_id, true, cx.emulated_tls);
+        debug_context.define_static(def_id, data_id);
+    }
 }
 
 pub(crate) fn codegen_tls_ref<'tcx>(
@@ -89,21 +127,51 @@ pub(crate) fn codegen_tls_ref<'tcx>(
     def_id: DefId,
     layout: TyAndLayout<'tcx>,
 ) -> CValue<'tcx> {
-    let data_id = data_id_for_static(fx.tcx, fx.module, def_id, false);
+    if fx.cx.emulated_tls == EmulatedTls::Local && !def_id.is_local() {
+        fx.tcx.sess.fatal(&format!(
+            "The thread local static `{}` is defined in a dependency using native TLS, which \
+            can't be accessed from jitted code",
+            fx.tcx.def_path_str(def_id),
+        ));
+    }
+
+    let data_id = data_id_for_static(fx.tcx, fx.module, def_id, false, fx.cx.emulated_tls);
     let local_data_id = fx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
     if fx.clif_comments.enabled() {
         fx.add_comment(local_data_id, format!("tls {:?}", def_id));
     }
-    let tls_ptr = fx.bcx.ins().tls_value(fx.pointer_type, local_data_id);
+    let tls_ptr = if fx.cx.emulated_tls.is_emulated(def_id) {
+        let control_ptr = fx.bcx.ins().global_value(fx.pointer_type, local_data_id);
+        fx.lib_call(
+            "__emutls_get_address",
+            vec![AbiParam::new(fx.pointer_type)],
+            vec![AbiParam::new(fx.pointer_type)],
+            &[control_ptr],
+        )[0]
+    } else {
+        fx.bcx.ins().tls_value(fx.pointer_type, local_data_id)
+    };
     CValue::by_val(tls_ptr, layout)
 }
 
+/// Whether thread locals are emulated by default using `__emutls_get_address` from libgcc instead
+/// of using native TLS. Cranelift doesn't support the native TLS model of Windows, but MinGW
+/// provides emutls just like GCC uses for its own thread locals on Windows. Bionic only supports
+/// native TLS since Android 10, so the NDK uses emutls too. OpenBSD doesn't support native TLS at
+/// all.
+pub(crate) fn uses_emutls(tcx: TyCtxt<'_>) -> bool {
+    // FIXME support TLS on MSVC targets. These don't have libgcc, so native TLS is necessary.
+    (tcx.sess.target.is_like_windows && tcx.sess.target.env == "gnu")
+        || tcx.sess.target.os == "android"
+        || tcx.sess.target.os == "openbsd"
+}
+
 fn codegen_static_ref<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
     def_id: DefId,
     layout: TyAndLayout<'tcx>,
 ) -> CPlace<'tcx> {
-    let data_id = data_id_for_static(fx.tcx, fx.module, def_id, false);
+    let data_id = data_id_for_static(fx.tcx, fx.module, def_id, false, fx.cx.emulated_tls);
     let local_data_id = fx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
     if fx.clif_comments.enabled() {
         fx.add_comment(local_data_id, format!("{:?}", def_id));
@@ -210,7 +278,13 @@ pub(crate) fn codegen_const_value<'tcx>(
                         }
                         Some(GlobalAlloc::Static(def_id)) => {
                             assert!(fx.tcx.is_static(def_id));
-                            let data_id = data_id_for_static(fx.tcx, fx.module, def_id, false);
+                            let data_id = data_id_for_static(
+                                fx.tcx,
+                                fx.module,
+                                def_id,
+                                false,
+                                fx.cx.emulated_tls,
+                            );
                             let local_data_id =
                                 fx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
                             if fx.clif_comments.enabled() {
@@ -280,6 +354,7 @@ fn data_id_for_static(
     module: &mut dyn Module,
     def_id: DefId,
     definition: bool,
+    emulated_tls: EmulatedTls,
 ) -> DataId {
     let rlinkage = tcx.codegen_fn_attrs(def_id).linkage;
     let linkage = if definition {
@@ -303,15 +378,15 @@ fn data_id_for_static(
     let align = tcx.layout_of(ParamEnv::reveal_all().and(ty)).unwrap().align.pref.bytes();
 
     let attrs = tcx.codegen_fn_attrs(def_id);
+    let is_tls = attrs.flags.contains(CodegenFnAttrFlags::THREAD_LOCAL);
 
-    let data_id = module
-        .declare_data(
-            &*symbol_name,
-            linkage,
-            is_mutable,
-            attrs.flags.contains(CodegenFnAttrFlags::THREAD_LOCAL),
-        )
-        .unwrap();
+    let data_id = if is_tls && emulated_tls.is_emulated(def_id) {
+        // Emulated thread locals are referenced through their control variable, which
+        // `__emutls_get_address` writes to on first use.
+        module.declare_data(&format!("__emutls_v.{}", symbol_name), linkage, true, false).unwrap()
+    } else {
+        module.declare_data(&*symbol_name, linkage, is_mutable, is_tls).unwrap()
+    };
 
     if rlinkage.is_some() {
         // Comment copied from https://github.com/rust-lang/rust/blob/45060c2a66dfd667f88bd8b94261b28a58d85bd5/src/librustc_codegen_llvm/consts.rs#L141
@@ -343,6 +418,7 @@ fn data_id_for_static(
 
 fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut dyn Module, cx: &mut ConstantCx) {
     while let Some(todo_item) = cx.todo.pop() {
+        let mut emutls_control_data_id = None;
         let (data_id, alloc, section_name) = match todo_item {
             TodoItem::Alloc(alloc_id) => {
                 //println!("alloc_id {}", alloc_id);
@@ -360,7 +436,23 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut dyn Module, cx: &mut Constant
 
                 let alloc = tcx.eval_static_initializer(def_id).unwrap();
 
-                let data_id = data_id_for_static(tcx, module, def_id, true);
+                let mut data_id = data_id_for_static(tcx, module, def_id, true, cx.emulated_tls);
+                if tcx.codegen_fn_attrs(def_id).flags.contains(CodegenFnAttrFlags::THREAD_LOCAL)
+                    && cx.emulated_tls.is_emulated(def_id)
+                {
+                    // The initial value is stored in a template which is copied for every thread.
+                    emutls_control_data_id = Some(data_id);
+                    let instance = Instance::mono(tcx, def_id).polymorphize(tcx);
+                    let symbol_name = tcx.symbol_name(instance).name;
+                    data_id = module
+                        .declare_data(
+                            &format!("__emutls_t.{}", symbol_name),
+                            Linkage::Local,
+                            false,
+                            false,
+                        )
+                        .unwrap();
+                }
                 (data_id, alloc, section_name)
             }
         };
@@ -417,7 +509,7 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut dyn Module, cx: &mut Constant
                     // Don't push a `TodoItem::Static` here, as it will cause statics used by
                     // multiple crates to be duplicated between them. It isn't necessary anyway,
                     // as it will get pushed by `codegen_static` when necessary.
-                    data_id_for_static(tcx, module, def_id, false)
+                    data_id_for_static(tcx, module, def_id, false, cx.emulated_tls)
                 }
             };
 
@@ -427,11 +519,40 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut dyn Module, cx: &mut Constant
 
         module.define_data(data_id, &data_ctx).unwrap();
         cx.done.insert(data_id);
+
+        if let Some(control_data_id) = emutls_control_data_id {
+            define_emutls_control(tcx, module, control_data_id, data_id, alloc);
+        }
     }
 
     assert!(cx.todo.is_empty(), "{:?}", cx.todo);
 }
 
+/// Define the `__emutls_v.*` control variable of an emulated thread local. This matches the
+/// `__emutls_object` struct of libgcc.
+fn define_emutls_control(
+    tcx: TyCtxt<'_>,
+    module: &mut dyn Module,
+    control_data_id: DataId,
+    template_data_id: DataId,
+    alloc: &Allocation,
+) {
+    let ptr_size = tcx.data_layout.pointer_size;
+    let mut control = Vec::with_capacity(4 * ptr_size.bytes() as usize);
+    for word in &[alloc.len() as u128, alloc.align.bytes() as u128, 0, 0] {
+        let mut bytes = vec![0; ptr_size.bytes() as usize];
+        write_target_uint(tcx.data_layout.endian, &mut bytes, *word).unwrap();
+        control.extend(bytes);
+    }
+
+    let mut data_ctx = DataContext::new();
+    data_ctx.set_align(tcx.data_layout.pointer_align.pref.bytes());
+    data_ctx.define(control.into_boxed_slice());
+    let template = module.declare_data_in_data(template_data_id, &mut data_ctx);
+    data_ctx.write_data_addr(3 * ptr_size.bytes() as u32, template, 0);
+    module.define_data(control_data_id, &data_ctx).unwrap();
+}
+
 pub(crate) fn mir_operand_get_const_val<'tcx>(
     fx: &FunctionCx<'_, '_, 'tcx>,
     operand: &Operand<'tcx>,
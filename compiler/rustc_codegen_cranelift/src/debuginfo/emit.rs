@@ -1,8 +1,15 @@
 //! Write the debuginfo into an object file.
 
+use std::rc::Rc;
+
 use rustc_data_structures::fx::FxHashMap;
 
-use gimli::write::{Address, AttributeValue, EndianVec, Result, Sections, Writer};
+use cranelift_module::{DataId, FuncId};
+
+use gimli::write::{
+    Address, AttributeValue, DebugInfoOffsets, EndianVec, LineProgram, Result, Sections, Unit,
+    UnitEntryId, UnitId, UnitTable, Writer,
+};
 use gimli::{RunTimeEndian, SectionId};
 
 use crate::backend::WriteDebugInfo;
@@ -10,32 +17,96 @@
 use super::DebugContext;
 
 impl DebugContext<'_> {
-    pub(crate) fn emit<P: WriteDebugInfo>(&mut self, product: &mut P) {
+    /// Write all non-empty debug sections. This leaves the debug context without any entries.
+    pub(super) fn write_sections(&mut self) -> Vec<(SectionId, WriterRelocate)> {
         let unit_range_list_id = self.dwarf.unit.ranges.add(self.unit_range_list.clone());
         let root = self.dwarf.unit.root();
         let root = self.dwarf.unit.get_mut(root);
         root.set(gimli::DW_AT_ranges, AttributeValue::RangeListRef(unit_range_list_id));
 
-        let mut sections = Sections::new(WriterRelocate::new(self.endian));
-        self.dwarf.write(&mut sections).unwrap();
+        // Write the unit as part of a `UnitTable` to get the offsets of the entries, which are
+        // necessary for the name lookup tables.
+        let encoding = self.dwarf.unit.encoding();
+        let unit =
+            std::mem::replace(&mut self.dwarf.unit, Unit::new(encoding, LineProgram::none()));
+        let mut units = UnitTable::default();
+        let unit_id = units.add(unit);
 
-        let mut section_map = FxHashMap::default();
+        let symbols: Rc<[DebugSymbol]> = self.symbols.clone().into();
+        let mut sections = Sections::new(WriterRelocate::new(self.endian, symbols.clone()));
+        let line_strings = self.dwarf.line_strings.write(&mut sections.debug_line_str).unwrap();
+        let strings = self.dwarf.strings.write(&mut sections.debug_str).unwrap();
+        let offsets = units.write(&mut sections, &line_strings, &strings).unwrap();
+        let debug_info_len = sections.debug_info.len();
+
+        let endian = self.endian;
+        let mut written = vec![];
         let _: Result<()> = sections.for_each_mut(|id, section| {
             if !section.writer.slice().is_empty() {
-                let section_id = product.add_debug_section(id, section.writer.take());
-                section_map.insert(id, section_id);
+                let empty = WriterRelocate::new(endian, symbols.clone());
+                written.push((id, std::mem::replace(section, empty)));
             }
             Ok(())
         });
 
-        let _: Result<()> = sections.for_each(|id, section| {
-            if let Some(section_id) = section_map.get(&id) {
-                for reloc in &section.relocs {
-                    product.add_debug_reloc(&section_map, section_id, reloc);
-                }
+        // FIXME emit .debug_names instead for DWARF 5. Until then keep emitting the deprecated
+        // .debug_pubnames and .debug_pubtypes, which debuggers still accept for DWARF 5 units.
+        let pubnames = self.write_name_table(&self.pubnames, &offsets, unit_id, debug_info_len);
+        written.push((SectionId::DebugPubNames, pubnames));
+        let pubtypes = self.write_name_table(&self.pubtypes, &offsets, unit_id, debug_info_len);
+        written.push((SectionId::DebugPubTypes, pubtypes));
+
+        written
+    }
+
+    /// Write a `.debug_pubnames` or `.debug_pubtypes` table for the given entries.
+    fn write_name_table(
+        &self,
+        names: &[(UnitEntryId, String)],
+        offsets: &DebugInfoOffsets,
+        unit_id: UnitId,
+        debug_info_len: usize,
+    ) -> WriterRelocate {
+        let unit_offset = offsets.unit(unit_id).0;
+
+        let mut table = WriterRelocate::new(self.endian, Rc::from(vec![]));
+        table.write_u32(0).unwrap(); // Length, filled in below
+        table.write_u16(2).unwrap(); // Version
+        table.write_offset(unit_offset, SectionId::DebugInfo, 4).unwrap();
+        table.write_u32((debug_info_len - unit_offset) as u32).unwrap();
+        for (entry_id, name) in names {
+            let entry_offset = offsets.entry(unit_id, *entry_id).0 - unit_offset;
+            table.write_u32(entry_offset as u32).unwrap();
+            table.write(name.as_bytes()).unwrap();
+            table.write_u8(0).unwrap();
+        }
+        table.write_u32(0).unwrap();
+
+        let length = table.len() - 4;
+        table.writer.write_udata_at(0, length as u64, 4).unwrap();
+        table
+    }
+
+    pub(crate) fn emit<P: WriteDebugInfo>(&mut self, product: &mut P) {
+        let mut sections = self.write_sections();
+        let is_like_osx = self.tcx.sess.target.is_like_osx;
+
+        let mut section_map = FxHashMap::default();
+        for (id, section) in &mut sections {
+            // Mach-O debug sections are not merged by the linker, so references between them are
+            // plain offsets. dsymutil doesn't understand relocations for them.
+            if is_like_osx {
+                section.resolve_section_relocs();
             }
-            Ok(())
-        });
+            let section_id = product.add_debug_section(*id, section.writer.take());
+            section_map.insert(*id, section_id);
+        }
+
+        for (id, section) in &sections {
+            for reloc in &section.relocs {
+                product.add_debug_reloc(&section_map, &section_map[id], reloc);
+            }
+        }
     }
 }
 
@@ -51,7 +122,15 @@ pub(crate) struct DebugReloc {
 #[derive(Clone)]
 pub(crate) enum DebugRelocName {
     Section(SectionId),
-    Symbol(usize),
+    Symbol(DebugSymbol),
+}
+
+/// A function or data object referenced by the debuginfo. The `symbol` of an [`Address::Symbol`]
+/// is an index into a table of these.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum DebugSymbol {
+    Func(FuncId),
+    Data(DataId),
 }
 
 /// A [`Writer`] that collects all necessary relocations.
@@ -59,25 +138,45 @@ pub(crate) enum DebugRelocName {
 pub(super) struct WriterRelocate {
     pub(super) relocs: Vec<DebugReloc>,
     pub(super) writer: EndianVec<RunTimeEndian>,
+    /// The table the `symbol` of every [`Address::Symbol`] is an index into.
+    symbols: Rc<[DebugSymbol]>,
 }
 
 impl WriterRelocate {
-    pub(super) fn new(endian: RunTimeEndian) -> Self {
-        WriterRelocate { relocs: Vec::new(), writer: EndianVec::new(endian) }
+    pub(super) fn new(endian: RunTimeEndian, symbols: Rc<[DebugSymbol]>) -> Self {
+        WriterRelocate { relocs: Vec::new(), writer: EndianVec::new(endian), symbols }
+    }
+
+    /// Resolve all relocations against other debug sections, leaving only relocations against
+    /// symbols.
+    pub(super) fn resolve_section_relocs(&mut self) {
+        let writer = &mut self.writer;
+        self.relocs.retain(|reloc| match reloc.name {
+            DebugRelocName::Section(_) => {
+                let val = reloc.addend as u64;
+                writer.write_udata_at(reloc.offset as usize, val, reloc.size).unwrap();
+                false
+            }
+            DebugRelocName::Symbol(_) => true,
+        });
     }
 
     /// Perform the collected relocations to be usable for JIT usage.
     #[cfg(feature = "jit")]
     pub(super) fn relocate_for_jit(mut self, jit_module: &cranelift_jit::JITModule) -> Vec<u8> {
-        use std::convert::TryInto;
-
         for reloc in self.relocs.drain(..) {
             match reloc.name {
-                super::DebugRelocName::Section(_) => unreachable!(),
+                // Every section is emitted separately, so offsets into a section don't need any
+                // adjustment.
+                super::DebugRelocName::Section(_) => {
+                    let val = reloc.addend as u64;
+                    self.writer.write_udata_at(reloc.offset as usize, val, reloc.size).unwrap();
+                }
                 super::DebugRelocName::Symbol(sym) => {
-                    let addr = jit_module.get_finalized_function(
-                        cranelift_module::FuncId::from_u32(sym.try_into().unwrap()),
-                    );
+                    let addr = match sym {
+                        DebugSymbol::Func(func_id) => jit_module.get_finalized_function(func_id),
+                        DebugSymbol::Data(data_id) => jit_module.get_finalized_data(data_id).0,
+                    };
                     let val = (addr as u64 as i64 + reloc.addend) as u64;
                     self.writer.write_udata_at(reloc.offset as usize, val, reloc.size).unwrap();
                 }
@@ -114,7 +213,7 @@ fn write_address(&mut self, address: Address, size: u8) -> Result<()> {
                 self.relocs.push(DebugReloc {
                     offset: offset as u32,
                     size,
-                    name: DebugRelocName::Symbol(symbol),
+                    name: DebugRelocName::Symbol(self.symbols[symbol]),
                     addend: addend as i64,
                     kind: object::RelocationKind::Absolute,
                 });
@@ -171,6 +270,16 @@ fn write_eh_pointer(&mut self, address: Address, eh_pe: gimli::DwEhPe, size: u8)
                 self.write_eh_pointer_data(val, eh_pe.format(), size)
             }
             Address::Symbol { symbol, addend } => match eh_pe.application() {
+                gimli::DW_EH_PE_absptr => {
+                    self.relocs.push(DebugReloc {
+                        offset: self.len() as u32,
+                        size,
+                        name: DebugRelocName::Symbol(self.symbols[symbol]),
+                        addend,
+                        kind: object::RelocationKind::Absolute,
+                    });
+                    self.write_udata(0, size)
+                }
                 gimli::DW_EH_PE_pcrel => {
                     let size = match eh_pe.format() {
                         gimli::DW_EH_PE_sdata4 => 4,
@@ -179,7 +288,7 @@ fn write_eh_pointer(&mut self, address: Address, eh_pe: gimli::DwEhPe, size: u8)
                     self.relocs.push(DebugReloc {
                         offset: self.len() as u32,
                         size,
-                        name: DebugRelocName::Symbol(symbol),
+                        name: DebugRelocName::Symbol(self.symbols[symbol]),
                         addend,
                         kind: object::RelocationKind::Relative,
                     });
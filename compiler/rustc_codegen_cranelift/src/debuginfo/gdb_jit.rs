@@ -0,0 +1,84 @@
+//! Registration of the debuginfo of JITed code with gdb using the [GDB JIT interface].
+//!
+//! [GDB JIT interface]: https://sourceware.org/gdb/current/onlinedocs/gdb/JIT-Interface.html
+
+use std::ptr;
+
+use object::write::{SectionKind, StandardSegment};
+
+use super::DebugContext;
+
+const JIT_REGISTER_FN: u32 = 1;
+
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+/// Gdb sets a breakpoint on this function to get notified about changes to
+/// `__jit_debug_descriptor`.
+#[no_mangle]
+#[inline(never)]
+extern "C" fn __jit_debug_register_code() {
+    // Prevent this function from being optimized away or merged with other functions.
+    unsafe {
+        ptr::read_volatile(&0u8);
+    }
+}
+
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: 0,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+impl DebugContext<'_> {
+    /// Write the debuginfo for all functions defined in `jit_module` into an in-memory object
+    /// file and register it with gdb. This must only be called from the thread running the JIT.
+    pub(crate) unsafe fn register_jit(mut self, jit_module: &cranelift_jit::JITModule) {
+        let sections = self.write_sections();
+
+        let symfile = crate::backend::with_object(self.tcx.sess, "jit", |object| {
+            for (id, section) in sections {
+                let data = section.relocate_for_jit(jit_module);
+
+                let segment = object.segment_name(StandardSegment::Debug).to_vec();
+                let section_id =
+                    object.add_section(segment, id.name().as_bytes().to_vec(), SectionKind::Debug);
+                object.section_mut(section_id).set_data(data, 1);
+            }
+        });
+
+        // FIXME unregister the symbol file once cranelift-jit supports deallocating individual
+        // functions
+        let (symfile_addr, symfile_size, _) = Vec::into_raw_parts(symfile);
+
+        let entry = Box::into_raw(Box::new(JitCodeEntry {
+            next_entry: __jit_debug_descriptor.first_entry,
+            prev_entry: ptr::null_mut(),
+            symfile_addr,
+            symfile_size: symfile_size as u64,
+        }));
+        if !__jit_debug_descriptor.first_entry.is_null() {
+            (*__jit_debug_descriptor.first_entry).prev_entry = entry;
+        }
+        __jit_debug_descriptor.first_entry = entry;
+        __jit_debug_descriptor.relevant_entry = entry;
+        __jit_debug_descriptor.action_flag = JIT_REGISTER_FN;
+        __jit_debug_register_code();
+    }
+}
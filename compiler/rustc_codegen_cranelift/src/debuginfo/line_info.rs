@@ -1,20 +1,19 @@
 //! Line info generation (`.debug_line`)
 
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::path::{Component, Path};
 
 use crate::prelude::*;
 
-use rustc_span::{
-    FileName, Pos, SourceFile, SourceFileAndLine, SourceFileHash, SourceFileHashAlgorithm,
-};
+use rustc_span::{FileName, Pos, SourceFile, SourceFileHash, SourceFileHashAlgorithm};
 
 use cranelift_codegen::binemit::CodeOffset;
 use cranelift_codegen::machinst::MachSrcLoc;
 
 use gimli::write::{
-    Address, AttributeValue, FileId, FileInfo, LineProgram, LineString, LineStringTable,
-    UnitEntryId,
+    Address, AttributeValue, FileId, FileInfo, LineProgram, LineString, LineStringTable, Range,
+    RangeList, UnitEntryId,
 };
 
 // OPTIMIZATION: It is cheaper to do this in one pass than using `.parent()` and `.file_name()`.
@@ -96,6 +95,36 @@ fn line_program_add_file(
     }
 }
 
+/// Map `span` to the location a debugger should show for code generated from it.
+pub(super) fn debugger_span(tcx: TyCtxt<'_>, span: Span, function_span: Span) -> Span {
+    // Based on https://github.com/rust-lang/rust/blob/e369d87b015a84653343032833d65d0545fd3f26/src/librustc_codegen_ssa/mir/mod.rs#L116-L131
+    // In order to have a good line stepping behavior in debugger, we overwrite debug
+    // locations of macro expansions with that of the outermost expansion site
+    // (unless the crate is being compiled with `-Z debug-macros`).
+    let span = if !span.from_expansion() || tcx.sess.opts.debugging_opts.debug_macros {
+        span
+    } else {
+        // Walk up the macro expansion chain until we reach a non-expanded span.
+        // We also stop at the function body level because no line stepping can occur
+        // at the level above that.
+        rustc_span::hygiene::walk_chain(span, function_span.ctxt())
+    };
+
+    if span.is_dummy() {
+        return function_span;
+    }
+
+    // Proc macros can produce spans pointing into sources that don't exist on disk, like the
+    // pretty printed token stream of the macro input. Use the outermost invocation site of the
+    // macro instead, which is always in a real file unless the function itself isn't.
+    if span.from_expansion() && !tcx.sess.source_map().span_to_filename(span).is_real() {
+        let call_site = span.source_callsite();
+        if call_site.is_dummy() { function_span } else { call_site }
+    } else {
+        span
+    }
+}
+
 impl<'tcx> DebugContext<'tcx> {
     pub(super) fn emit_location(&mut self, entry_id: UnitEntryId, span: Span) {
         let loc = self.tcx.sess.source_map().lookup_char_pos(span.lo());
@@ -110,8 +139,7 @@ pub(super) fn emit_location(&mut self, entry_id: UnitEntryId, span: Span) {
 
         entry.set(gimli::DW_AT_decl_file, AttributeValue::FileIndex(Some(file_id)));
         entry.set(gimli::DW_AT_decl_line, AttributeValue::Udata(loc.line as u64));
-        // FIXME: probably omit this
-        entry.set(gimli::DW_AT_decl_column, AttributeValue::Udata(loc.col.to_usize() as u64));
+        entry.set(gimli::DW_AT_decl_column, AttributeValue::Udata(loc.col.to_usize() as u64 + 1));
     }
 
     pub(super) fn create_debug_lines(
@@ -137,30 +165,14 @@ pub(super) fn create_debug_lines(
             }
             last_span = Some(span);
 
-            // Based on https://github.com/rust-lang/rust/blob/e369d87b015a84653343032833d65d0545fd3f26/src/librustc_codegen_ssa/mir/mod.rs#L116-L131
-            // In order to have a good line stepping behavior in debugger, we overwrite debug
-            // locations of macro expansions with that of the outermost expansion site
-            // (unless the crate is being compiled with `-Z debug-macros`).
-            let span = if !span.from_expansion() || tcx.sess.opts.debugging_opts.debug_macros {
-                span
-            } else {
-                // Walk up the macro expansion chain until we reach a non-expanded span.
-                // We also stop at the function body level because no line stepping can occur
-                // at the level above that.
-                rustc_span::hygiene::walk_chain(span, function_span.ctxt())
-            };
-
-            let (file, line, col) = match tcx.sess.source_map().lookup_line(span.lo()) {
-                Ok(SourceFileAndLine { sf: file, line }) => {
-                    let line_pos = file.line_begin_pos(span.lo());
+            let span = debugger_span(tcx, span, function_span);
 
-                    (
-                        file,
-                        u64::try_from(line).unwrap() + 1,
-                        u64::from((span.lo() - line_pos).to_u32()) + 1,
-                    )
-                }
-                Err(file) => (file, 0, 0),
+            let file = tcx.sess.source_map().lookup_source_file(span.lo());
+            // Count columns in chars rather than bytes like the LLVM backend, so that multi-byte
+            // chars earlier on the line don't shift the column.
+            let (line, col) = match file.lookup_file_pos(span.lo()) {
+                (0, _) => (0, 0),
+                (line, col) => (line as u64, col.to_usize() as u64 + 1),
             };
 
             // line_program_add_file is very slow.
@@ -205,15 +217,121 @@ pub(super) fn create_debug_lines(
 
         assert_ne!(func_end, 0);
 
+        let high_pc = self.high_pc(symbol, func_end);
         let entry = self.dwarf.unit.get_mut(entry_id);
         entry.set(
             gimli::DW_AT_low_pc,
             AttributeValue::Address(Address::Symbol { symbol, addend: 0 }),
         );
-        entry.set(gimli::DW_AT_high_pc, AttributeValue::Udata(u64::from(func_end)));
+        entry.set(gimli::DW_AT_high_pc, high_pc);
 
         self.emit_location(entry_id, function_span);
 
         func_end
     }
+
+    /// Emit a `DW_TAG_inlined_subroutine` for every body inlined by the MIR inliner, covering the
+    /// code generated for the inlined body.
+    pub(super) fn create_inlined_subroutines(
+        &mut self,
+        symbol: usize,
+        entry_id: UnitEntryId,
+        instance: Instance<'tcx>,
+        mir: &Body<'tcx>,
+        context: &Context,
+        source_info_set: &indexmap::IndexSet<SourceInfo>,
+    ) {
+        let inlined_root = |scope: SourceScope| {
+            let scope_data = &mir.source_scopes[scope];
+            if scope_data.inlined.is_some() { Some(scope) } else { scope_data.inlined_parent_scope }
+        };
+
+        // Inlined scopes are always created after the scope containing the call, so iterating
+        // in scope order visits parents before their children.
+        let mut scope_ranges = BTreeMap::<SourceScope, Vec<(CodeOffset, CodeOffset)>>::new();
+        let mcr = context.mach_compile_result.as_ref().unwrap();
+        for &MachSrcLoc { start, end, loc } in mcr.buffer.get_srclocs_sorted() {
+            if loc.is_default() {
+                continue;
+            }
+            let source_info = *source_info_set.get_index(loc.bits() as usize).unwrap();
+
+            // The code belongs to the inlined body and to every body it has been inlined into.
+            let mut scope = inlined_root(source_info.scope);
+            while let Some(inlined_scope) = scope {
+                let ranges = scope_ranges.entry(inlined_scope).or_default();
+                match ranges.last_mut() {
+                    Some((_, last_end)) if *last_end == start => *last_end = end,
+                    _ => ranges.push((start, end)),
+                }
+                scope = mir.source_scopes[inlined_scope].parent_scope.and_then(inlined_root);
+            }
+        }
+
+        let mut scope_entries = FxHashMap::default();
+        for (scope, ranges) in scope_ranges {
+            let scope_data = &mir.source_scopes[scope];
+            let (callee, call_span) = scope_data.inlined.unwrap();
+            let callee = self.tcx.subst_and_normalize_erasing_regions(
+                instance.substs,
+                ParamEnv::reveal_all(),
+                callee,
+            );
+            let parent = match scope_data.parent_scope.and_then(inlined_root) {
+                Some(parent) => scope_entries[&parent],
+                None => entry_id,
+            };
+            let abstract_origin = self.abstract_subprogram(callee);
+
+            let range_list = RangeList(
+                ranges
+                    .into_iter()
+                    .map(|(start, end)| Range::StartEnd {
+                        begin: Address::Symbol { symbol, addend: i64::from(start) },
+                        end: Address::Symbol { symbol, addend: i64::from(end) },
+                    })
+                    .collect(),
+            );
+            let range_list_id = self.dwarf.unit.ranges.add(range_list);
+
+            let call_loc = self.tcx.sess.source_map().lookup_char_pos(call_span.lo());
+            let call_file = line_program_add_file(
+                &mut self.dwarf.unit.line_program,
+                &mut self.dwarf.line_strings,
+                &call_loc.file,
+            );
+
+            let inlined_id = self.dwarf.unit.add(parent, gimli::DW_TAG_inlined_subroutine);
+            let inlined_entry = self.dwarf.unit.get_mut(inlined_id);
+            inlined_entry
+                .set(gimli::DW_AT_abstract_origin, AttributeValue::UnitRef(abstract_origin));
+            inlined_entry.set(gimli::DW_AT_ranges, AttributeValue::RangeListRef(range_list_id));
+            inlined_entry.set(gimli::DW_AT_call_file, AttributeValue::FileIndex(Some(call_file)));
+            inlined_entry.set(gimli::DW_AT_call_line, AttributeValue::Udata(call_loc.line as u64));
+            inlined_entry.set(
+                gimli::DW_AT_call_column,
+                AttributeValue::Udata(call_loc.col.to_usize() as u64 + 1),
+            );
+
+            scope_entries.insert(scope, inlined_id);
+        }
+    }
+
+    /// The `DW_TAG_subprogram` referenced by all places `instance` got inlined into.
+    fn abstract_subprogram(&mut self, instance: Instance<'tcx>) -> UnitEntryId {
+        if let Some(&entry_id) = self.abstract_subprograms.get(&instance) {
+            return entry_id;
+        }
+
+        let entry_id = self.dwarf.unit.add(self.dwarf.unit.root(), gimli::DW_TAG_subprogram);
+        let entry = self.dwarf.unit.get_mut(entry_id);
+        let name_id = self.dwarf.strings.add(self.tcx.symbol_name(instance).name);
+        entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
+        entry.set(gimli::DW_AT_linkage_name, AttributeValue::StringRef(name_id));
+        entry.set(gimli::DW_AT_inline, AttributeValue::Inline(gimli::DW_INL_inlined));
+        self.emit_location(entry_id, self.tcx.def_span(instance.def_id()));
+
+        self.abstract_subprograms.insert(instance, entry_id);
+        entry_id
+    }
 }
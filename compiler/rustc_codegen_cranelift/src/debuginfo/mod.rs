@@ -1,12 +1,21 @@
 //! Handling of everything related to debuginfo.
 
 mod emit;
+#[cfg(feature = "jit")]
+mod gdb_jit;
 mod line_info;
+mod perf_jit;
 mod unwind;
 
+use crate::pointer::PointerBase;
 use crate::prelude::*;
 
+use rustc_codegen_ssa::debuginfo::type_names::compute_debuginfo_type_name;
 use rustc_index::vec::IndexVec;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
+use rustc_middle::ty::layout::PrimitiveExt;
+use rustc_session::config::DebugInfo;
+use rustc_target::abi::{Primitive, TagEncoding, Variants};
 
 use cranelift_codegen::entity::EntityRef;
 use cranelift_codegen::ir::{LabelValueLoc, StackSlots, ValueLabel, ValueLoc};
@@ -17,9 +26,12 @@
     Address, AttributeValue, DwarfUnit, Expression, LineProgram, LineString, Location,
     LocationList, Range, RangeList, UnitEntryId,
 };
-use gimli::{Encoding, Format, LineEncoding, RunTimeEndian, X86_64};
+use gimli::{AArch64, Encoding, Format, LineEncoding, RunTimeEndian, X86_64};
 
-pub(crate) use emit::{DebugReloc, DebugRelocName};
+pub(crate) use emit::{DebugReloc, DebugRelocName, DebugSymbol};
+#[cfg(feature = "jit")]
+pub(crate) use perf_jit::write_perf_map;
+pub(crate) use perf_jit::PerfMapEntry;
 pub(crate) use unwind::UnwindContext;
 
 fn target_endian(tcx: TyCtxt<'_>) -> RunTimeEndian {
@@ -39,26 +51,39 @@ pub(crate) struct DebugContext<'tcx> {
     dwarf: DwarfUnit,
     unit_range_list: RangeList,
 
+    /// `-Cdebuginfo=1`: only emit line tables and the function entries they belong to.
+    line_tables_only: bool,
+
     types: FxHashMap<Ty<'tcx>, UnitEntryId>,
+    abstract_subprograms: FxHashMap<Instance<'tcx>, UnitEntryId>,
+
+    /// The functions and statics referenced by `Address::Symbol`, indexed by its `symbol`.
+    symbols: Vec<DebugSymbol>,
+
+    /// Functions and global variables for `.debug_pubnames`.
+    pubnames: Vec<(UnitEntryId, String)>,
+    /// Types for `.debug_pubtypes`.
+    pubtypes: Vec<(UnitEntryId, String)>,
 }
 
 impl<'tcx> DebugContext<'tcx> {
-    pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Self {
+    pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa, dwarf_version: Option<u16>) -> Self {
         let encoding = Encoding {
             format: Format::Dwarf32,
-            // TODO: this should be configurable
-            // macOS doesn't seem to support DWARF > 3
             // 5 version is required for md5 file hash
-            version: if tcx.sess.target.is_like_osx {
+            version: dwarf_version.unwrap_or(if tcx.sess.target.is_like_osx {
+                // macOS doesn't seem to support DWARF > 3
                 3
             } else {
                 // FIXME change to version 5 once the gdb and lldb shipping with the latest debian
                 // support it.
                 4
-            },
+            }),
             address_size: isa.frontend_config().pointer_bytes(),
         };
 
+        // FIXME use DW_FORM_strx and emit .debug_str_offsets for DWARF 5 once gimli supports
+        // writing it.
         let mut dwarf = DwarfUnit::new(encoding);
 
         // FIXME: how to get version when building out of tree?
@@ -105,7 +130,15 @@ pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Self {
             dwarf,
             unit_range_list: RangeList(Vec::new()),
 
+            line_tables_only: tcx.sess.opts.debuginfo == DebugInfo::Limited,
+
             types: FxHashMap::default(),
+            abstract_subprograms: FxHashMap::default(),
+
+            symbols: Vec::new(),
+
+            pubnames: Vec::new(),
+            pubtypes: Vec::new(),
         }
     }
 
@@ -123,7 +156,9 @@ fn dwarf_ty(&mut self, ty: Ty<'tcx>) -> UnitEntryId {
             type_id
         };
 
-        let name = format!("{}", ty);
+        // Use the same type names as the LLVM backend, as the pretty printers shipped with rustc
+        // match on them.
+        let name = compute_debuginfo_type_name(self.tcx, ty, true);
         let layout = self.tcx.layout_of(ParamEnv::reveal_all().and(ty)).unwrap();
 
         let type_id = match ty.kind() {
@@ -154,32 +189,40 @@ fn dwarf_ty(&mut self, ty: Ty<'tcx>) -> UnitEntryId {
                 // Ensure that type is inserted before recursing to avoid duplicates
                 self.types.insert(ty, type_id);
 
-                let variant = adt_def.non_enum_variant();
-
-                for (field_idx, field_def) in variant.fields.iter().enumerate() {
-                    let field_offset = layout.fields.offset(field_idx);
-                    let field_layout = layout
-                        .field(
-                            &layout::LayoutCx { tcx: self.tcx, param_env: ParamEnv::reveal_all() },
-                            field_idx,
-                        )
-                        .unwrap();
-
-                    let field_type = self.dwarf_ty(field_layout.ty);
-
-                    let field_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_member);
-                    let field_entry = self.dwarf.unit.get_mut(field_id);
-
-                    field_entry.set(
-                        gimli::DW_AT_name,
-                        AttributeValue::String(field_def.ident.as_str().to_string().into_bytes()),
-                    );
-                    field_entry.set(
-                        gimli::DW_AT_data_member_location,
-                        AttributeValue::Udata(field_offset.bytes()),
-                    );
-                    field_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(field_type));
-                }
+                self.define_fields(type_id, layout, adt_def.non_enum_variant());
+
+                type_id
+            }
+            ty::Adt(adt_def, _substs) if adt_def.is_enum() && !layout.is_unsized() => {
+                let type_id = new_entry(&mut self.dwarf, gimli::DW_TAG_structure_type);
+
+                // Ensure that type is inserted before recursing to avoid duplicates
+                self.types.insert(ty, type_id);
+
+                self.define_enum_variants(type_id, layout, adt_def);
+
+                type_id
+            }
+            ty::Closure(def_id, substs) => {
+                let type_id = new_entry(&mut self.dwarf, gimli::DW_TAG_structure_type);
+
+                // Ensure that type is inserted before recursing to avoid duplicates
+                self.types.insert(ty, type_id);
+
+                let upvar_count = substs.as_closure().upvar_tys().count();
+                self.define_upvars(type_id, layout, *def_id, upvar_count);
+
+                type_id
+            }
+            ty::Generator(def_id, substs, _) => {
+                let type_id = new_entry(&mut self.dwarf, gimli::DW_TAG_structure_type);
+
+                // Ensure that type is inserted before recursing to avoid duplicates
+                self.types.insert(ty, type_id);
+
+                // FIXME describe the locals saved across suspension points too
+                let upvar_count = substs.as_generator().prefix_tys().count();
+                self.define_upvars(type_id, layout, *def_id, upvar_count);
 
                 type_id
             }
@@ -188,18 +231,257 @@ fn dwarf_ty(&mut self, ty: Ty<'tcx>) -> UnitEntryId {
 
         let type_entry = self.dwarf.unit.get_mut(type_id);
 
-        type_entry.set(gimli::DW_AT_name, AttributeValue::String(name.into_bytes()));
+        type_entry.set(gimli::DW_AT_name, AttributeValue::String(name.clone().into_bytes()));
         type_entry.set(gimli::DW_AT_byte_size, AttributeValue::Udata(layout.size.bytes()));
 
         self.types.insert(ty, type_id);
+        self.pubtypes.push((type_id, name));
 
         type_id
     }
 
-    fn define_local(&mut self, scope: UnitEntryId, name: String, ty: Ty<'tcx>) -> UnitEntryId {
+    fn define_fields(
+        &mut self,
+        type_id: UnitEntryId,
+        layout: TyAndLayout<'tcx>,
+        variant: &ty::VariantDef,
+    ) {
+        for (field_idx, field_def) in variant.fields.iter().enumerate() {
+            self.define_field(type_id, layout, field_idx, field_def.ident.as_str().to_string());
+        }
+    }
+
+    /// Describe the captured variables of a closure or generator. By-ref captures are stored as
+    /// references to the captured variable, so they are marked as artificial pointers.
+    fn define_upvars(
+        &mut self,
+        type_id: UnitEntryId,
+        layout: TyAndLayout<'tcx>,
+        def_id: DefId,
+        upvar_count: usize,
+    ) {
+        // The names of captures are only known for closures defined in the local crate.
+        let captures: Vec<(String, bool)> = match def_id.as_local() {
+            Some(local_def_id) => self
+                .tcx
+                .typeck(local_def_id)
+                .closure_min_captures_flattened(def_id)
+                .map(|capture| {
+                    let by_ref = matches!(capture.info.capture_kind, ty::UpvarCapture::ByRef(_));
+                    (capture.to_string(self.tcx), by_ref)
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        for field_idx in 0..upvar_count {
+            let (name, by_ref) = captures
+                .get(field_idx)
+                .cloned()
+                .unwrap_or_else(|| (format!("__{}", field_idx), false));
+            let field_id = self.define_field(type_id, layout, field_idx, name);
+            if by_ref {
+                let field_entry = self.dwarf.unit.get_mut(field_id);
+                field_entry.set(gimli::DW_AT_artificial, AttributeValue::Flag(true));
+            }
+        }
+    }
+
+    fn define_field(
+        &mut self,
+        type_id: UnitEntryId,
+        layout: TyAndLayout<'tcx>,
+        field_idx: usize,
+        name: String,
+    ) -> UnitEntryId {
+        let field_offset = layout.fields.offset(field_idx);
+        let field_layout = layout
+            .field(
+                &layout::LayoutCx { tcx: self.tcx, param_env: ParamEnv::reveal_all() },
+                field_idx,
+            )
+            .unwrap();
+
+        let field_type = self.dwarf_ty(field_layout.ty);
+
+        let field_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_member);
+        let field_entry = self.dwarf.unit.get_mut(field_id);
+
+        field_entry.set(gimli::DW_AT_name, AttributeValue::String(name.into_bytes()));
+        field_entry
+            .set(gimli::DW_AT_data_member_location, AttributeValue::Udata(field_offset.bytes()));
+        field_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(field_type));
+
+        field_id
+    }
+
+    /// Describe the variants of an enum using a `DW_TAG_variant_part`. The discriminant values
+    /// are the values of the tag for the respective variant, so for niche encoded enums they are
+    /// the niche values and the dataful variant is the default variant without a discriminant.
+    fn define_enum_variants(
+        &mut self,
+        type_id: UnitEntryId,
+        layout: TyAndLayout<'tcx>,
+        adt_def: &ty::AdtDef,
+    ) {
+        // Uninhabited enums like `Infallible` have a `Variants::Single` layout despite not having
+        // any variant, so leave their type empty.
+        if adt_def.variants.is_empty() {
+            return;
+        }
+
+        let variant_part_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_variant_part);
+
+        let (tag, tag_encoding) = match layout.variants {
+            Variants::Single { index } => {
+                self.define_enum_variant(type_id, variant_part_id, layout, adt_def, index, None);
+                return;
+            }
+            Variants::Multiple { ref tag, ref tag_encoding, tag_field, variants: _ } => {
+                let tag_ty = tag.value.to_int_ty(self.tcx);
+                let tag_type = self.dwarf_ty(tag_ty);
+
+                let tag_id = self.dwarf.unit.add(variant_part_id, gimli::DW_TAG_member);
+                let tag_entry = self.dwarf.unit.get_mut(tag_id);
+                tag_entry.set(gimli::DW_AT_name, AttributeValue::String(b"<tag>".to_vec()));
+                tag_entry.set(gimli::DW_AT_artificial, AttributeValue::Flag(true));
+                tag_entry.set(
+                    gimli::DW_AT_data_member_location,
+                    AttributeValue::Udata(layout.fields.offset(tag_field).bytes()),
+                );
+                tag_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(tag_type));
+
+                let variant_part_entry = self.dwarf.unit.get_mut(variant_part_id);
+                variant_part_entry.set(gimli::DW_AT_discr, AttributeValue::UnitRef(tag_id));
+
+                (tag.clone(), tag_encoding.clone())
+            }
+        };
+
+        let tag_size = tag.value.size(&self.tcx);
+        let is_signed = matches!(tag.value, Primitive::Int(_, true));
+        let discr_value = |tag_value: u128| {
+            let tag_value = tag_size.truncate(tag_value);
+            if is_signed {
+                AttributeValue::Sdata(tag_size.sign_extend(tag_value) as i128 as i64)
+            } else {
+                AttributeValue::Udata(tag_value as u64)
+            }
+        };
+
+        for variant_idx in adt_def.variants.indices() {
+            let discr = match tag_encoding {
+                TagEncoding::Direct => Some(discr_value(
+                    layout.ty.discriminant_for_variant(self.tcx, variant_idx).unwrap().val,
+                )),
+                TagEncoding::Niche { dataful_variant, ref niche_variants, niche_start } => {
+                    if variant_idx == dataful_variant {
+                        None
+                    } else if niche_variants.contains(&variant_idx) {
+                        let relative =
+                            (variant_idx.as_u32() - niche_variants.start().as_u32()) as u128;
+                        Some(discr_value(relative.wrapping_add(niche_start)))
+                    } else {
+                        // Uninhabited variant without a niche value
+                        continue;
+                    }
+                }
+            };
+            self.define_enum_variant(type_id, variant_part_id, layout, adt_def, variant_idx, discr);
+        }
+    }
+
+    fn define_enum_variant(
+        &mut self,
+        type_id: UnitEntryId,
+        variant_part_id: UnitEntryId,
+        layout: TyAndLayout<'tcx>,
+        adt_def: &ty::AdtDef,
+        variant_idx: VariantIdx,
+        discr: Option<AttributeValue>,
+    ) {
+        let variant_def = &adt_def.variants[variant_idx];
+        let variant_layout = layout.for_variant(
+            &layout::LayoutCx { tcx: self.tcx, param_env: ParamEnv::reveal_all() },
+            variant_idx,
+        );
+        let variant_name = variant_def.ident.as_str().to_string();
+
+        // The fields of a variant are described by a separate struct type for every variant. It is
+        // nested inside the enum type to prevent name collisions between variants of different
+        // enums.
+        let variant_type_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_structure_type);
+        let variant_type_entry = self.dwarf.unit.get_mut(variant_type_id);
+        variant_type_entry
+            .set(gimli::DW_AT_name, AttributeValue::String(variant_name.clone().into_bytes()));
+        variant_type_entry
+            .set(gimli::DW_AT_byte_size, AttributeValue::Udata(variant_layout.size.bytes()));
+        self.define_fields(variant_type_id, variant_layout, variant_def);
+
+        let variant_id = self.dwarf.unit.add(variant_part_id, gimli::DW_TAG_variant);
+        if let Some(discr) = discr {
+            self.dwarf.unit.get_mut(variant_id).set(gimli::DW_AT_discr_value, discr);
+        }
+
+        let member_id = self.dwarf.unit.add(variant_id, gimli::DW_TAG_member);
+        let member_entry = self.dwarf.unit.get_mut(member_id);
+        member_entry.set(gimli::DW_AT_name, AttributeValue::String(variant_name.into_bytes()));
+        member_entry.set(gimli::DW_AT_data_member_location, AttributeValue::Udata(0));
+        member_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(variant_type_id));
+    }
+
+    fn add_symbol(&mut self, symbol: DebugSymbol) -> usize {
+        self.symbols.push(symbol);
+        self.symbols.len() - 1
+    }
+
+    pub(crate) fn define_static(&mut self, def_id: DefId, data_id: DataId) {
+        if self.line_tables_only {
+            return;
+        }
+
+        let tcx = self.tcx;
+        if tcx.codegen_fn_attrs(def_id).flags.contains(CodegenFnAttrFlags::THREAD_LOCAL) {
+            // FIXME describe the location of thread locals using DW_OP_form_tls_address
+            return;
+        }
+
+        let instance = Instance::mono(tcx, def_id).polymorphize(tcx);
+        let ty = instance.ty(tcx, ParamEnv::reveal_all());
+        let dw_ty = self.dwarf_ty(ty);
+
+        let var_id = self.dwarf.unit.add(self.dwarf.unit.root(), gimli::DW_TAG_variable);
+        let var_entry = self.dwarf.unit.get_mut(var_id);
+        let name = tcx.def_path_str(def_id);
+        let name_id = self.dwarf.strings.add(name.clone());
+        let linkage_name_id = self.dwarf.strings.add(tcx.symbol_name(instance).name);
+        var_entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
+        var_entry.set(gimli::DW_AT_linkage_name, AttributeValue::StringRef(linkage_name_id));
+        var_entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(dw_ty));
+        var_entry
+            .set(gimli::DW_AT_external, AttributeValue::Flag(tcx.is_reachable_non_generic(def_id)));
+
+        let symbol = self.add_symbol(DebugSymbol::Data(data_id));
+        let mut location = Expression::new();
+        location.op_addr(Address::Symbol { symbol, addend: 0 });
+        let var_entry = self.dwarf.unit.get_mut(var_id);
+        var_entry.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+
+        self.emit_location(var_id, tcx.def_span(def_id));
+        self.pubnames.push((var_id, name));
+    }
+
+    fn define_local(
+        &mut self,
+        scope: UnitEntryId,
+        name: String,
+        ty: Ty<'tcx>,
+        is_arg: bool,
+    ) -> UnitEntryId {
         let dw_ty = self.dwarf_ty(ty);
 
-        let var_id = self.dwarf.unit.add(scope, gimli::DW_TAG_variable);
+        let tag = if is_arg { gimli::DW_TAG_formal_parameter } else { gimli::DW_TAG_variable };
+        let var_id = self.dwarf.unit.add(scope, tag);
         let var_entry = self.dwarf.unit.get_mut(var_id);
 
         var_entry.set(gimli::DW_AT_name, AttributeValue::String(name.into_bytes()));
@@ -208,6 +490,16 @@ fn define_local(&mut self, scope: UnitEntryId, name: String, ty: Ty<'tcx>) -> Un
         var_id
     }
 
+    /// The `DW_AT_high_pc` of a function of `size` bytes starting at `symbol`.
+    fn high_pc(&self, symbol: usize, size: u32) -> AttributeValue {
+        // Using Udata for DW_AT_high_pc requires at least DWARF4
+        if self.dwarf.unit.encoding().version >= 4 {
+            AttributeValue::Udata(u64::from(size))
+        } else {
+            AttributeValue::Address(Address::Symbol { symbol, addend: i64::from(size) })
+        }
+    }
+
     pub(crate) fn define_function(
         &mut self,
         instance: Instance<'tcx>,
@@ -218,7 +510,7 @@ pub(crate) fn define_function(
         source_info_set: &indexmap::IndexSet<SourceInfo>,
         local_map: IndexVec<mir::Local, CPlace<'tcx>>,
     ) {
-        let symbol = func_id.as_u32() as usize;
+        let symbol = self.add_symbol(DebugSymbol::Func(func_id));
         let mir = self.tcx.instance_mir(instance.def);
 
         // FIXME: add to appropriate scope instead of root
@@ -230,57 +522,72 @@ pub(crate) fn define_function(
         // Gdb requires DW_AT_name. Otherwise the DW_TAG_subprogram is skipped.
         entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
         entry.set(gimli::DW_AT_linkage_name, AttributeValue::StringRef(name_id));
+        self.pubnames.push((entry_id, name.to_string()));
 
         let end = self.create_debug_lines(symbol, entry_id, context, mir.span, source_info_set);
+        self.create_inlined_subroutines(symbol, entry_id, instance, mir, context, source_info_set);
 
         self.unit_range_list.0.push(Range::StartLength {
             begin: Address::Symbol { symbol, addend: 0 },
             length: u64::from(end),
         });
 
+        let high_pc = self.high_pc(symbol, end);
         let func_entry = self.dwarf.unit.get_mut(entry_id);
         // Gdb requires both DW_AT_low_pc and DW_AT_high_pc. Otherwise the DW_TAG_subprogram is skipped.
         func_entry.set(
             gimli::DW_AT_low_pc,
             AttributeValue::Address(Address::Symbol { symbol, addend: 0 }),
         );
-        // Using Udata for DW_AT_high_pc requires at least DWARF4
-        func_entry.set(gimli::DW_AT_high_pc, AttributeValue::Udata(u64::from(end)));
-
-        // FIXME make it more reliable and implement scopes before re-enabling this.
-        if false {
-            let value_labels_ranges = context.build_value_labels_ranges(isa).unwrap();
-
-            for (local, _local_decl) in mir.local_decls.iter_enumerated() {
-                let ty = self.tcx.subst_and_normalize_erasing_regions(
-                    instance.substs,
-                    ty::ParamEnv::reveal_all(),
-                    mir.local_decls[local].ty,
-                );
-                let var_id = self.define_local(entry_id, format!("{:?}", local), ty);
-
-                let location = place_location(
-                    self,
-                    isa,
-                    symbol,
-                    context,
-                    &local_map,
-                    &value_labels_ranges,
-                    Place { local, projection: ty::List::empty() },
-                );
+        func_entry.set(gimli::DW_AT_high_pc, high_pc);
 
-                let var_entry = self.dwarf.unit.get_mut(var_id);
-                var_entry.set(gimli::DW_AT_location, location);
-            }
+        if self.line_tables_only {
+            return;
         }
 
-        // FIXME create locals for all entries in mir.var_debug_info
+        let frame_base = FrameBase::new(self.tcx, isa);
+        let func_entry = self.dwarf.unit.get_mut(entry_id);
+        func_entry.set(gimli::DW_AT_frame_base, AttributeValue::Exprloc(frame_base.expression()));
+
+        let value_labels_ranges = context.build_value_labels_ranges(isa).unwrap();
+
+        // FIXME add the variables to their lexical scope instead of the function
+        for var_debug_info in &mir.var_debug_info {
+            let place = match var_debug_info.value {
+                VarDebugInfoContents::Place(place) if place.projection.is_empty() => place,
+                // FIXME support projections and constants
+                _ => continue,
+            };
+
+            let ty = self.tcx.subst_and_normalize_erasing_regions(
+                instance.substs,
+                ty::ParamEnv::reveal_all(),
+                mir.local_decls[place.local].ty,
+            );
+            let is_arg = place.local != RETURN_PLACE && place.local.as_usize() <= mir.arg_count;
+            let var_id = self.define_local(entry_id, var_debug_info.name.to_string(), ty, is_arg);
+
+            let location = place_location(
+                self,
+                isa,
+                frame_base,
+                symbol,
+                context,
+                &local_map,
+                &value_labels_ranges,
+                place,
+            );
+
+            let var_entry = self.dwarf.unit.get_mut(var_id);
+            var_entry.set(gimli::DW_AT_location, location);
+        }
     }
 }
 
 fn place_location<'tcx>(
     debug_context: &mut DebugContext<'tcx>,
     isa: &dyn TargetIsa,
+    frame_base: FrameBase,
     symbol: usize,
     context: &Context,
     local_map: &IndexVec<mir::Local, CPlace<'tcx>>,
@@ -305,12 +612,18 @@ fn place_location<'tcx>(
                                 addend: i64::from(value_loc_range.start),
                             },
                             end: Address::Symbol { symbol, addend: i64::from(value_loc_range.end) },
-                            data: translate_loc(
-                                isa,
-                                value_loc_range.loc,
-                                &context.func.stack_slots,
-                            )
-                            .unwrap(),
+                            data: {
+                                let mut expr = Expression::new();
+                                let known = translate_loc(
+                                    &mut expr,
+                                    isa,
+                                    frame_base,
+                                    value_loc_range.loc,
+                                    &context.func.stack_slots,
+                                );
+                                assert!(known);
+                                expr
+                            },
                         })
                         .collect(),
                 );
@@ -323,60 +636,201 @@ fn place_location<'tcx>(
                 AttributeValue::Exprloc(Expression::new())
             }
         }
-        CPlaceInner::VarPair(_, _, _) => {
-            // FIXME implement this
+        CPlaceInner::VarPair(_local, var1, var2) => {
+            let tcx = debug_context.tcx;
+            let (a_scalar, b_scalar) = match &local_map[place.local].layout().abi {
+                Abi::ScalarPair(a, b) => (a, b),
+                abi => unreachable!("{:?}", abi),
+            };
+            let a_size = a_scalar.value.size(&tcx);
+            let b_offset = a_size.align_to(b_scalar.value.align(&tcx).abi);
+            let b_size = b_scalar.value.size(&tcx);
+
+            let empty = vec![];
+            let ranges1 = value_labels_ranges.get(&ValueLabel::new(var1.index())).unwrap_or(&empty);
+            let ranges2 = value_labels_ranges.get(&ValueLabel::new(var2.index())).unwrap_or(&empty);
+
+            // Split the function into parts in which the location of both halves stays the same.
+            let mut boundaries = vec![];
+            for range in ranges1.iter().chain(ranges2) {
+                boundaries.push(range.start);
+                boundaries.push(range.end);
+            }
+            boundaries.sort_unstable();
+            boundaries.dedup();
+
+            // Describe a single half using a `DW_OP_piece`. A piece without location means that
+            // this half is unavailable.
+            let piece = |expr: &mut Expression, ranges: &[ValueLocRange], start: u32, size: u64| {
+                let range = ranges.iter().find(|range| range.start <= start && start < range.end);
+                let known = match range {
+                    Some(range) => {
+                        translate_loc(expr, isa, frame_base, range.loc, &context.func.stack_slots)
+                    }
+                    None => false,
+                };
+                expr.op_piece(size);
+                known
+            };
+
+            let mut loc_list = LocationList(vec![]);
+            for window in boundaries.windows(2) {
+                let (start, end) = (window[0], window[1]);
+                let mut expr = Expression::new();
+                let has_a = piece(&mut expr, ranges1, start, a_size.bytes());
+                if b_offset > a_size {
+                    expr.op_piece((b_offset - a_size).bytes());
+                }
+                let has_b = piece(&mut expr, ranges2, start, b_size.bytes());
+                if has_a || has_b {
+                    loc_list.0.push(Location::StartEnd {
+                        begin: Address::Symbol { symbol, addend: i64::from(start) },
+                        end: Address::Symbol { symbol, addend: i64::from(end) },
+                        data: expr,
+                    });
+                }
+            }
 
-            AttributeValue::Exprloc(Expression::new())
+            if loc_list.0.is_empty() {
+                AttributeValue::Exprloc(Expression::new())
+            } else {
+                AttributeValue::LocationListRef(debug_context.dwarf.unit.locations.add(loc_list))
+            }
         }
         CPlaceInner::VarLane(_, _, _) => {
             // FIXME implement this
 
             AttributeValue::Exprloc(Expression::new())
         }
-        CPlaceInner::Addr(_, _) => {
-            // FIXME implement this (used by arguments and returns)
+        CPlaceInner::Addr(ptr, None) => match ptr.debug_base_and_offset() {
+            (PointerBase::Stack(stack_slot), offset) => {
+                // The new backends address stack slots relative to the nominal stack pointer.
+                let mcr = context.mach_compile_result.as_ref().unwrap();
+                match stack_pointer(isa) {
+                    Some(sp) => {
+                        let mut expr = Expression::new();
+                        let ss_offset = i64::from(mcr.stackslot_offsets[stack_slot]);
+                        expr.op_breg(sp, ss_offset + i64::from(offset));
+                        AttributeValue::Exprloc(expr)
+                    }
+                    None => AttributeValue::Exprloc(Expression::new()),
+                }
+            }
+            // FIXME track the location of the pointer for places behind a pointer (used by
+            // indirectly passed arguments)
+            (PointerBase::Addr(_), _) | (PointerBase::Dangling(_), _) => {
+                AttributeValue::Exprloc(Expression::new())
+            }
+        },
+        CPlaceInner::Addr(_, Some(_)) => {
+            // FIXME implement this for unsized locals
 
             AttributeValue::Exprloc(Expression::new())
+        }
+    }
+}
+
+/// The location `DW_AT_frame_base` points to. Stack locations of variables are described relative
+/// to it using `DW_OP_fbreg`.
+#[derive(Copy, Clone)]
+enum FrameBase {
+    /// The frame pointer. When frame pointers are forced on, this is used instead of the CFA, as
+    /// it stays correct even in code that adjusts the stack pointer.
+    FramePointer {
+        reg: gimli::Register,
+        /// The offset of the CFA from the location the frame pointer points to.
+        cfa_offset: i64,
+    },
+    /// The CFA as described by the unwind info.
+    Cfa,
+}
+
+impl FrameBase {
+    /// The frame base of a function compiled for `isa`. Like the LLVM backend, this only relies on
+    /// the frame pointer when `-Cforce-frame-pointers` is enabled for the function.
+    fn new(tcx: TyCtxt<'_>, isa: &dyn TargetIsa) -> Self {
+        if tcx.sess.must_not_eliminate_frame_pointers() {
+            // Cranelift always sets up a frame pointer on these architectures. The saved frame
+            // pointer and the return address are stored between the CFA and the location it points
+            // to.
+            let reg = match isa.triple().architecture {
+                target_lexicon::Architecture::X86_64 => Some(X86_64::RBP),
+                target_lexicon::Architecture::Aarch64(_) => Some(AArch64::X29),
+                _ => None,
+            };
+            if let Some(reg) = reg {
+                let cfa_offset = 2 * i64::from(isa.pointer_bytes());
+                return FrameBase::FramePointer { reg, cfa_offset };
+            }
+        }
+        FrameBase::Cfa
+    }
+
+    fn expression(self) -> Expression {
+        let mut expr = Expression::new();
+        match self {
+            FrameBase::FramePointer { reg, cfa_offset: _ } => expr.op_reg(reg),
+            FrameBase::Cfa => expr.op(gimli::DW_OP_call_frame_cfa),
+        }
+        expr
+    }
 
-            // For PointerBase::Stack:
-            //AttributeValue::Exprloc(translate_loc(ValueLoc::Stack(*stack_slot), &context.func.stack_slots).unwrap())
+    /// The offset of the CFA from the frame base.
+    fn cfa_offset(self) -> i64 {
+        match self {
+            FrameBase::FramePointer { reg: _, cfa_offset } => cfa_offset,
+            FrameBase::Cfa => 0,
         }
     }
 }
 
+/// The DWARF register number of the stack pointer.
+fn stack_pointer(isa: &dyn TargetIsa) -> Option<gimli::Register> {
+    match isa.triple().architecture {
+        target_lexicon::Architecture::X86_64 => Some(X86_64::RSP),
+        target_lexicon::Architecture::Aarch64(_) => Some(AArch64::SP),
+        _ => None,
+    }
+}
+
 // Adapted from https://github.com/CraneStation/wasmtime/blob/5a1845b4caf7a5dba8eda1fef05213a532ed4259/crates/debug/src/transform/expression.rs#L59-L137
+/// Append the location `loc` to `expr`. Returns `false` if the location isn't known.
 fn translate_loc(
+    expr: &mut Expression,
     isa: &dyn TargetIsa,
+    frame_base: FrameBase,
     loc: LabelValueLoc,
     stack_slots: &StackSlots,
-) -> Option<Expression> {
+) -> bool {
     match loc {
         LabelValueLoc::ValueLoc(ValueLoc::Reg(reg)) => {
             let machine_reg = isa.map_dwarf_register(reg).unwrap();
-            let mut expr = Expression::new();
             expr.op_reg(gimli::Register(machine_reg));
-            Some(expr)
+            true
         }
         LabelValueLoc::ValueLoc(ValueLoc::Stack(ss)) => {
+            // Stack slot offsets are relative to the CFA.
             if let Some(ss_offset) = stack_slots[ss].offset {
-                let mut expr = Expression::new();
-                expr.op_breg(X86_64::RBP, i64::from(ss_offset) + 16);
-                Some(expr)
+                expr.op_fbreg(i64::from(ss_offset) + frame_base.cfa_offset());
+                true
             } else {
-                None
+                false
             }
         }
         LabelValueLoc::ValueLoc(ValueLoc::Unassigned) => unreachable!(),
         LabelValueLoc::Reg(reg) => {
             let machine_reg = isa.map_regalloc_reg_to_dwarf(reg).unwrap();
-            let mut expr = Expression::new();
             expr.op_reg(gimli::Register(machine_reg));
-            Some(expr)
-        }
-        LabelValueLoc::SPOffset(offset) => {
-            let mut expr = Expression::new();
-            expr.op_breg(X86_64::RSP, offset);
-            Some(expr)
+            true
         }
+        // FIXME describe these relative to the frame base too. This requires the distance between
+        // the nominal stack pointer and the CFA, which Cranelift doesn't expose yet.
+        LabelValueLoc::SPOffset(offset) => match stack_pointer(isa) {
+            Some(sp) => {
+                expr.op_breg(sp, offset);
+                true
+            }
+            None => false,
+        },
     }
 }
@@ -0,0 +1,320 @@
+//! Support for profiling JITed code with `perf` using a [perf map] and the [jitdump format].
+//!
+//! [perf map]: https://github.com/torvalds/linux/blob/master/tools/perf/Documentation/jit-interface.txt
+//! [jitdump format]: https://github.com/torvalds/linux/blob/master/tools/perf/Documentation/jitdump-specification.txt
+
+use crate::prelude::*;
+
+use cranelift_codegen::machinst::MachSrcLoc;
+
+/// A function which has to be written to the perf map and jitdump file once it is finalized.
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) struct PerfMapEntry {
+    func_id: FuncId,
+    code_size: u32,
+    /// The code offset, file name and line of every row of the line table of the function.
+    lines: Vec<(u32, String, u32)>,
+}
+
+impl PerfMapEntry {
+    pub(crate) fn new(
+        tcx: TyCtxt<'_>,
+        func_id: FuncId,
+        context: &Context,
+        function_span: Span,
+        source_info_set: &indexmap::IndexSet<SourceInfo>,
+    ) -> Self {
+        let mcr = context.mach_compile_result.as_ref().unwrap();
+
+        let mut lines: Vec<(u32, String, u32)> = vec![];
+        for &MachSrcLoc { start, loc, .. } in mcr.buffer.get_srclocs_sorted() {
+            let span = if !loc.is_default() {
+                source_info_set.get_index(loc.bits() as usize).unwrap().span
+            } else {
+                function_span
+            };
+            let span = super::line_info::debugger_span(tcx, span, function_span);
+            let file = tcx.sess.source_map().lookup_source_file(span.lo());
+            let line = match file.lookup_file_pos(span.lo()) {
+                (0, _) => continue,
+                (line, _) => line as u32,
+            };
+            let file_name = file.name.prefer_remapped().to_string();
+            if let Some((_, last_file_name, last_line)) = lines.last() {
+                if *last_line == line && *last_file_name == file_name {
+                    continue;
+                }
+            }
+            lines.push((start, file_name, line));
+        }
+
+        PerfMapEntry { func_id, code_size: mcr.buffer.total_size(), lines }
+    }
+}
+
+#[cfg(feature = "jit")]
+pub(crate) use jit::write_perf_map;
+
+#[cfg(feature = "jit")]
+mod jit {
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::lazy::SyncOnceCell;
+    use std::sync::Mutex;
+
+    use rustc_session::Session;
+
+    use cranelift_jit::JITModule;
+
+    use super::PerfMapEntry;
+
+    const JITDUMP_MAGIC: u32 = 0x4A695444;
+    const JITDUMP_VERSION: u32 = 1;
+    const JIT_CODE_LOAD: u32 = 0;
+    const JIT_CODE_DEBUG_INFO: u32 = 2;
+
+    /// The perf map and jitdump file of the current process. `None` if they couldn't be created.
+    static PERF_FILES: SyncOnceCell<Mutex<Option<PerfFiles>>> = SyncOnceCell::new();
+
+    struct PerfFiles {
+        perf_map: File,
+        jitdump: Option<File>,
+        /// The number of functions written to the jitdump file so far.
+        code_index: u64,
+    }
+
+    /// Append the given finalized functions to the perf map and jitdump file of the current
+    /// process. Errors are reported as warnings, as they don't affect the execution of the program.
+    pub(crate) fn write_perf_map(
+        sess: &Session,
+        jit_module: &JITModule,
+        entries: Vec<PerfMapEntry>,
+    ) {
+        let mut perf_files = PERF_FILES
+            .get_or_init(|| Mutex::new(PerfFiles::create(sess, jit_module)))
+            .lock()
+            .unwrap();
+        let perf_files = match &mut *perf_files {
+            Some(perf_files) => perf_files,
+            None => return,
+        };
+
+        for entry in entries {
+            let ptr = jit_module.get_finalized_function(entry.func_id);
+            let name = &jit_module.declarations().get_function_decl(entry.func_id).name;
+
+            if let Err(err) =
+                writeln!(perf_files.perf_map, "{:x} {:x} {}", ptr as usize, entry.code_size, name)
+            {
+                sess.warn(&format!("failed to write perf map: {}", err));
+                return;
+            }
+
+            if let Some(jitdump) = &mut perf_files.jitdump {
+                // The finalized code is never modified or deallocated.
+                let code = unsafe { std::slice::from_raw_parts(ptr, entry.code_size as usize) };
+                let code_index = perf_files.code_index;
+                let res = write_debug_info(jitdump, code, &entry.lines)
+                    .and_then(|()| write_code_load(jitdump, code_index, code, name));
+                if let Err(err) = res {
+                    sess.warn(&format!("failed to write jitdump file: {}", err));
+                    perf_files.jitdump = None;
+                }
+                perf_files.code_index += 1;
+            }
+        }
+    }
+
+    impl PerfFiles {
+        fn create(sess: &Session, jit_module: &JITModule) -> Option<PerfFiles> {
+            let pid = std::process::id();
+
+            // perf only looks for the perf map in /tmp, even when `TMPDIR` is set.
+            let perf_map_path = std::path::PathBuf::from(format!("/tmp/perf-{}.map", pid));
+            let perf_map = match File::create(&perf_map_path) {
+                Ok(perf_map) => perf_map,
+                Err(err) => {
+                    sess.warn(&format!(
+                        "failed to create perf map `{}`: {}",
+                        perf_map_path.display(),
+                        err
+                    ));
+                    return None;
+                }
+            };
+
+            // The jitdump file is found through its mapping, so it can be anywhere.
+            let jitdump_path = std::env::temp_dir().join(format!("jit-{}.dump", pid));
+            let jitdump = match create_jitdump(&jitdump_path, jit_module) {
+                Ok(jitdump) => Some(jitdump),
+                Err(err) => {
+                    sess.warn(&format!(
+                        "failed to create jitdump file `{}`: {}",
+                        jitdump_path.display(),
+                        err
+                    ));
+                    None
+                }
+            };
+
+            Some(PerfFiles { perf_map, jitdump, code_index: 0 })
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn create_jitdump(path: &std::path::Path, jit_module: &JITModule) -> io::Result<File> {
+        use std::os::raw::{c_int, c_long, c_void};
+        use std::os::unix::io::AsRawFd;
+
+        use target_lexicon::Architecture;
+
+        extern "C" {
+            fn mmap(
+                addr: *mut c_void,
+                len: usize,
+                prot: c_int,
+                flags: c_int,
+                fd: c_int,
+                offset: c_long,
+            ) -> *mut c_void;
+        }
+        const PROT_READ: c_int = 1;
+        const PROT_EXEC: c_int = 4;
+        const MAP_PRIVATE: c_int = 2;
+
+        let elf_mach: u32 = match jit_module.isa().triple().architecture {
+            Architecture::X86_32(_) => 3,    // EM_386
+            Architecture::S390x => 22,       // EM_S390
+            Architecture::X86_64 => 62,      // EM_X86_64
+            Architecture::Aarch64(_) => 183, // EM_AARCH64
+            Architecture::Riscv64(_) => 243, // EM_RISCV
+            _ => 0,                          // EM_NONE
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let mut header = Vec::with_capacity(40);
+        header.extend_from_slice(&JITDUMP_MAGIC.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_VERSION.to_ne_bytes());
+        header.extend_from_slice(&40u32.to_ne_bytes()); // total_size
+        header.extend_from_slice(&elf_mach.to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes()); // pad1
+        header.extend_from_slice(&std::process::id().to_ne_bytes());
+        header.extend_from_slice(&timestamp().to_ne_bytes());
+        header.extend_from_slice(&0u64.to_ne_bytes()); // flags
+        file.write_all(&header)?;
+
+        // `perf record` finds the jitdump file through the executable mapping of it. The mapping is
+        // never removed, as `perf inject` needs it to still exist when samples are recorded.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                4096,
+                PROT_READ | PROT_EXEC,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(file)
+    }
+
+    // FIXME support other unixes. The jitdump format is only supported by perf, which is linux
+    // only.
+    #[cfg(not(target_os = "linux"))]
+    fn create_jitdump(_path: &std::path::Path, _jit_module: &JITModule) -> io::Result<File> {
+        Err(io::Error::new(io::ErrorKind::Other, "jitdump is only supported on Linux"))
+    }
+
+    /// The time in the clock used by `perf record -k 1`.
+    #[cfg(target_os = "linux")]
+    fn timestamp() -> u64 {
+        use std::os::raw::{c_int, c_long};
+
+        #[repr(C)]
+        struct Timespec {
+            tv_sec: c_long,
+            tv_nsec: c_long,
+        }
+
+        extern "C" {
+            fn clock_gettime(clock_id: c_int, tp: *mut Timespec) -> c_int;
+        }
+        const CLOCK_MONOTONIC: c_int = 1;
+
+        let mut ts = Timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe {
+            clock_gettime(CLOCK_MONOTONIC, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn timestamp() -> u64 {
+        0
+    }
+
+    fn write_record_header(file: &mut File, id: u32, total_size: usize) -> io::Result<()> {
+        file.write_all(&id.to_ne_bytes())?;
+        file.write_all(&(total_size as u32).to_ne_bytes())?;
+        file.write_all(&timestamp().to_ne_bytes())
+    }
+
+    /// Write a `JIT_CODE_DEBUG_INFO` record. It applies to the next `JIT_CODE_LOAD` record.
+    fn write_debug_info(
+        file: &mut File,
+        code: &[u8],
+        lines: &[(u32, String, u32)],
+    ) -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut record = vec![];
+        record.extend_from_slice(&(code.as_ptr() as u64).to_ne_bytes());
+        record.extend_from_slice(&(lines.len() as u64).to_ne_bytes());
+        for (offset, file_name, line) in lines {
+            record.extend_from_slice(&(code.as_ptr() as u64 + u64::from(*offset)).to_ne_bytes());
+            record.extend_from_slice(&line.to_ne_bytes());
+            record.extend_from_slice(&0u32.to_ne_bytes()); // discrim
+            record.extend_from_slice(file_name.as_bytes());
+            record.push(0);
+        }
+
+        write_record_header(file, JIT_CODE_DEBUG_INFO, 16 + record.len())?;
+        file.write_all(&record)
+    }
+
+    /// Write a `JIT_CODE_LOAD` record containing the name and machine code of a function.
+    fn write_code_load(
+        file: &mut File,
+        code_index: u64,
+        code: &[u8],
+        name: &str,
+    ) -> io::Result<()> {
+        let pid = std::process::id();
+
+        let mut record = vec![];
+        record.extend_from_slice(&pid.to_ne_bytes());
+        record.extend_from_slice(&pid.to_ne_bytes()); // tid
+        record.extend_from_slice(&(code.as_ptr() as u64).to_ne_bytes()); // vma
+        record.extend_from_slice(&(code.as_ptr() as u64).to_ne_bytes()); // code_addr
+        record.extend_from_slice(&(code.len() as u64).to_ne_bytes());
+        record.extend_from_slice(&code_index.to_ne_bytes());
+        record.extend_from_slice(name.as_bytes());
+        record.push(0);
+        record.extend_from_slice(code);
+
+        write_record_header(file, JIT_CODE_LOAD, 16 + record.len())?;
+        file.write_all(&record)
+    }
+}
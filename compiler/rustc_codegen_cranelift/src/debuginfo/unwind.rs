@@ -1,18 +1,32 @@
-//! Unwind info generation (`.eh_frame`)
+//! Unwind info generation (`.eh_frame` and `.pdata`/`.xdata`)
 
 use crate::prelude::*;
 
+use cranelift_codegen::binemit::{NullStackMapSink, NullTrapSink};
 use cranelift_codegen::isa::{unwind::UnwindInfo, TargetIsa};
+use cranelift_module::FuncOrDataId;
 
 use gimli::write::{Address, CieId, EhFrame, FrameTable, Section};
 use gimli::RunTimeEndian;
 
 use crate::backend::WriteDebugInfo;
 
+use super::DebugSymbol;
+
 pub(crate) struct UnwindContext {
     endian: RunTimeEndian,
     frame_table: FrameTable,
     cie_id: Option<CieId>,
+    /// The CIE used by functions in which every unwind has to abort. It is created on first use,
+    /// as it references the personality function defined by [`define_abort_personality`].
+    abort_on_unwind_cie_id: Option<CieId>,
+    pic_eh_frame: bool,
+    emit_seh: bool,
+    /// The functions referenced by `Address::Symbol` in the frame table, indexed by its `symbol`.
+    symbols: Vec<DebugSymbol>,
+    /// The function, its code size and its encoded `UNWIND_INFO` for every function with Windows
+    /// x64 unwind info.
+    seh_functions: Vec<(FuncId, u32, Vec<u8>)>,
 }
 
 impl UnwindContext {
@@ -30,10 +44,58 @@ pub(crate) fn new(tcx: TyCtxt<'_>, isa: &dyn TargetIsa, pic_eh_frame: bool) -> S
             None
         };
 
-        UnwindContext { endian, frame_table, cie_id }
+        UnwindContext {
+            endian,
+            frame_table,
+            cie_id,
+            abort_on_unwind_cie_id: None,
+            pic_eh_frame,
+            emit_seh: tcx.sess.target.is_like_windows,
+            symbols: vec![],
+            seh_functions: vec![],
+        }
     }
 
     pub(crate) fn add_function(&mut self, func_id: FuncId, context: &Context, isa: &dyn TargetIsa) {
+        self.add_function_with_cie(func_id, context, isa, self.cie_id);
+    }
+
+    /// Add the unwind info of a function out of which no unwind may escape. RFC 2945 requires
+    /// this for `extern "C-unwind"` calls when compiling with `-Cpanic=abort`. Cranelift doesn't
+    /// support landing pads yet, so instead the personality of the whole function stops every
+    /// unwind before it reaches the frame, after which the unwinder aborts.
+    pub(crate) fn add_function_abort_on_unwind(
+        &mut self,
+        module: &mut dyn Module,
+        func_id: FuncId,
+        context: &Context,
+    ) {
+        if self.abort_on_unwind_cie_id.is_none() {
+            if let Some(mut cie) = module.isa().create_systemv_cie() {
+                let personality = define_abort_personality(module);
+                self.symbols.push(DebugSymbol::Func(personality));
+                let symbol = self.symbols.len() - 1;
+                if self.pic_eh_frame {
+                    cie.fde_address_encoding =
+                        gimli::DwEhPe(gimli::DW_EH_PE_pcrel.0 | gimli::DW_EH_PE_sdata4.0);
+                }
+                cie.personality =
+                    Some((cie.fde_address_encoding, Address::Symbol { symbol, addend: 0 }));
+                self.abort_on_unwind_cie_id = Some(self.frame_table.add_cie(cie));
+            }
+        }
+
+        // FIXME abort on unwind for functions with Windows x64 unwind info too
+        self.add_function_with_cie(func_id, context, module.isa(), self.abort_on_unwind_cie_id);
+    }
+
+    fn add_function_with_cie(
+        &mut self,
+        func_id: FuncId,
+        context: &Context,
+        isa: &dyn TargetIsa,
+        cie_id: Option<CieId>,
+    ) {
         let unwind_info = if let Some(unwind_info) = context.create_unwind_info(isa).unwrap() {
             unwind_info
         } else {
@@ -42,21 +104,32 @@ pub(crate) fn add_function(&mut self, func_id: FuncId, context: &Context, isa: &
 
         match unwind_info {
             UnwindInfo::SystemV(unwind_info) => {
+                self.symbols.push(DebugSymbol::Func(func_id));
+                let symbol = self.symbols.len() - 1;
                 self.frame_table.add_fde(
-                    self.cie_id.unwrap(),
-                    unwind_info
-                        .to_fde(Address::Symbol { symbol: func_id.as_u32() as usize, addend: 0 }),
+                    cie_id.unwrap(),
+                    unwind_info.to_fde(Address::Symbol { symbol, addend: 0 }),
                 );
             }
-            UnwindInfo::WindowsX64(_) => {
-                // FIXME implement this
+            UnwindInfo::WindowsX64(unwind_info) => {
+                // Cranelift picks the unwind info kind based on the calling convention rather than
+                // the target, so this is also reached for `extern "win64"` functions on non-Windows
+                // targets. Those are left without unwind info for now.
+                if !self.emit_seh {
+                    return;
+                }
+                let code_size = context.mach_compile_result.as_ref().unwrap().buffer.total_size();
+                let mut data = vec![0; unwind_info.emit_size()];
+                unwind_info.emit(&mut data);
+                self.seh_functions.push((func_id, code_size, data));
             }
             unwind_info => unimplemented!("{:?}", unwind_info),
         }
     }
 
     pub(crate) fn emit<P: WriteDebugInfo>(self, product: &mut P) {
-        let mut eh_frame = EhFrame::from(super::emit::WriterRelocate::new(self.endian));
+        let mut eh_frame =
+            EhFrame::from(super::emit::WriterRelocate::new(self.endian, self.symbols.into()));
         self.frame_table.write_eh_frame(&mut eh_frame).unwrap();
 
         if !eh_frame.0.writer.slice().is_empty() {
@@ -69,14 +142,23 @@ pub(crate) fn emit<P: WriteDebugInfo>(self, product: &mut P) {
                 product.add_debug_reloc(&section_map, &section_id, reloc);
             }
         }
+
+        if !self.seh_functions.is_empty() {
+            product.add_seh_unwind_info(&self.seh_functions);
+        }
     }
 
+    // FIXME register the SEH unwind info using `RtlAddFunctionTable`
     #[cfg(all(feature = "jit", windows))]
     pub(crate) unsafe fn register_jit(self, _jit_module: &cranelift_jit::JITModule) {}
 
+    /// Register the `.eh_frame` of the jitted functions with the unwinder of the host. This allows
+    /// backtraces and panics to unwind through jitted frames. Actually catching a panic in jitted
+    /// code additionally requires landing pads, which aren't emitted yet. See the `try` intrinsic.
     #[cfg(all(feature = "jit", not(windows)))]
     pub(crate) unsafe fn register_jit(self, jit_module: &cranelift_jit::JITModule) {
-        let mut eh_frame = EhFrame::from(super::emit::WriterRelocate::new(self.endian));
+        let mut eh_frame =
+            EhFrame::from(super::emit::WriterRelocate::new(self.endian, self.symbols.into()));
         self.frame_table.write_eh_frame(&mut eh_frame).unwrap();
 
         if eh_frame.0.writer.slice().is_empty() {
@@ -124,6 +206,52 @@ pub(crate) unsafe fn register_jit(self, jit_module: &cranelift_jit::JITModule) {
     }
 }
 
+/// Define a personality function which returns `_URC_FATAL_PHASE1_ERROR` for every exception. This
+/// makes `_Unwind_RaiseException` fail without running any landing pad, after which the Rust
+/// runtime aborts and C++ calls `std::terminate`.
+fn define_abort_personality(module: &mut dyn Module) -> FuncId {
+    const NAME: &str = "__cg_clif_abort_on_unwind_personality";
+    const URC_FATAL_PHASE1_ERROR: i64 = 3;
+
+    // Lazy JIT mode uses a new `UnwindContext` for every function, but they share a single module.
+    if let Some(FuncOrDataId::Func(func_id)) = module.get_name(NAME) {
+        return func_id;
+    }
+
+    let ptr_ty = module.target_config().pointer_type();
+    let sig = Signature {
+        call_conv: module.target_config().default_call_conv,
+        params: vec![
+            AbiParam::new(types::I32), // version
+            AbiParam::new(types::I32), // actions
+            AbiParam::new(types::I64), // exception class
+            AbiParam::new(ptr_ty),     // exception object
+            AbiParam::new(ptr_ty),     // unwind context
+        ],
+        returns: vec![AbiParam::new(types::I32)],
+    };
+    let func_id = module.declare_function(NAME, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+    {
+        let mut func_ctx = FunctionBuilderContext::new();
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let ret = bcx.ins().iconst(types::I32, URC_FATAL_PHASE1_ERROR);
+        bcx.ins().return_(&[ret]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module
+        .define_function(func_id, &mut ctx, &mut NullTrapSink {}, &mut NullStackMapSink {})
+        .unwrap();
+    func_id
+}
+
 extern "C" {
     // libunwind import
     fn __register_frame(fde: *const u8);
@@ -1,7 +1,7 @@
 //! The AOT driver uses [`cranelift_object`] to write object files suitable for linking into a
 //! standalone executable.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use rustc_ast::{InlineAsmOptions, InlineAsmTemplatePiece};
 use rustc_codegen_ssa::back::linker::LinkerInfo;
@@ -12,6 +12,7 @@
 use rustc_middle::mir::mono::{CodegenUnit, MonoItem};
 use rustc_session::cgu_reuse_tracker::CguReuse;
 use rustc_session::config::{DebugInfo, OutputType};
+use rustc_target::spec::SplitDebuginfo;
 
 use cranelift_object::ObjectModule;
 
@@ -27,13 +28,12 @@ fn hash_stable(&self, _: &mut HCX, _: &mut StableHasher) {
 
 fn emit_module(
     tcx: TyCtxt<'_>,
-    backend_config: &BackendConfig,
     name: String,
     kind: ModuleKind,
     module: ObjectModule,
     debug: Option<DebugContext<'_>>,
     unwind_context: UnwindContext,
-) -> ModuleCodegenResult {
+) -> CompiledModule {
     let mut product = module.finish();
 
     if let Some(mut debug) = debug {
@@ -48,19 +48,25 @@ fn emit_module(
         tcx.sess.fatal(&format!("error writing object file: {}", err));
     }
 
-    let work_product = if backend_config.disable_incr_cache {
-        None
-    } else {
-        rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
-            tcx.sess,
-            &name,
-            &Some(tmp_file.clone()),
-        )
-    };
+    CompiledModule { name, kind, object: Some(tmp_file), dwarf_object: None, bytecode: None }
+}
 
-    ModuleCodegenResult(
-        CompiledModule { name, kind, object: Some(tmp_file), dwarf_object: None, bytecode: None },
-        work_product,
+/// Copy the object file of the module into the incremental cache. This has to happen after the
+/// object file is complete, including the assembled `global_asm!`, as a later session reuses it
+/// as is when the CGU is unchanged.
+fn save_work_product(
+    tcx: TyCtxt<'_>,
+    backend_config: &BackendConfig,
+    module: &CompiledModule,
+) -> Option<(WorkProductId, WorkProduct)> {
+    if backend_config.disable_incr_cache {
+        return None;
+    }
+
+    rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
+        tcx.sess,
+        &module.name,
+        &module.object,
     )
 }
 
@@ -123,7 +129,9 @@ fn module_codegen(
                     .sess
                     .time("codegen fn", || crate::base::codegen_fn(&mut cx, &mut module, inst));
             }
-            MonoItem::Static(def_id) => crate::constant::codegen_static(tcx, &mut module, def_id),
+            MonoItem::Static(def_id) => {
+                crate::constant::codegen_static(&mut cx, &mut module, def_id)
+            }
             MonoItem::GlobalAsm(item_id) => {
                 let item = cx.tcx.hir().item(item_id);
                 if let rustc_hir::ItemKind::GlobalAsm(asm) = item.kind {
@@ -149,10 +157,9 @@ fn module_codegen(
 
     let debug_context = cx.debug_context;
     let unwind_context = cx.unwind_context;
-    let codegen_result = tcx.sess.time("write object file", || {
+    let compiled_module = tcx.sess.time("write object file", || {
         emit_module(
             tcx,
-            &backend_config,
             cgu.name().as_str().to_string(),
             ModuleKind::Regular,
             module,
@@ -163,7 +170,21 @@ fn module_codegen(
 
     codegen_global_asm(tcx, &cgu.name().as_str(), &cx.global_asm);
 
-    codegen_result
+    if let Some(mut asm_output) = cx.asm_output {
+        if !cx.global_asm.is_empty() {
+            asm_output.push_str("// global_asm!\n");
+            asm_output.push_str(&cx.global_asm);
+        }
+        let asm_file = tcx
+            .output_filenames(LOCAL_CRATE)
+            .temp_path(OutputType::Assembly, Some(&cgu.name().as_str()));
+        if let Err(err) = std::fs::write(&asm_file, asm_output) {
+            tcx.sess.fatal(&format!("error writing assembly file: {}", err));
+        }
+    }
+
+    let work_product = save_work_product(tcx, &backend_config, &compiled_module);
+    ModuleCodegenResult(compiled_module, work_product)
 }
 
 pub(crate) fn run_aot(
@@ -187,6 +208,23 @@ pub(crate) fn run_aot(
         subsystem.to_string()
     });
 
+    if tcx.sess.opts.debuginfo != DebugInfo::None && tcx.sess.target.is_like_msvc {
+        tcx.sess.warn(
+            "CodeView debuginfo is not yet supported by Cranelift; no debuginfo will be emitted",
+        );
+    }
+
+    if tcx.sess.opts.debuginfo != DebugInfo::None
+        && tcx.sess.target_can_use_split_dwarf()
+        && tcx.sess.split_debuginfo() != SplitDebuginfo::Off
+    {
+        // FIXME emit skeleton units and .dwo files once gimli supports writing split units
+        tcx.sess.warn(
+            "split DWARF is not yet supported by Cranelift; debuginfo is emitted into the object \
+            files instead",
+        );
+    }
+
     let mut work_products = FxHashMap::default();
 
     let cgus = if tcx.sess.opts.output_types.should_codegen() {
@@ -238,6 +276,10 @@ pub(crate) fn run_aot(
 
     tcx.sess.abort_if_errors();
 
+    if tcx.sess.opts.output_types.contains_key(&OutputType::Assembly) {
+        write_asm_output(tcx, cgus);
+    }
+
     let isa = crate::build_isa(tcx.sess, &backend_config);
     let mut allocator_module =
         crate::backend::make_module(tcx.sess, isa, "allocator_shim".to_string());
@@ -247,16 +289,15 @@ pub(crate) fn run_aot(
         crate::allocator::codegen(tcx, &mut allocator_module, &mut allocator_unwind_context);
 
     let allocator_module = if created_alloc_shim {
-        let ModuleCodegenResult(module, work_product) = emit_module(
+        let module = emit_module(
             tcx,
-            &backend_config,
             "allocator_shim".to_string(),
             ModuleKind::Allocator,
             allocator_module,
             None,
             allocator_unwind_context,
         );
-        if let Some((id, product)) = work_product {
+        if let Some((id, product)) = save_work_product(tcx, &backend_config, &module) {
             work_products.insert(id, product);
         }
         Some(module)
@@ -316,9 +357,29 @@ pub(crate) fn run_aot(
     ))
 }
 
+/// Combine the disassembly written for every CGU into the final `--emit asm` output. This is the
+/// machine code as shown by Cranelift, so it can't necessarily be assembled again.
+fn write_asm_output(tcx: TyCtxt<'_>, cgus: &[CodegenUnit<'_>]) {
+    let output_filenames = tcx.output_filenames(LOCAL_CRATE);
+    let mut asm_output = String::new();
+    for cgu in cgus {
+        let asm_file = output_filenames.temp_path(OutputType::Assembly, Some(&cgu.name().as_str()));
+        match std::fs::read_to_string(&asm_file) {
+            Ok(asm) => asm_output.push_str(&asm),
+            Err(err) => tcx.sess.fatal(&format!("error reading assembly file: {}", err)),
+        }
+        if !tcx.sess.opts.cg.save_temps {
+            std::fs::remove_file(&asm_file).unwrap();
+        }
+    }
+
+    if let Err(err) = std::fs::write(output_filenames.path(OutputType::Assembly), asm_output) {
+        tcx.sess.fatal(&format!("error writing assembly file: {}", err));
+    }
+}
+
 fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
-    use std::io::Write;
-    use std::process::{Command, Stdio};
+    use std::process::Command;
 
     if global_asm.is_empty() {
         return;
@@ -326,7 +387,7 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
 
     if cfg!(not(feature = "inline_asm"))
         || tcx.sess.target.is_like_osx
-        || tcx.sess.target.is_like_windows
+        || tcx.sess.target.is_like_msvc
     {
         if global_asm.contains("__rust_probestack") {
             return;
@@ -337,49 +398,44 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
             tcx.sess.fatal(
                 "asm! and global_asm! support is disabled while compiling rustc_codegen_cranelift",
             );
+        } else if tcx.sess.target.is_like_msvc {
+            // FIXME assemble into a COFF object without depending on the GNU assembler and linker
+            // of a MinGW toolchain.
+            tcx.sess.fatal(
+                "asm! and global_asm! are not yet supported on MSVC targets, as they require a GNU \
+                compatible assembler and linker",
+            );
         } else {
-            tcx.sess.fatal("asm! and global_asm! are not yet supported on macOS and Windows");
+            tcx.sess.fatal("asm! and global_asm! are not yet supported on macOS");
         }
     }
 
-    let assembler = crate::toolchain::get_toolchain_binary(tcx.sess, "as");
     let linker = crate::toolchain::get_toolchain_binary(tcx.sess, "ld");
 
-    // Remove all LLVM style comments
-    let global_asm = global_asm
-        .lines()
-        .map(|line| if let Some(index) = line.find("//") { &line[0..index] } else { line })
-        .collect::<Vec<_>>()
-        .join("\n");
-
     let output_object_file =
         tcx.output_filenames(LOCAL_CRATE).temp_path(OutputType::Object, Some(cgu_name));
 
     // Assemble `global_asm`
     let global_asm_object_file = add_file_stem_postfix(output_object_file.clone(), ".asm");
-    let mut child = Command::new(assembler)
-        .arg("-o")
-        .arg(&global_asm_object_file)
-        .stdin(Stdio::piped())
-        .spawn()
-        .expect("Failed to spawn `as`.");
-    child.stdin.take().unwrap().write_all(global_asm.as_bytes()).unwrap();
-    let status = child.wait().expect("Failed to wait for `as`.");
-    if !status.success() {
-        tcx.sess.fatal(&format!("Failed to assemble `{}`", global_asm));
-    }
+    assemble_global_asm(tcx, global_asm, &global_asm_object_file);
 
     // Link the global asm and main object file together
     let main_object_file = add_file_stem_postfix(output_object_file.clone(), ".main");
     std::fs::rename(&output_object_file, &main_object_file).unwrap();
-    let status = Command::new(linker)
+    let status = Command::new(&linker)
         .arg("-r") // Create a new object file
         .arg("-o")
         .arg(output_object_file)
         .arg(&main_object_file)
         .arg(&global_asm_object_file)
         .status()
-        .unwrap();
+        .unwrap_or_else(|err| {
+            tcx.sess.fatal(&format!(
+                "Failed to spawn the linker `{}` needed for asm! and global_asm!: {}",
+                linker.display(),
+                err,
+            ))
+        });
     if !status.success() {
         tcx.sess.fatal(&format!(
             "Failed to link `{}` and `{}` together",
@@ -392,6 +448,42 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
     std::fs::remove_file(main_object_file).unwrap();
 }
 
+/// Assemble the generated asm into an object file at `object_file` using the assembler of the
+/// toolchain.
+pub(super) fn assemble_global_asm(tcx: TyCtxt<'_>, global_asm: &str, object_file: &Path) {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let assembler = crate::toolchain::get_toolchain_binary(tcx.sess, "as");
+
+    // Remove all LLVM style comments
+    let global_asm = global_asm
+        .lines()
+        .map(|line| if let Some(index) = line.find("//") { &line[0..index] } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // FIXME assemble in-process once there is an assembler which can be used as library for all
+    // supported targets. Until then an assembler needs to be installed.
+    let mut child = Command::new(&assembler)
+        .arg("-o")
+        .arg(object_file)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| {
+            tcx.sess.fatal(&format!(
+                "Failed to spawn the assembler `{}` needed for asm! and global_asm!: {}",
+                assembler.display(),
+                err,
+            ))
+        });
+    child.stdin.take().unwrap().write_all(global_asm.as_bytes()).unwrap();
+    let status = child.wait().expect("Failed to wait for `as`.");
+    if !status.success() {
+        tcx.sess.fatal(&format!("Failed to assemble `{}`", global_asm));
+    }
+}
+
 fn add_file_stem_postfix(mut path: PathBuf, postfix: &str) -> PathBuf {
     let mut new_filename = path.file_stem().unwrap().to_owned();
     new_filename.push(postfix);
@@ -409,6 +501,11 @@ fn determine_cgu_reuse<'tcx>(tcx: TyCtxt<'tcx>, cgu: &CodegenUnit<'tcx>) -> CguR
         return CguReuse::No;
     }
 
+    // Only the object file is cached, not the assembly.
+    if tcx.sess.opts.output_types.contains_key(&OutputType::Assembly) {
+        return CguReuse::No;
+    }
+
     let work_product_id = &cgu.work_product_id();
     if tcx.dep_graph.previous_work_product(work_product_id).is_none() {
         // We don't have anything cached for this CGU. This can happen
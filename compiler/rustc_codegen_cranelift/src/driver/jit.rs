@@ -2,15 +2,21 @@
 //! files.
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::ffi::CString;
+use std::lazy::SyncOnceCell;
 use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
 
 use cranelift_codegen::binemit::{NullStackMapSink, NullTrapSink};
 use rustc_codegen_ssa::CrateInfo;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::mir::mono::MonoItem;
-use rustc_session::config::EntryFnType;
+use rustc_session::config::{DebugInfo, EntryFnType, OutputType};
 
 use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::FuncOrDataId;
 
 use crate::{prelude::*, BackendConfig};
 use crate::{CodegenCx, CodegenMode};
@@ -18,16 +24,27 @@
 struct JitState {
     backend_config: BackendConfig,
     jit_module: JITModule,
+    /// The functions compiled by [`__clif_jit_fn`] so far, indexed by symbol name.
+    lazy_compiled_functions: FxHashMap<String, *const u8>,
+    /// The functions to compile speculatively when `jit_speculative_compilation` is enabled. Like
+    /// for [`__clif_jit_fn`], the instances are allocated in the arena of the `TyCtxt` and have to
+    /// be lifted using `tcx.lift` before use.
+    speculative_queue: VecDeque<*const Instance<'static>>,
 }
 
 thread_local! {
     static LAZY_JIT_STATE: RefCell<Option<JitState>> = RefCell::new(None);
 }
 
+/// A callback used to resolve symbols referenced by jitted code, which are neither defined by the
+/// jitted crate nor by any of its dependencies. It is consulted before falling back to `dlsym`.
+pub type JitSymbolResolver = Box<dyn Fn(&str) -> Option<*const u8>>;
+
 fn create_jit_module<'tcx>(
     tcx: TyCtxt<'tcx>,
     backend_config: &BackendConfig,
     hotswap: bool,
+    symbol_resolver: Option<JitSymbolResolver>,
 ) -> (JITModule, CodegenCx<'tcx>) {
     let imported_symbols = load_imported_symbols_for_jit(tcx);
 
@@ -36,9 +53,17 @@ fn create_jit_module<'tcx>(
     jit_builder.hotswap(hotswap);
     crate::compiler_builtins::register_functions_for_jit(&mut jit_builder);
     jit_builder.symbols(imported_symbols);
+    if let Some(symbol_resolver) = symbol_resolver {
+        jit_builder.symbol_lookup_fn(symbol_resolver);
+    }
     let mut jit_module = JITModule::new(jit_builder);
 
-    let mut cx = crate::CodegenCx::new(tcx, backend_config.clone(), jit_module.isa(), false);
+    let mut cx = crate::CodegenCx::new(
+        tcx,
+        backend_config.clone(),
+        jit_module.isa(),
+        tcx.sess.opts.debuginfo != DebugInfo::None,
+    );
 
     crate::allocator::codegen(tcx, &mut jit_module, &mut cx.unwind_context);
     crate::main_shim::maybe_create_entry_wrapper(
@@ -51,7 +76,11 @@ fn create_jit_module<'tcx>(
     (jit_module, cx)
 }
 
-pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
+fn codegen_crate_for_jit(
+    tcx: TyCtxt<'_>,
+    backend_config: &BackendConfig,
+    symbol_resolver: Option<JitSymbolResolver>,
+) -> JITModule {
     if !tcx.sess.opts.output_types.should_codegen() {
         tcx.sess.fatal("JIT mode doesn't work with `cargo check`");
     }
@@ -62,8 +91,9 @@ pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
 
     let (mut jit_module, mut cx) = create_jit_module(
         tcx,
-        &backend_config,
+        backend_config,
         matches!(backend_config.codegen_mode, CodegenMode::JitLazy),
+        symbol_resolver,
     );
 
     let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
@@ -89,7 +119,7 @@ pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
                     CodegenMode::JitLazy => codegen_shim(&mut cx, &mut jit_module, inst),
                 },
                 MonoItem::Static(def_id) => {
-                    crate::constant::codegen_static(tcx, &mut jit_module, def_id);
+                    crate::constant::codegen_static(&mut cx, &mut jit_module, def_id);
                 }
                 MonoItem::GlobalAsm(item_id) => {
                     let item = tcx.hir().item(item_id);
@@ -99,28 +129,141 @@ pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
         }
     });
 
-    if !cx.global_asm.is_empty() {
-        tcx.sess.fatal("Inline asm is not supported in JIT mode");
-    }
+    define_inline_asm_for_jit(tcx, &mut jit_module, &cx.global_asm);
+    check_denied_symbols(tcx, &jit_module, backend_config);
 
     tcx.sess.abort_if_errors();
 
     jit_module.finalize_definitions();
     unsafe { cx.unwind_context.register_jit(&jit_module) };
+    if let Some(debug_context) = cx.debug_context {
+        unsafe { debug_context.register_jit(&jit_module) };
+    }
+    if let Some(perf_map_entries) = cx.perf_map_entries {
+        crate::debuginfo::write_perf_map(tcx.sess, &jit_module, perf_map_entries);
+    }
 
-    println!(
-        "Rustc codegen cranelift will JIT run the executable, because -Cllvm-args=mode=jit was passed"
-    );
+    jit_module
+}
 
-    let args = std::iter::once(&*tcx.crate_name(LOCAL_CRATE).as_str().to_string())
-        .chain(backend_config.jit_args.iter().map(|arg| &**arg))
-        .map(|arg| CString::new(arg).unwrap())
+/// A crate which has been JIT compiled using [`jit_crate`].
+pub struct JitCrate {
+    jit_module: JITModule,
+    exported_symbols: Vec<String>,
+}
+
+impl JitCrate {
+    /// Get a pointer to the JIT compiled function with the given symbol name, or `None` if there
+    /// is no such function.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type matching the ABI and signature of the function.
+    pub unsafe fn get_function<F: Copy>(&self, symbol_name: &str) -> Option<F> {
+        assert_eq!(std::mem::size_of::<F>(), std::mem::size_of::<*const u8>());
+        match self.jit_module.get_name(symbol_name) {
+            Some(FuncOrDataId::Func(func_id)) => {
+                let ptr = self.jit_module.get_finalized_function(func_id);
+                Some(std::mem::transmute_copy::<*const u8, F>(&ptr))
+            }
+            Some(FuncOrDataId::Data(_)) | None => None,
+        }
+    }
+
+    /// Get a pointer to the JIT compiled static with the given symbol name, or `None` if there is
+    /// no such static.
+    pub fn get_data(&self, symbol_name: &str) -> Option<*const u8> {
+        match self.jit_module.get_name(symbol_name) {
+            Some(FuncOrDataId::Data(data_id)) => {
+                Some(self.jit_module.get_finalized_data(data_id).0)
+            }
+            Some(FuncOrDataId::Func(_)) | None => None,
+        }
+    }
+
+    /// The symbol names of all `#[no_mangle]` and `#[export_name]` functions and statics of the
+    /// crate. These can be looked up using [`get_function`](Self::get_function) and
+    /// [`get_data`](Self::get_data) to pass them to code in the host process.
+    pub fn exported_symbols(&self) -> &[String] {
+        &self.exported_symbols
+    }
+
+    /// Free the memory containing the machine code and statics of the crate. Dropping a
+    /// [`JitCrate`] without calling this leaks the memory, as any function pointer obtained from it
+    /// could still be in use. Embedders which need shared ownership can wrap it in an `Arc` and
+    /// call this once the last reference is gone.
+    ///
+    /// # Safety
+    ///
+    /// No function or static of the crate may be used after this, including through pointers
+    /// returned by [`get_function`](Self::get_function) and [`get_data`](Self::get_data). No
+    /// thread may be executing code of the crate.
+    // FIXME deregister the unwind tables and the debuginfo registered for the crate. Both still
+    // refer to the freed code and could be picked for code jitted at the same address later.
+    pub unsafe fn free_memory(self) {
+        self.jit_module.free_memory();
+    }
+}
+
+/// JIT compile the local crate without running it. This makes it possible to embed the JIT in a
+/// REPL or plugin host using the rustc driver callbacks. Lazy compilation is not supported, so
+/// the whole crate is compiled up front.
+///
+/// The crate has to be an executable for the dependencies to be resolved, but its `main`
+/// function isn't run.
+///
+/// This can be called multiple times in the same process. Every call creates a separate JIT
+/// module with its own symbol table, so the symbols of the jitted crates don't clash, even when
+/// the same crate is jitted twice. The dylibs of the dependencies are loaded without adding their
+/// symbols to the global scope, so different dependencies defining the same `#[no_mangle]` symbol
+/// don't clash either. Each [`JitCrate`] has its own copy of the statics of the local crate, while
+/// the statics of dependencies loaded from the same dylib are shared.
+pub fn jit_crate(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> JitCrate {
+    jit_crate_inner(tcx, backend_config, None)
+}
+
+/// Like [`jit_crate`], but consult `symbol_resolver` for every symbol that is referenced by the
+/// jitted code and not defined by the crate or its dependencies. This allows providing custom
+/// implementations of `extern "C"` functions like `malloc` or a logging sink.
+pub fn jit_crate_with_symbol_resolver(
+    tcx: TyCtxt<'_>,
+    backend_config: BackendConfig,
+    symbol_resolver: JitSymbolResolver,
+) -> JitCrate {
+    jit_crate_inner(tcx, backend_config, Some(symbol_resolver))
+}
+
+fn jit_crate_inner(
+    tcx: TyCtxt<'_>,
+    mut backend_config: BackendConfig,
+    symbol_resolver: Option<JitSymbolResolver>,
+) -> JitCrate {
+    if let CodegenMode::JitLazy = backend_config.codegen_mode {
+        tcx.sess.fatal("lazy JIT compilation is not supported when embedding the JIT");
+    }
+    backend_config.codegen_mode = CodegenMode::Jit;
+    let jit_module = codegen_crate_for_jit(tcx, &backend_config, symbol_resolver);
+
+    let mut exported_symbols = tcx
+        .reachable_non_generics(LOCAL_CRATE)
+        .keys()
+        .filter(|&&def_id| {
+            let attrs = tcx.codegen_fn_attrs(def_id);
+            attrs.flags.contains(CodegenFnAttrFlags::NO_MANGLE) || attrs.export_name.is_some()
+        })
+        .map(|&def_id| tcx.symbol_name(Instance::mono(tcx, def_id)).name.to_string())
         .collect::<Vec<_>>();
-    let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+    exported_symbols.sort();
 
-    // Push a null pointer as a terminating argument. This is required by POSIX and
-    // useful as some dynamic linkers use it as a marker to jump over.
-    argv.push(std::ptr::null());
+    JitCrate { jit_module, exported_symbols }
+}
+
+pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
+    let mut jit_module = codegen_crate_for_jit(tcx, &backend_config, None);
+
+    println!(
+        "Rustc codegen cranelift will JIT run the executable, because -Cllvm-args=mode=jit was passed"
+    );
 
     let start_sig = Signature {
         params: vec![
@@ -133,20 +276,142 @@ pub(crate) fn run_jit(tcx: TyCtxt<'_>, backend_config: BackendConfig) -> ! {
     let start_func_id = jit_module.declare_function("main", Linkage::Import, &start_sig).unwrap();
     let finalized_start: *const u8 = jit_module.get_finalized_function(start_func_id);
 
+    let args = std::iter::once(&*tcx.crate_name(LOCAL_CRATE).as_str().to_string())
+        .chain(backend_config.jit_args.iter().map(|arg| &**arg))
+        .map(|arg| CString::new(arg).unwrap())
+        .collect::<Vec<_>>();
+    let f: extern "C" fn(c_int, *const *const c_char) -> c_int =
+        unsafe { ::std::mem::transmute(finalized_start) };
+    let jit_env = backend_config.jit_env.clone();
+    let jit_cwd = backend_config.jit_cwd.clone();
+    let run_main = move || -> ! {
+        // The program shares the environment and working directory with rustc, so changing them
+        // here affects rustc too.
+        // FIXME lazy compilation of functions containing inline asm breaks when changing the
+        // working directory while the output directory passed to rustc is relative.
+        for (var, val) in jit_env {
+            std::env::set_var(var, val);
+        }
+        if let Some(jit_cwd) = jit_cwd {
+            if let Err(err) = std::env::set_current_dir(&jit_cwd) {
+                eprintln!(
+                    "Failed to change the working directory to `{}`: {}",
+                    jit_cwd.display(),
+                    err
+                );
+                std::process::exit(1);
+            }
+        }
+
+        let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+
+        // Push a null pointer as a terminating argument. This is required by POSIX and
+        // useful as some dynamic linkers use it as a marker to jump over.
+        argv.push(std::ptr::null());
+
+        let ret = f(args.len() as c_int, argv.as_ptr());
+
+        // The jitted program shares libc with rustc, so `exit` runs the `atexit` handlers and the
+        // TLS destructors of the current thread registered by the jitted program, just like when
+        // returning from `main` in a native executable. Unmapping the jitted code isn't necessary
+        // as the whole process exits.
+        // FIXME Windows doesn't run the TLS callbacks of the jitted code, as they can only be
+        // registered using the `.tls` section of a PE image.
+        std::process::exit(ret);
+    };
+
+    if let Some(timeout) = backend_config.jit_timeout {
+        std::thread::spawn(move || {
+            std::thread::sleep(timeout);
+            eprintln!("The program exceeded the time limit of {} seconds", timeout.as_secs());
+            std::process::exit(1);
+        });
+    }
+
+    let mut main_thread = std::thread::Builder::new();
+    if let Some(stack_size) = backend_config.jit_stack_size {
+        main_thread = main_thread.stack_size(stack_size);
+    }
+
+    if let CodegenMode::Jit = backend_config.codegen_mode {
+        if backend_config.jit_stack_size.is_none() {
+            run_main();
+        }
+        // The stack of the current thread can't be resized, so run the program on a new thread.
+        // `run_main` exits the process, so joining only returns when the program panicked.
+        let _ = main_thread.spawn(run_main).unwrap().join();
+        std::process::exit(101);
+    }
+
     LAZY_JIT_STATE.with(|lazy_jit_state| {
         let mut lazy_jit_state = lazy_jit_state.borrow_mut();
         assert!(lazy_jit_state.is_none());
-        *lazy_jit_state = Some(JitState { backend_config, jit_module });
+        *lazy_jit_state = Some(JitState {
+            backend_config,
+            jit_module,
+            lazy_compiled_functions: FxHashMap::default(),
+            speculative_queue: VecDeque::new(),
+        });
     });
 
-    let f: extern "C" fn(c_int, *const *const c_char) -> c_int =
-        unsafe { ::std::mem::transmute(finalized_start) };
-    let ret = f(args.len() as c_int, argv.as_ptr());
-    std::process::exit(ret);
+    // The `TyCtxt` is only accessible on the current thread, so run the program on a new thread
+    // and compile functions on this thread whenever any thread of the program requests it. This
+    // also serializes all compilation, as the JIT module isn't thread safe. For the same reason
+    // speculative compilation happens on this thread too rather than on a pool of threads.
+    // Use the default stack size of the main thread on Linux rather than the much smaller default
+    // of spawned threads, as the program would otherwise run on the main thread.
+    if backend_config.jit_stack_size.is_none() {
+        main_thread = main_thread.stack_size(8 * 1024 * 1024);
+    }
+    let (tx, rx) = mpsc::channel();
+    GLOBAL_MESSAGE_SENDER.set(Mutex::new(tx)).unwrap();
+    main_thread.spawn(run_main).unwrap();
+
+    loop {
+        let JitFnRequest { instance_ptr, result_sender } = match rx.try_recv() {
+            Ok(request) => request,
+            Err(mpsc::TryRecvError::Empty) => {
+                if jit_next_speculative_fn() {
+                    continue;
+                }
+                rx.recv().unwrap()
+            }
+            Err(mpsc::TryRecvError::Disconnected) => unreachable!(),
+        };
+        result_sender.send(CompiledFnPtr(jit_fn(instance_ptr))).unwrap();
+    }
+}
+
+/// A request from a thread of the jitted program to compile a function, sent to the thread
+/// running rustc.
+struct JitFnRequest {
+    instance_ptr: *const Instance<'static>,
+    result_sender: mpsc::Sender<CompiledFnPtr>,
 }
 
+// The `Instance` is only accessed on the rustc thread.
+unsafe impl Send for JitFnRequest {}
+
+struct CompiledFnPtr(*const u8);
+
+unsafe impl Send for CompiledFnPtr {}
+
+static GLOBAL_MESSAGE_SENDER: SyncOnceCell<Mutex<mpsc::Sender<JitFnRequest>>> = SyncOnceCell::new();
+
 #[no_mangle]
 extern "C" fn __clif_jit_fn(instance_ptr: *const Instance<'static>) -> *const u8 {
+    let (result_sender, result_receiver) = mpsc::channel();
+    GLOBAL_MESSAGE_SENDER
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .send(JitFnRequest { instance_ptr, result_sender })
+        .unwrap();
+    result_receiver.recv().unwrap().0
+}
+
+fn jit_fn(instance_ptr: *const Instance<'static>) -> *const u8 {
     rustc_middle::ty::tls::with(|tcx| {
         // lift is used to ensure the correct lifetime for instance.
         let instance = tcx.lift(unsafe { *instance_ptr }).unwrap();
@@ -154,23 +419,199 @@ extern "C" fn __clif_jit_fn(instance_ptr: *const Instance<'static>) -> *const u8
         LAZY_JIT_STATE.with(|lazy_jit_state| {
             let mut lazy_jit_state = lazy_jit_state.borrow_mut();
             let lazy_jit_state = lazy_jit_state.as_mut().unwrap();
-            let jit_module = &mut lazy_jit_state.jit_module;
-            let backend_config = lazy_jit_state.backend_config.clone();
+            compile_lazy_fn(tcx, lazy_jit_state, instance)
+        })
+    })
+}
 
-            let name = tcx.symbol_name(instance).name;
-            let sig = crate::abi::get_function_sig(tcx, jit_module.isa().triple(), instance);
-            let func_id = jit_module.declare_function(name, Linkage::Export, &sig).unwrap();
-            jit_module.prepare_for_function_redefine(func_id).unwrap();
+/// Compile the next function queued for speculative compilation which hasn't been compiled yet.
+/// Returns false if there is no such function.
+fn jit_next_speculative_fn() -> bool {
+    rustc_middle::ty::tls::with(|tcx| {
+        LAZY_JIT_STATE.with(|lazy_jit_state| {
+            let mut lazy_jit_state = lazy_jit_state.borrow_mut();
+            let lazy_jit_state = lazy_jit_state.as_mut().unwrap();
+            while let Some(instance_ptr) = lazy_jit_state.speculative_queue.pop_front() {
+                let instance = tcx.lift(unsafe { *instance_ptr }).unwrap();
+                let name = tcx.symbol_name(instance).name;
+                if !lazy_jit_state.lazy_compiled_functions.contains_key(name) {
+                    compile_lazy_fn(tcx, lazy_jit_state, instance);
+                    return true;
+                }
+            }
+            false
+        })
+    })
+}
+
+fn compile_lazy_fn<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    lazy_jit_state: &mut JitState,
+    instance: Instance<'tcx>,
+) -> *const u8 {
+    let jit_module = &mut lazy_jit_state.jit_module;
+    let backend_config = lazy_jit_state.backend_config.clone();
+
+    let name = tcx.symbol_name(instance).name;
+
+    // Redefining the function below updates its GOT entry, so every call site that calls through
+    // the GOT jumps directly to the compiled function from then on. Only the remaining references
+    // to the shim itself, like function pointers taken before the first call, still get here.
+    // Don't compile the function again for those.
+    if let Some(&compiled_fn) = lazy_jit_state.lazy_compiled_functions.get(name) {
+        return compiled_fn;
+    }
+    let sig = crate::abi::get_function_sig(tcx, jit_module.isa().triple(), instance);
+    let func_id = jit_module.declare_function(name, Linkage::Export, &sig).unwrap();
+    jit_module.prepare_for_function_redefine(func_id).unwrap();
 
-            let mut cx = crate::CodegenCx::new(tcx, backend_config, jit_module.isa(), false);
-            tcx.sess.time("codegen fn", || crate::base::codegen_fn(&mut cx, jit_module, instance));
+    let mut cx = crate::CodegenCx::new(
+        tcx,
+        backend_config.clone(),
+        jit_module.isa(),
+        tcx.sess.opts.debuginfo != DebugInfo::None,
+    );
+    tcx.sess.time("codegen fn", || crate::base::codegen_fn(&mut cx, jit_module, instance));
 
-            assert!(cx.global_asm.is_empty());
-            jit_module.finalize_definitions();
-            unsafe { cx.unwind_context.register_jit(&jit_module) };
-            jit_module.get_finalized_function(func_id)
+    define_inline_asm_for_jit(tcx, jit_module, &cx.global_asm);
+    check_denied_symbols(tcx, jit_module, &backend_config);
+    tcx.sess.abort_if_errors();
+    jit_module.finalize_definitions();
+    unsafe { cx.unwind_context.register_jit(&jit_module) };
+    if let Some(debug_context) = cx.debug_context {
+        unsafe { debug_context.register_jit(&jit_module) };
+    }
+    if let Some(perf_map_entries) = cx.perf_map_entries {
+        crate::debuginfo::write_perf_map(tcx.sess, jit_module, perf_map_entries);
+    }
+    let compiled_fn = jit_module.get_finalized_function(func_id);
+    lazy_jit_state.lazy_compiled_functions.insert(name.to_string(), compiled_fn);
+
+    if backend_config.jit_speculative_compilation {
+        // The functions called directly by this function are likely to be called soon.
+        for callee in direct_callees(tcx, instance) {
+            let callee_name = tcx.symbol_name(callee).name;
+            if lazy_jit_state.lazy_compiled_functions.contains_key(callee_name) {
+                continue;
+            }
+            // Only functions of the local crate are compiled lazily.
+            match lazy_jit_state.jit_module.get_name(callee_name) {
+                Some(FuncOrDataId::Func(func_id))
+                    if lazy_jit_state
+                        .jit_module
+                        .declarations()
+                        .get_function_decl(func_id)
+                        .linkage
+                        != Linkage::Import => {}
+                _ => continue,
+            }
+            let callee: *const Instance<'tcx> = tcx.arena.alloc(callee);
+            lazy_jit_state.speculative_queue.push_back(callee.cast());
+        }
+    }
+
+    compiled_fn
+}
+
+/// Get all functions which are statically known to be called by the given function.
+fn direct_callees<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>) -> Vec<Instance<'tcx>> {
+    let mir = tcx.instance_mir(instance.def);
+    mir.basic_blocks()
+        .iter()
+        .filter_map(|bb_data| match &bb_data.terminator().kind {
+            TerminatorKind::Call { func, .. } => {
+                let fn_ty = instance.subst_mir_and_normalize_erasing_regions(
+                    tcx,
+                    ParamEnv::reveal_all(),
+                    func.ty(mir, tcx),
+                );
+                match *fn_ty.kind() {
+                    ty::FnDef(def_id, substs) => {
+                        Instance::resolve(tcx, ParamEnv::reveal_all(), def_id, substs)
+                            .ok()
+                            .flatten()
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
         })
-    })
+        // Intrinsics, virtual calls and shims don't have a corresponding mono item of their own.
+        .filter(|callee| matches!(callee.def, InstanceDef::Item(_)))
+        .map(|callee| callee.polymorphize(tcx))
+        .collect()
+}
+
+/// Report an error for every symbol imported by the jitted code which has been denied using
+/// `-Cllvm-args=jit_deny_symbol=...`.
+fn check_denied_symbols(tcx: TyCtxt<'_>, jit_module: &JITModule, backend_config: &BackendConfig) {
+    if backend_config.jit_denied_symbols.is_empty() {
+        return;
+    }
+
+    let declarations = jit_module.declarations();
+    let imported_functions = declarations
+        .get_functions()
+        .filter(|(_, decl)| decl.linkage == Linkage::Import)
+        .map(|(_, decl)| &decl.name);
+    let imported_data_objects = declarations
+        .get_data_objects()
+        .filter(|(_, decl)| decl.linkage == Linkage::Import)
+        .map(|(_, decl)| &decl.name);
+    for name in imported_functions.chain(imported_data_objects) {
+        if backend_config.jit_denied_symbols.contains(name) {
+            tcx.sess.err(&format!("the program references the denied symbol `{}`", name));
+        }
+    }
+}
+
+/// Assemble the inline asm wrappers of all functions codegened so far and copy their machine code
+/// into the JIT module, as there is no linker which could combine them with the JITed code.
+fn define_inline_asm_for_jit(tcx: TyCtxt<'_>, jit_module: &mut JITModule, global_asm: &str) {
+    use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+
+    if global_asm.is_empty() {
+        return;
+    }
+
+    if cfg!(not(feature = "inline_asm")) {
+        tcx.sess.fatal("asm! support is disabled while compiling rustc_codegen_cranelift");
+    }
+
+    let object_file =
+        tcx.output_filenames(LOCAL_CRATE).temp_path(OutputType::Object, Some("jit_inline_asm"));
+    super::aot::assemble_global_asm(tcx, global_asm, &object_file);
+    let object_data = std::fs::read(&object_file).unwrap();
+    std::fs::remove_file(&object_file).unwrap();
+    let object = object::File::parse(&object_data).unwrap();
+
+    for symbol in object.symbols() {
+        if symbol.kind() != SymbolKind::Text || !symbol.is_global() {
+            continue;
+        }
+        let name = symbol.name().unwrap();
+
+        // Every wrapper is placed in its own section, so any relocation belongs to this wrapper.
+        let section = object.section_by_index(symbol.section_index().unwrap()).unwrap();
+        if section.relocations().next().is_some() {
+            tcx.sess.fatal(&format!(
+                "asm! referencing symbols is not yet supported in JIT mode (in `{}`)",
+                name,
+            ));
+        }
+        let data = section.data().unwrap();
+        let start = usize::try_from(symbol.address() - section.address()).unwrap();
+        let code = &data[start..start + usize::try_from(symbol.size()).unwrap()];
+
+        let sig = match jit_module.get_name(name) {
+            Some(FuncOrDataId::Func(func_id)) => {
+                jit_module.declarations().get_function_decl(func_id).signature.clone()
+            }
+            _ => unreachable!("inline asm wrapper {} has not been declared", name),
+        };
+        let func_id = jit_module.declare_function(name, Linkage::Export, &sig).unwrap();
+        jit_module.define_function_bytes(func_id, code).unwrap();
+    }
 }
 
 fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
@@ -185,16 +626,24 @@ fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
         .find(|(crate_type, _data)| *crate_type == rustc_session::config::CrateType::Executable)
         .unwrap()
         .1;
-    for &(cnum, _) in &crate_info.used_crates_dynamic {
+    // `used_crates_dynamic` lists every crate before its dependencies, but the dependencies need to
+    // be loaded first for the symbols of the dylibs created from rlibs to resolve.
+    for &(cnum, _) in crate_info.used_crates_dynamic.iter().rev() {
         let src = &crate_info.used_crate_source[&cnum];
         match data[cnum.as_usize() - 1] {
             Linkage::NotLinked | Linkage::IncludedFromDylib => {}
             Linkage::Static => {
                 let name = tcx.crate_name(cnum);
-                let mut err =
-                    tcx.sess.struct_err(&format!("Can't load static lib {}", name.as_str()));
-                err.note("rustc_codegen_cranelift can only load dylibs in JIT mode.");
-                err.emit();
+                if tcx.sess.target.is_like_osx || tcx.sess.target.is_like_windows {
+                    let mut err =
+                        tcx.sess.struct_err(&format!("Can't load static lib {}", name.as_str()));
+                    err.note("rustc_codegen_cranelift can only load dylibs in JIT mode.");
+                    err.emit();
+                    continue;
+                }
+                let rlib = &src.rlib.as_ref().unwrap().0;
+                let dylib = rlib_to_dylib_for_jit(tcx, &name.as_str(), rlib, &dylib_paths);
+                dylib_paths.push(dylib);
             }
             Linkage::Dynamic => {
                 dylib_paths.push(src.dylib.as_ref().unwrap().0.clone());
@@ -205,7 +654,7 @@ fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
     let mut imported_symbols = Vec::new();
     for path in dylib_paths {
         use object::{Object, ObjectSymbol};
-        let lib = libloading::Library::new(&path).unwrap();
+        let lib = load_dylib_for_jit(&path);
         let obj = std::fs::read(path).unwrap();
         let obj = object::File::parse(&obj).unwrap();
         imported_symbols.extend(obj.dynamic_symbols().filter_map(|symbol| {
@@ -237,6 +686,151 @@ fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
     imported_symbols
 }
 
+/// Link the object files of an rlib into a dylib which can be loaded by the JIT using the linker of
+/// the session. The dylib is cached in the temp dir and reused as long as the rlib doesn't change.
+///
+/// The dylib is linked against the dylibs of all dependencies in `deps`. This makes the dynamic
+/// linker resolve references to them within the dependencies of the dylib rather than in the
+/// global scope, where they could clash with the dylibs loaded by other JIT sessions.
+fn rlib_to_dylib_for_jit(
+    tcx: TyCtxt<'_>,
+    crate_name: &str,
+    rlib: &Path,
+    deps: &[PathBuf],
+) -> PathBuf {
+    use std::collections::hash_map::{DefaultHasher, RandomState};
+    use std::hash::{BuildHasher, Hash, Hasher};
+    use std::process::Command;
+
+    use rustc_target::spec::LinkerFlavor;
+
+    // Undefined symbols are resolved against the previously loaded dylibs of the dependencies.
+    let (linker, linker_flavor) = crate::toolchain::linker_and_flavor(tcx.sess);
+    let mut link_cmd = Command::new(&linker);
+    match linker_flavor {
+        LinkerFlavor::Gcc if cfg!(target_os = "macos") => {
+            link_cmd.args(&["-dynamiclib", "-undefined", "dynamic_lookup"]);
+        }
+        LinkerFlavor::Gcc | LinkerFlavor::Ld if cfg!(unix) && !cfg!(target_os = "macos") => {
+            link_cmd.arg("-shared");
+        }
+        // FIXME support Windows. This needs an import library for every dependency and exporting
+        // all symbols from the dylib.
+        _ => tcx.sess.fatal(&format!(
+            "Loading the rlib `{}` in JIT mode requires linking it into a dylib, which is not \
+             supported for the {:?} linker flavor on {}",
+            rlib.display(),
+            linker_flavor,
+            std::env::consts::OS,
+        )),
+    }
+
+    let fs_error = |action: &str, path: &Path, err: std::io::Error| -> ! {
+        tcx.sess.fatal(&format!(
+            "Failed to {} `{}` while loading `{}` in JIT mode: {}",
+            action,
+            path.display(),
+            rlib.display(),
+            err,
+        ))
+    };
+
+    let rlib_metadata = std::fs::metadata(rlib).unwrap_or_else(|err| fs_error("read", rlib, err));
+    let mut hasher = DefaultHasher::new();
+    rlib.hash(&mut hasher);
+    rlib_metadata.len().hash(&mut hasher);
+    rlib_metadata.modified().unwrap_or_else(|err| fs_error("read", rlib, err)).hash(&mut hasher);
+    deps.hash(&mut hasher);
+    let hash = hasher.finish();
+    let cache_dir = std::env::temp_dir().join("cg_clif_jit_dylibs");
+    let dylib = cache_dir.join(format!(
+        "{}{}-{:016x}{}",
+        std::env::consts::DLL_PREFIX,
+        crate_name,
+        hash,
+        std::env::consts::DLL_SUFFIX,
+    ));
+    if dylib.exists() {
+        return dylib;
+    }
+
+    // Extract and link in a directory unique to this process, so concurrent rustc processes
+    // loading the same rlib don't interfere with each other.
+    let object_dir = cache_dir.join(format!(
+        "{}-{:016x}-{}-{:016x}",
+        crate_name,
+        hash,
+        std::process::id(),
+        RandomState::new().build_hasher().finish(),
+    ));
+    std::fs::create_dir_all(&object_dir).unwrap_or_else(|err| fs_error("create", &object_dir, err));
+    let mut object_files = vec![];
+    let mut archive = ar::Archive::new(
+        std::fs::File::open(rlib).unwrap_or_else(|err| fs_error("open", rlib, err)),
+    );
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry.unwrap_or_else(|err| fs_error("read", rlib, err));
+        let name = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+        // Skip the crate metadata and any other non-object members.
+        if !name.ends_with(".o") {
+            continue;
+        }
+        let object_file = object_dir.join(name);
+        std::fs::File::create(&object_file)
+            .and_then(|mut file| std::io::copy(&mut entry, &mut file))
+            .unwrap_or_else(|err| fs_error("write", &object_file, err));
+        object_files.push(object_file);
+    }
+
+    // FIXME symbols with hidden visibility referenced by other crates, like those of shared
+    // generics, can't be resolved between the dylibs.
+    let tmp_dylib = object_dir.join(format!("tmp{}", std::env::consts::DLL_SUFFIX));
+    link_cmd.arg("-o").arg(&tmp_dylib).args(&object_files).args(deps);
+    let status = link_cmd.status().unwrap_or_else(|err| {
+        tcx.sess.fatal(&format!(
+            "Failed to spawn the linker `{}` needed to load `{}` in JIT mode: {}",
+            linker.display(),
+            rlib.display(),
+            err,
+        ))
+    });
+    if !status.success() {
+        tcx.sess.fatal(&format!("Failed to link `{}` into a dylib", rlib.display()));
+    }
+
+    // Renaming is atomic, so other rustc processes never see a partially written dylib. If
+    // another process won the race, its dylib is identical to ours and can be used instead.
+    if let Err(err) = std::fs::rename(&tmp_dylib, &dylib) {
+        if !dylib.exists() {
+            fs_error("move the dylib to", &dylib, err);
+        }
+    }
+    if let Err(err) = std::fs::remove_dir_all(&object_dir) {
+        tcx.sess.warn(&format!(
+            "Failed to remove temporary directory `{}`: {}",
+            object_dir.display(),
+            err
+        ));
+    }
+
+    dylib
+}
+
+#[cfg(unix)]
+fn load_dylib_for_jit(path: &Path) -> libloading::Library {
+    use libloading::os::unix::{Library, RTLD_LOCAL, RTLD_NOW};
+
+    // Keep the symbols out of the global scope, so `#[no_mangle]` symbols of the dependencies of
+    // different JIT sessions in the same process don't clash. The dylibs created by
+    // `rlib_to_dylib_for_jit` find the symbols of their dependencies by linking against them.
+    Library::open(Some(path), RTLD_NOW | RTLD_LOCAL).unwrap().into()
+}
+
+#[cfg(not(unix))]
+fn load_dylib_for_jit(path: &Path) -> libloading::Library {
+    libloading::Library::new(path).unwrap()
+}
+
 fn codegen_shim<'tcx>(cx: &mut CodegenCx<'tcx>, module: &mut JITModule, inst: Instance<'tcx>) {
     let tcx = cx.tcx;
 
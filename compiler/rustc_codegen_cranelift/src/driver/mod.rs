@@ -25,12 +25,15 @@ fn predefine_mono_items<'tcx>(
                     let name = tcx.symbol_name(instance).name;
                     let _inst_guard = crate::PrintOnPanic(|| format!("{:?} {}", instance, name));
                     let sig = get_function_sig(tcx, module.isa().triple(), instance);
-                    let linkage = crate::linkage::get_clif_linkage(
+                    let mut linkage = crate::linkage::get_clif_linkage(
                         mono_item,
                         linkage,
                         visibility,
                         is_compiler_builtins,
                     );
+                    if crate::abi::is_c_variadic(tcx, instance) {
+                        linkage = crate::abi::c_variadic_linkage(linkage);
+                    }
                     module.declare_function(name, linkage, &sig).unwrap();
                 }
                 MonoItem::Static(_) | MonoItem::GlobalAsm(_) => {}
@@ -5,12 +5,14 @@
 use std::fmt::Write;
 
 use rustc_ast::ast::{InlineAsmOptions, InlineAsmTemplatePiece};
+use rustc_data_structures::fx::FxHashSet;
 use rustc_middle::mir::InlineAsmOperand;
+use rustc_span::Symbol;
 use rustc_target::asm::*;
 
 pub(crate) fn codegen_inline_asm<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
-    _span: Span,
+    span: Span,
     template: &[InlineAsmTemplatePiece],
     operands: &[InlineAsmOperand<'tcx>],
     options: InlineAsmOptions,
@@ -84,18 +86,25 @@ pub(crate) fn codegen_inline_asm<'tcx>(
         crate::trap::trap_unimplemented(fx, "Alloca is not supported");
     }
 
+    let arch = fx.tcx.sess.asm_arch.unwrap();
+    match arch {
+        InlineAsmArch::X86_64 | InlineAsmArch::AArch64 => {}
+        _ => fx
+            .tcx
+            .sess
+            .span_fatal(span, &format!("asm! is not yet supported on {:?} by Cranelift", arch)),
+    }
+
+    let regs = allocate_registers(fx.tcx, span, arch, operands);
+
     let mut slot_size = Size::from_bytes(0);
     let mut clobbered_regs = Vec::new();
     let mut inputs = Vec::new();
     let mut outputs = Vec::new();
 
     let mut new_slot = |reg_class: InlineAsmRegClass| {
-        let reg_size = reg_class
-            .supported_types(InlineAsmArch::X86_64)
-            .iter()
-            .map(|(ty, _)| ty.size())
-            .max()
-            .unwrap();
+        let reg_size =
+            reg_class.supported_types(arch).iter().map(|(ty, _)| ty.size()).max().unwrap();
         let align = rustc_target::abi::Align::from_bytes(reg_size.bytes()).unwrap();
         slot_size = slot_size.align_to(align);
         let offset = slot_size;
@@ -104,10 +113,10 @@ pub(crate) fn codegen_inline_asm<'tcx>(
     };
 
     // FIXME overlap input and output slots to save stack space
-    for operand in operands {
+    for (operand, reg) in operands.iter().zip(&regs) {
         match *operand {
-            InlineAsmOperand::In { reg, ref value } => {
-                let reg = expect_reg(reg);
+            InlineAsmOperand::In { reg: _, ref value } => {
+                let reg = reg.unwrap();
                 clobbered_regs.push((reg, new_slot(reg.reg_class())));
                 inputs.push((
                     reg,
@@ -115,8 +124,8 @@ pub(crate) fn codegen_inline_asm<'tcx>(
                     crate::base::codegen_operand(fx, value).load_scalar(fx),
                 ));
             }
-            InlineAsmOperand::Out { reg, late: _, place } => {
-                let reg = expect_reg(reg);
+            InlineAsmOperand::Out { reg: _, late: _, place } => {
+                let reg = reg.unwrap();
                 clobbered_regs.push((reg, new_slot(reg.reg_class())));
                 if let Some(place) = place {
                     outputs.push((
@@ -126,8 +135,8 @@ pub(crate) fn codegen_inline_asm<'tcx>(
                     ));
                 }
             }
-            InlineAsmOperand::InOut { reg, late: _, ref in_value, out_place } => {
-                let reg = expect_reg(reg);
+            InlineAsmOperand::InOut { reg: _, late: _, ref in_value, out_place } => {
+                let reg = reg.unwrap();
                 clobbered_regs.push((reg, new_slot(reg.reg_class())));
                 inputs.push((
                     reg,
@@ -154,36 +163,135 @@ pub(crate) fn codegen_inline_asm<'tcx>(
 
     let generated_asm = generate_asm_wrapper(
         &asm_name,
-        InlineAsmArch::X86_64,
+        arch,
+        fx.tcx.sess.target.is_like_windows,
         options,
         template,
+        &regs,
         clobbered_regs,
         &inputs,
         &outputs,
     );
     fx.cx.global_asm.push_str(&generated_asm);
 
-    call_inline_asm(fx, &asm_name, slot_size, inputs, outputs);
+    call_inline_asm(fx, arch, &asm_name, slot_size, inputs, outputs);
+}
+
+/// Assign a register to every register operand. Operands which only specify a register class get
+/// the first register of that class which doesn't overlap with any other operand.
+fn allocate_registers(
+    tcx: TyCtxt<'_>,
+    span: Span,
+    arch: InlineAsmArch,
+    operands: &[InlineAsmOperand<'_>],
+) -> Vec<Option<InlineAsmReg>> {
+    fn operand_reg(operand: &InlineAsmOperand<'_>) -> Option<InlineAsmRegOrRegClass> {
+        match *operand {
+            InlineAsmOperand::In { reg, .. }
+            | InlineAsmOperand::Out { reg, .. }
+            | InlineAsmOperand::InOut { reg, .. } => Some(reg),
+            InlineAsmOperand::Const { .. }
+            | InlineAsmOperand::SymFn { .. }
+            | InlineAsmOperand::SymStatic { .. } => None,
+        }
+    }
+
+    let mut used_regs = FxHashSet::default();
+    for operand in operands {
+        if let Some(InlineAsmRegOrRegClass::Reg(reg)) = operand_reg(operand) {
+            reg.overlapping_regs(|r| {
+                used_regs.insert(r);
+            });
+        }
+    }
+
+    let has_feature = |feature: &str| {
+        // Cranelift always uses SSE2 on x86_64 and the FP/SIMD extension on AArch64.
+        match (arch, feature) {
+            (InlineAsmArch::X86_64, "sse") | (InlineAsmArch::X86_64, "sse2") => true,
+            (InlineAsmArch::AArch64, "fp") => true,
+            _ => tcx.sess.target_features.contains(&Symbol::intern(feature)),
+        }
+    };
+    let allocatable_regs = allocatable_registers(arch, has_feature, &tcx.sess.target);
+
+    operands
+        .iter()
+        .map(|operand| match operand_reg(operand)? {
+            InlineAsmRegOrRegClass::Reg(reg) => Some(reg),
+            InlineAsmRegOrRegClass::RegClass(class) => {
+                let mut candidates = allocatable_regs
+                    .get(&class)
+                    .map(|regs| regs.iter().copied().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                candidates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let reg = candidates
+                    .into_iter()
+                    .find(|reg| {
+                        let mut is_free = true;
+                        reg.overlapping_regs(|r| is_free &= !used_regs.contains(&r));
+                        is_free
+                    })
+                    .unwrap_or_else(|| {
+                        tcx.sess.span_fatal(
+                            span,
+                            &format!("no free register of class `{}` left for asm!", class.name()),
+                        )
+                    });
+                reg.overlapping_regs(|r| {
+                    used_regs.insert(r);
+                });
+                Some(reg)
+            }
+        })
+        .collect()
 }
 
 fn generate_asm_wrapper(
     asm_name: &str,
     arch: InlineAsmArch,
+    is_like_windows: bool,
     options: InlineAsmOptions,
     template: &[InlineAsmTemplatePiece],
+    regs: &[Option<InlineAsmReg>],
     clobbered_regs: Vec<(InlineAsmReg, Size)>,
     inputs: &[(InlineAsmReg, Size, Value)],
     outputs: &[(InlineAsmReg, Size, CPlace<'_>)],
 ) -> String {
+    // Windows uses SEH unwind info instead of CFI directives.
+    // FIXME emit unwind info for the wrapper on Windows too
+    let emit_cfi = !is_like_windows;
+
     let mut generated_asm = String::new();
-    writeln!(generated_asm, ".globl {}", asm_name).unwrap();
-    writeln!(generated_asm, ".type {},@function", asm_name).unwrap();
-    writeln!(generated_asm, ".section .text.{},\"ax\",@progbits", asm_name).unwrap();
-    writeln!(generated_asm, "{}:", asm_name).unwrap();
+    begin_asm_function(&mut generated_asm, asm_name, is_like_windows);
+    if emit_cfi {
+        generated_asm.push_str(".cfi_startproc\n");
+    }
 
-    generated_asm.push_str(".intel_syntax noprefix\n");
-    generated_asm.push_str("    push rbp\n");
-    generated_asm.push_str("    mov rbp,rdi\n");
+    // Describe the saved frame pointer and return address so backtraces taken inside the inline
+    // asm can unwind into the calling function.
+    // FIXME this doesn't account for user asm that temporarily adjusts the stack pointer
+    match arch {
+        InlineAsmArch::X86_64 => {
+            generated_asm.push_str(".intel_syntax noprefix\n");
+            generated_asm.push_str("    push rbp\n");
+            if emit_cfi {
+                generated_asm.push_str(".cfi_def_cfa_offset 16\n");
+                generated_asm.push_str(".cfi_offset rbp, -16\n");
+            }
+            generated_asm.push_str("    mov rbp,rdi\n");
+        }
+        InlineAsmArch::AArch64 => {
+            generated_asm.push_str("    stp fp, lr, [sp, #-16]!\n");
+            if emit_cfi {
+                generated_asm.push_str(".cfi_def_cfa_offset 16\n");
+                generated_asm.push_str(".cfi_offset x29, -16\n");
+                generated_asm.push_str(".cfi_offset x30, -8\n");
+            }
+            generated_asm.push_str("    mov fp, x0\n");
+        }
+        _ => unreachable!(),
+    }
 
     // Save clobbered registers
     if !options.contains(InlineAsmOptions::NORETURN) {
@@ -208,7 +316,12 @@ fn generate_asm_wrapper(
             InlineAsmTemplatePiece::String(s) => {
                 generated_asm.push_str(s);
             }
-            InlineAsmTemplatePiece::Placeholder { operand_idx: _, modifier: _, span: _ } => todo!(),
+            InlineAsmTemplatePiece::Placeholder { operand_idx, modifier, span: _ } => {
+                if options.contains(InlineAsmOptions::ATT_SYNTAX) {
+                    generated_asm.push('%');
+                }
+                regs[*operand_idx].unwrap().emit(&mut generated_asm, arch, *modifier).unwrap();
+            }
         }
     }
     generated_asm.push('\n');
@@ -228,22 +341,118 @@ fn generate_asm_wrapper(
             restore_register(&mut generated_asm, arch, reg, offset);
         }
 
-        generated_asm.push_str("    pop rbp\n");
-        generated_asm.push_str("    ret\n");
+        match arch {
+            InlineAsmArch::X86_64 => {
+                generated_asm.push_str("    pop rbp\n");
+                if emit_cfi {
+                    generated_asm.push_str(".cfi_def_cfa_offset 8\n");
+                    generated_asm.push_str(".cfi_restore rbp\n");
+                }
+                generated_asm.push_str("    ret\n");
+            }
+            InlineAsmArch::AArch64 => {
+                generated_asm.push_str("    ldp fp, lr, [sp], #16\n");
+                if emit_cfi {
+                    generated_asm.push_str(".cfi_def_cfa_offset 0\n");
+                    generated_asm.push_str(".cfi_restore x29\n");
+                    generated_asm.push_str(".cfi_restore x30\n");
+                }
+                generated_asm.push_str("    ret\n");
+            }
+            _ => unreachable!(),
+        }
     } else {
-        generated_asm.push_str("    ud2\n");
+        match arch {
+            InlineAsmArch::X86_64 => generated_asm.push_str("    ud2\n"),
+            InlineAsmArch::AArch64 => generated_asm.push_str("    brk #0x1\n"),
+            _ => unreachable!(),
+        }
     }
 
-    generated_asm.push_str(".att_syntax\n");
-    writeln!(generated_asm, ".size {name}, .-{name}", name = asm_name).unwrap();
-    generated_asm.push_str(".text\n");
-    generated_asm.push_str("\n\n");
+    if arch == InlineAsmArch::X86_64 {
+        generated_asm.push_str(".att_syntax\n");
+    }
+    if emit_cfi {
+        generated_asm.push_str(".cfi_endproc\n");
+    }
+    end_asm_function(&mut generated_asm, asm_name, is_like_windows);
 
     generated_asm
 }
 
+/// Codegen a `#[naked]` function by emitting the template of its `asm!` as the whole function
+/// body, without any prologue or epilogue.
+pub(crate) fn codegen_naked_asm<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    cx: &mut crate::CodegenCx<'tcx>,
+    instance: Instance<'tcx>,
+    mir: &Body<'tcx>,
+) {
+    let symbol_name = tcx.symbol_name(instance).name;
+
+    let (template, operands, options) = match mir.basic_blocks()[START_BLOCK].terminator().kind {
+        TerminatorKind::InlineAsm {
+            template, ref operands, options, destination: None, ..
+        } if options.contains(InlineAsmOptions::NORETURN) => (template, operands, options),
+        _ => tcx.sess.span_fatal(
+            mir.span,
+            "naked functions must consist of a single asm! with options(noreturn)",
+        ),
+    };
+    if !operands.is_empty() {
+        // FIXME support const and sym operands
+        tcx.sess.span_fatal(mir.span, "operands are not yet supported for asm! in naked functions");
+    }
+
+    let mut generated_asm = String::new();
+    begin_asm_function(&mut generated_asm, symbol_name, tcx.sess.target.is_like_windows);
+
+    let is_x86 = tcx.sess.asm_arch == Some(InlineAsmArch::X86_64);
+    if is_x86 && !options.contains(InlineAsmOptions::ATT_SYNTAX) {
+        generated_asm.push_str(".intel_syntax noprefix\n");
+    }
+    for piece in template {
+        match piece {
+            InlineAsmTemplatePiece::String(s) => generated_asm.push_str(s),
+            InlineAsmTemplatePiece::Placeholder { .. } => unreachable!(),
+        }
+    }
+    generated_asm.push('\n');
+    if is_x86 && !options.contains(InlineAsmOptions::ATT_SYNTAX) {
+        generated_asm.push_str(".att_syntax\n");
+    }
+
+    end_asm_function(&mut generated_asm, symbol_name, tcx.sess.target.is_like_windows);
+    cx.global_asm.push_str(&generated_asm);
+}
+
+pub(crate) fn begin_asm_function(generated_asm: &mut String, name: &str, is_like_windows: bool) {
+    writeln!(generated_asm, ".globl {}", name).unwrap();
+    if is_like_windows {
+        // COFF symbol of storage class external (2) and type function (32)
+        writeln!(generated_asm, ".def {}", name).unwrap();
+        generated_asm.push_str(".scl 2\n");
+        generated_asm.push_str(".type 32\n");
+        generated_asm.push_str(".endef\n");
+        writeln!(generated_asm, ".section .text.{},\"xr\"", name).unwrap();
+    } else {
+        writeln!(generated_asm, ".type {},@function", name).unwrap();
+        writeln!(generated_asm, ".section .text.{},\"ax\",@progbits", name).unwrap();
+    }
+    writeln!(generated_asm, "{}:", name).unwrap();
+}
+
+pub(crate) fn end_asm_function(generated_asm: &mut String, name: &str, is_like_windows: bool) {
+    if !is_like_windows {
+        writeln!(generated_asm, ".size {name}, .-{name}", name = name).unwrap();
+    }
+    generated_asm.push_str(".text\n");
+    generated_asm.push_str("\n\n");
+}
+
 fn call_inline_asm<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
+    arch: InlineAsmArch,
     asm_name: &str,
     slot_size: Size,
     inputs: Vec<(InlineAsmReg, Size, Value)>,
@@ -264,7 +473,10 @@ fn call_inline_asm<'tcx>(
             asm_name,
             Linkage::Import,
             &Signature {
-                call_conv: CallConv::SystemV,
+                call_conv: match arch {
+                    InlineAsmArch::X86_64 => CallConv::SystemV,
+                    _ => CallConv::triple_default(fx.triple()),
+                },
                 params: vec![AbiParam::new(fx.pointer_type)],
                 returns: vec![],
             },
@@ -297,12 +509,23 @@ fn expect_reg(reg_or_class: InlineAsmRegOrRegClass) -> InlineAsmReg {
 }
 
 fn save_register(generated_asm: &mut String, arch: InlineAsmArch, reg: InlineAsmReg, offset: Size) {
-    match arch {
-        InlineAsmArch::X86_64 => {
+    match (arch, reg.reg_class()) {
+        (InlineAsmArch::X86_64, InlineAsmRegClass::X86(X86InlineAsmRegClass::xmm_reg)) => {
+            write!(generated_asm, "    movdqu [rbp+0x{:x}], ", offset.bytes()).unwrap();
+            reg.emit(generated_asm, InlineAsmArch::X86_64, None).unwrap();
+            generated_asm.push('\n');
+        }
+        (InlineAsmArch::X86_64, _) => {
             write!(generated_asm, "    mov [rbp+0x{:x}], ", offset.bytes()).unwrap();
             reg.emit(generated_asm, InlineAsmArch::X86_64, None).unwrap();
             generated_asm.push('\n');
         }
+        (InlineAsmArch::AArch64, reg_class) => {
+            generated_asm.push_str("    str ");
+            reg.emit(generated_asm, InlineAsmArch::AArch64, aarch64_full_width_modifier(reg_class))
+                .unwrap();
+            writeln!(generated_asm, ", [fp, #0x{:x}]", offset.bytes()).unwrap();
+        }
         _ => unimplemented!("save_register for {:?}", arch),
     }
 }
@@ -313,12 +536,34 @@ fn restore_register(
     reg: InlineAsmReg,
     offset: Size,
 ) {
-    match arch {
-        InlineAsmArch::X86_64 => {
+    match (arch, reg.reg_class()) {
+        (InlineAsmArch::X86_64, InlineAsmRegClass::X86(X86InlineAsmRegClass::xmm_reg)) => {
+            generated_asm.push_str("    movdqu ");
+            reg.emit(generated_asm, InlineAsmArch::X86_64, None).unwrap();
+            writeln!(generated_asm, ", [rbp+0x{:x}]", offset.bytes()).unwrap();
+        }
+        (InlineAsmArch::X86_64, _) => {
             generated_asm.push_str("    mov ");
             reg.emit(generated_asm, InlineAsmArch::X86_64, None).unwrap();
             writeln!(generated_asm, ", [rbp+0x{:x}]", offset.bytes()).unwrap();
         }
+        (InlineAsmArch::AArch64, reg_class) => {
+            generated_asm.push_str("    ldr ");
+            reg.emit(generated_asm, InlineAsmArch::AArch64, aarch64_full_width_modifier(reg_class))
+                .unwrap();
+            writeln!(generated_asm, ", [fp, #0x{:x}]", offset.bytes()).unwrap();
+        }
         _ => unimplemented!("restore_register for {:?}", arch),
     }
 }
+
+/// The template modifier to access the whole register, as registers are saved and restored
+/// including the bits not used by the operand.
+fn aarch64_full_width_modifier(reg_class: InlineAsmRegClass) -> Option<char> {
+    match reg_class {
+        InlineAsmRegClass::AArch64(AArch64InlineAsmRegClass::reg) => Some('x'),
+        InlineAsmRegClass::AArch64(AArch64InlineAsmRegClass::vreg)
+        | InlineAsmRegClass::AArch64(AArch64InlineAsmRegClass::vreg_low16) => Some('q'),
+        _ => unreachable!(),
+    }
+}
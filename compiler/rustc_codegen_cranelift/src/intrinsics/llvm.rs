@@ -106,6 +106,12 @@ pub(crate) fn codegen_llvm_intrinsic_call<'tcx>(
             let dest = CPlace::for_ptr(Pointer::new(mem_addr), a.layout());
             dest.write_cvalue(fx, a);
         };
+
+        // Used by `core::hint::spin_loop` on AArch64
+        "llvm.aarch64.isb", (c _option) {
+            // FIXME emit an actual isb instruction once Cranelift supports it
+            fx.bcx.ins().fence();
+        };
     }
 
     if let Some((_, dest)) = destination {
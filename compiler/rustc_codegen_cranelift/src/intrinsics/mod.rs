@@ -392,14 +392,141 @@ fn bool_to_zero_or_max_uint<'tcx>(
     );
 }
 
+fn volatile_mem_flags(aligned: bool) -> MemFlags {
+    // Cranelift never merges, removes or reorders memory accesses, so a plain load or store
+    // without the `notrap` flag is as strong as a volatile access.
+    let mut flags = MemFlags::new();
+    if aligned {
+        flags.set_aligned();
+    }
+    flags
+}
+
+/// Copy `layout.size` bytes using accesses exactly as wide as the alignment of `layout` (at most
+/// 8 bytes). Unlike `emit_small_memory_copy` this never calls `memcpy` and never widens accesses.
+fn volatile_memory_copy<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    dst: Pointer,
+    src: Pointer,
+    layout: TyAndLayout<'tcx>,
+    aligned: bool,
+) {
+    let flags = volatile_mem_flags(aligned);
+    let chunk_size = layout.align.abi.bytes().min(8);
+    let chunk_ty = Type::int(u16::try_from(chunk_size * 8).unwrap()).unwrap();
+    for offset in (0..layout.size.bytes()).step_by(usize::try_from(chunk_size).unwrap()) {
+        let offset = i64::try_from(offset).unwrap();
+        let val = src.offset_i64(fx, offset).load(fx, chunk_ty, flags);
+        dst.offset_i64(fx, offset).store(fx, val, flags);
+    }
+}
+
+/// Emit a loop calling `f` with the offset of every `chunk_size` bytes chunk below `byte_amount`.
+/// When `backwards` is true at runtime the chunks are visited starting from the end, which is
+/// necessary to implement `memmove` when the destination is after the source.
+fn volatile_chunk_loop<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    byte_amount: Value,
+    chunk_size: u64,
+    backwards: Option<Value>,
+    mut f: impl FnMut(&mut FunctionCx<'_, '_, 'tcx>, Value),
+) {
+    let loop_block = fx.bcx.create_block();
+    let loop_block2 = fx.bcx.create_block();
+    let done_block = fx.bcx.create_block();
+    let offset = fx.bcx.append_block_param(loop_block, fx.pointer_type);
+    let zero = fx.bcx.ins().iconst(fx.pointer_type, 0);
+    fx.bcx.ins().jump(loop_block, &[zero]);
+
+    fx.bcx.switch_to_block(loop_block);
+    let done = fx.bcx.ins().icmp(IntCC::Equal, offset, byte_amount);
+    fx.bcx.ins().brnz(done, done_block, &[]);
+    fx.bcx.ins().jump(loop_block2, &[]);
+
+    fx.bcx.switch_to_block(loop_block2);
+    let chunk_offset = if let Some(backwards) = backwards {
+        let remaining = fx.bcx.ins().isub(byte_amount, offset);
+        let backwards_offset = fx.bcx.ins().iadd_imm(remaining, -(chunk_size as i64));
+        fx.bcx.ins().select(backwards, backwards_offset, offset)
+    } else {
+        offset
+    };
+    f(fx, chunk_offset);
+    let offset = fx.bcx.ins().iadd_imm(offset, chunk_size as i64);
+    fx.bcx.ins().jump(loop_block, &[offset]);
+
+    fx.bcx.switch_to_block(done_block);
+}
+
+fn codegen_volatile_load<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    ptr: Pointer,
+    layout: TyAndLayout<'tcx>,
+    aligned: bool,
+) -> CValue<'tcx> {
+    let flags = volatile_mem_flags(aligned);
+    match layout.abi {
+        Abi::Scalar(ref scalar) => {
+            let val = ptr.load(fx, scalar_to_clif_type(fx.tcx, scalar.clone()), flags);
+            CValue::by_val(val, layout)
+        }
+        Abi::Vector { ref element, count } => {
+            let vector_ty =
+                scalar_to_clif_type(fx.tcx, element.clone()).by(u16::try_from(count).unwrap());
+            let val = ptr.load(fx, vector_ty.unwrap(), flags);
+            CValue::by_val(val, layout)
+        }
+        Abi::ScalarPair(ref a, ref b) => {
+            let b_offset = crate::value_and_place::scalar_pair_calculate_b_offset(fx.tcx, a, b);
+            let a_ty = scalar_to_clif_type(fx.tcx, a.clone());
+            let b_ty = scalar_to_clif_type(fx.tcx, b.clone());
+            let a_val = ptr.load(fx, a_ty, flags);
+            let b_val = ptr.offset(fx, b_offset).load(fx, b_ty, flags);
+            CValue::by_val_pair(a_val, b_val, layout)
+        }
+        _ => {
+            let tmp = CPlace::new_stack_slot(fx, layout);
+            volatile_memory_copy(fx, tmp.to_ptr(), ptr, layout, aligned);
+            tmp.to_cvalue(fx)
+        }
+    }
+}
+
+fn codegen_volatile_store<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    ptr: Pointer,
+    val: CValue<'tcx>,
+    aligned: bool,
+) {
+    let flags = volatile_mem_flags(aligned);
+    let layout = val.layout();
+    match layout.abi {
+        Abi::Scalar(_) | Abi::Vector { .. } => {
+            let val = val.load_scalar(fx);
+            ptr.store(fx, val, flags);
+        }
+        Abi::ScalarPair(ref a, ref b) => {
+            let b_offset = crate::value_and_place::scalar_pair_calculate_b_offset(fx.tcx, a, b);
+            let (a_val, b_val) = val.load_scalar_pair(fx);
+            ptr.store(fx, a_val, flags);
+            ptr.offset(fx, b_offset).store(fx, b_val, flags);
+        }
+        _ => {
+            let (src, _meta) = val.force_stack(fx);
+            volatile_memory_copy(fx, ptr, src, layout, aligned);
+        }
+    }
+}
+
 pub(crate) fn codegen_intrinsic_call<'tcx>(
     fx: &mut FunctionCx<'_, '_, 'tcx>,
     instance: Instance<'tcx>,
     args: &[mir::Operand<'tcx>],
     destination: Option<(CPlace<'tcx>, BasicBlock)>,
-    span: Span,
+    source_info: mir::SourceInfo,
 ) {
     let def_id = instance.def_id();
+    let span = source_info.span;
     let substs = instance.substs;
 
     let intrinsic = fx.tcx.item_name(def_id);
@@ -413,7 +540,7 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
                     trap_abort(fx, "Called intrinsic::abort.");
                 }
                 sym::transmute => {
-                    crate::base::codegen_panic(fx, "Transmuting to uninhabited type.", span);
+                    crate::base::codegen_panic(fx, "Transmuting to uninhabited type.", source_info);
                 }
                 _ => unimplemented!("unsupported instrinsic {}", intrinsic),
             }
@@ -438,8 +565,6 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
         exp2f64(flt) -> f64 => exp2,
         sqrtf32(flt) -> f32 => sqrtf,
         sqrtf64(flt) -> f64 => sqrt,
-        powif32(a, x) -> f32 => __powisf2, // compiler-builtins
-        powif64(a, x) -> f64 => __powidf2, // compiler-builtins
         powf32(a, x) -> f32 => powf,
         powf64(a, x) -> f64 => pow,
         logf32(flt) -> f32 => logf,
@@ -456,13 +581,7 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
         copysignf64(x, y) -> f64 => copysign,
 
         // rounding variants
-        // FIXME use clif insts
-        floorf32(flt) -> f32 => floorf,
-        floorf64(flt) -> f64 => floor,
-        ceilf32(flt) -> f32 => ceilf,
-        ceilf64(flt) -> f64 => ceil,
-        truncf32(flt) -> f32 => truncf,
-        truncf64(flt) -> f64 => trunc,
+        // Cranelift doesn't have an instruction for rounding half away from zero.
         roundf32(flt) -> f32 => roundf,
         roundf64(flt) -> f64 => round,
 
@@ -486,7 +605,18 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
         breakpoint, () {
             fx.bcx.ins().debugtrap();
         };
-        copy | copy_nonoverlapping, <elem_ty> (v src, v dst, v count) {
+        copy_nonoverlapping, <elem_ty> (v src, v dst, o count) {
+            let elem_layout = fx.layout_of(elem_ty);
+            crate::base::codegen_copy_nonoverlapping(
+                fx,
+                dst,
+                src,
+                count,
+                elem_layout.size.bytes(),
+                elem_layout.align.abi.bytes(),
+            );
+        };
+        copy, <elem_ty> (v src, v dst, v count) {
             let elem_size: u64 = fx.layout_of(elem_ty).size.bytes();
             assert_eq!(args.len(), 3);
             let byte_amount = if elem_size != 1 {
@@ -495,17 +625,13 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
                 count
             };
 
-            if intrinsic == sym::copy_nonoverlapping {
-                // FIXME emit_small_memcpy
-                fx.bcx.call_memcpy(fx.module.target_config(), dst, src, byte_amount);
-            } else {
-                // FIXME emit_small_memmove
-                fx.bcx.call_memmove(fx.module.target_config(), dst, src, byte_amount);
-            }
+            // FIXME emit_small_memmove
+            fx.bcx.call_memmove(fx.module.target_config(), dst, src, byte_amount);
         };
         // NOTE: the volatile variants have src and dst swapped
         volatile_copy_memory | volatile_copy_nonoverlapping_memory, <elem_ty> (v dst, v src, v count) {
-            let elem_size: u64 = fx.layout_of(elem_ty).size.bytes();
+            let elem_layout = fx.layout_of(elem_ty);
+            let elem_size: u64 = elem_layout.size.bytes();
             assert_eq!(args.len(), 3);
             let byte_amount = if elem_size != 1 {
                 fx.bcx.ins().imul_imm(count, elem_size as i64)
@@ -513,14 +639,20 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
                 count
             };
 
-            // FIXME make the copy actually volatile when using emit_small_mem{cpy,move}
-            if intrinsic == sym::volatile_copy_nonoverlapping_memory {
-                // FIXME emit_small_memcpy
-                fx.bcx.call_memcpy(fx.module.target_config(), dst, src, byte_amount);
+            // Like `volatile_memory_copy`, but with a runtime amount of bytes. Both pointers are
+            // aligned to `elem_ty`, so chunks at the same offset never partially overlap.
+            let chunk_size = elem_layout.align.abi.bytes().min(8);
+            let chunk_ty = Type::int(u16::try_from(chunk_size * 8).unwrap()).unwrap();
+            let flags = volatile_mem_flags(true);
+            let backwards = if intrinsic == sym::volatile_copy_nonoverlapping_memory {
+                None
             } else {
-                // FIXME emit_small_memmove
-                fx.bcx.call_memmove(fx.module.target_config(), dst, src, byte_amount);
-            }
+                Some(fx.bcx.ins().icmp(IntCC::UnsignedGreaterThan, dst, src))
+            };
+            volatile_chunk_loop(fx, byte_amount, chunk_size, backwards, |fx, offset| {
+                let val = Pointer::new(src).offset_value(fx, offset).load(fx, chunk_ty, flags);
+                Pointer::new(dst).offset_value(fx, offset).store(fx, val, flags);
+            });
         };
         size_of_val, <T> (c ptr) {
             let layout = fx.layout_of(T);
@@ -669,18 +801,57 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
         transmute, (c from) {
             ret.write_cvalue_transmute(fx, from);
         };
-        write_bytes | volatile_set_memory, (c dst, v val, v count) {
+        write_bytes, (c dst, o val, o count) {
             let pointee_ty = dst.layout().ty.builtin_deref(true).unwrap().ty;
-            let pointee_size = fx.layout_of(pointee_ty).size.bytes();
+            let pointee_layout = fx.layout_of(pointee_ty);
+            let dst_ptr = dst.load_scalar(fx);
+            crate::base::codegen_write_bytes(
+                fx,
+                dst_ptr,
+                val,
+                count,
+                pointee_layout.size.bytes(),
+                pointee_layout.align.abi.bytes(),
+            );
+        };
+        volatile_set_memory, (c dst, v val, v count) {
+            let pointee_ty = dst.layout().ty.builtin_deref(true).unwrap().ty;
+            let pointee_layout = fx.layout_of(pointee_ty);
+            let pointee_size = pointee_layout.size.bytes();
             let count = if pointee_size != 1 {
                 fx.bcx.ins().imul_imm(count, pointee_size as i64)
             } else {
                 count
             };
             let dst_ptr = dst.load_scalar(fx);
-            // FIXME make the memset actually volatile when switching to emit_small_memset
-            // FIXME use emit_small_memset
-            fx.bcx.call_memset(fx.module.target_config(), dst_ptr, val, count);
+
+            // Store `val` repeated to fill chunks as wide as the alignment of the pointee.
+            let chunk_size = pointee_layout.align.abi.bytes().min(8);
+            let chunk_ty = Type::int(u16::try_from(chunk_size * 8).unwrap()).unwrap();
+            let chunk_val = if chunk_size != 1 {
+                let val = fx.bcx.ins().uextend(chunk_ty, val);
+                fx.bcx.ins().imul_imm(val, ((u64::MAX / 0xff) >> (64 - chunk_size * 8)) as i64)
+            } else {
+                val
+            };
+            let flags = volatile_mem_flags(true);
+            volatile_chunk_loop(fx, count, chunk_size, None, |fx, offset| {
+                Pointer::new(dst_ptr).offset_value(fx, offset).store(fx, chunk_val, flags);
+            });
+        };
+        va_end, (v _va_list) {
+            // Nothing to clean up
+        };
+        va_copy, (v dest, c src) {
+            let va_list_layout = fx.layout_of(src.layout().ty.builtin_deref(true).unwrap().ty);
+            let src = src.load_scalar(fx);
+            let dest = CPlace::for_ptr(Pointer::new(dest), va_list_layout);
+            dest.write_cvalue(fx, CValue::by_ref(Pointer::new(src), va_list_layout));
+        };
+        va_arg, <T> (v va_list) {
+            let layout = fx.layout_of(T);
+            let val = crate::abi::codegen_va_arg(fx, span, Pointer::new(va_list), layout);
+            ret.write_cvalue(fx, val);
         };
         ctlz | ctlz_nonzero, <T> (v arg) {
             // FIXME trap on `ctlz_nonzero` with zero arg.
@@ -806,48 +977,47 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
             ret.write_cvalue(fx, res);
         };
         assert_inhabited | assert_zero_valid | assert_uninit_valid, <T> () {
+            // These checks only depend on the monomorphized type, so they are either statically
+            // known to pass, in which case nothing is emitted, or statically known to fail, in
+            // which case the intrinsic call becomes an unconditional panic.
             let layout = fx.layout_of(T);
-            if layout.abi.is_uninhabited() {
-                with_no_trimmed_paths(|| crate::base::codegen_panic(
-                    fx,
-                    &format!("attempted to instantiate uninhabited type `{}`", T),
-                    span,
-                ));
-                return;
-            }
-
-            if intrinsic == sym::assert_zero_valid && !layout.might_permit_raw_init(fx, /*zero:*/ true).unwrap() {
-                with_no_trimmed_paths(|| crate::base::codegen_panic(
-                    fx,
-                    &format!("attempted to zero-initialize type `{}`, which is invalid", T),
-                    span,
-                ));
-                return;
-            }
+            let do_panic = match intrinsic {
+                sym::assert_inhabited => layout.abi.is_uninhabited(),
+                // We unwrap as the error type is `!`.
+                sym::assert_zero_valid => !layout.might_permit_raw_init(fx, /*zero:*/ true).unwrap(),
+                // We unwrap as the error type is `!`.
+                sym::assert_uninit_valid => !layout.might_permit_raw_init(fx, /*zero:*/ false).unwrap(),
+                _ => unreachable!(),
+            };
 
-            if intrinsic == sym::assert_uninit_valid && !layout.might_permit_raw_init(fx, /*zero:*/ false).unwrap() {
-                with_no_trimmed_paths(|| crate::base::codegen_panic(
-                    fx,
-                    &format!("attempted to leave type `{}` uninitialized, which is invalid", T),
-                    span,
-                ));
+            if do_panic {
+                // Keep these messages in sync with `rustc_codegen_ssa`.
+                let msg_str = with_no_trimmed_paths(|| {
+                    if layout.abi.is_uninhabited() {
+                        // Use this error even for the other intrinsics as it is more precise.
+                        format!("attempted to instantiate uninhabited type `{}`", T)
+                    } else if intrinsic == sym::assert_zero_valid {
+                        format!("attempted to zero-initialize type `{}`, which is invalid", T)
+                    } else {
+                        format!("attempted to leave type `{}` uninitialized, which is invalid", T)
+                    }
+                });
+                crate::base::codegen_panic(fx, &msg_str, source_info);
                 return;
             }
         };
 
         volatile_load | unaligned_volatile_load, (c ptr) {
-            // Cranelift treats loads as volatile by default
-            // FIXME correctly handle unaligned_volatile_load
             let inner_layout =
                 fx.layout_of(ptr.layout().ty.builtin_deref(true).unwrap().ty);
-            let val = CValue::by_ref(Pointer::new(ptr.load_scalar(fx)), inner_layout);
+            let aligned = intrinsic == sym::volatile_load;
+            let ptr = Pointer::new(ptr.load_scalar(fx));
+            let val = codegen_volatile_load(fx, ptr, inner_layout, aligned);
             ret.write_cvalue(fx, val);
         };
         volatile_store | unaligned_volatile_store, (v ptr, c val) {
-            // Cranelift treats stores as volatile by default
-            // FIXME correctly handle unaligned_volatile_store
-            let dest = CPlace::for_ptr(Pointer::new(ptr), val.layout());
-            dest.write_cvalue(fx, val);
+            let aligned = intrinsic == sym::volatile_store;
+            codegen_volatile_store(fx, Pointer::new(ptr), val, aligned);
         };
 
         pref_align_of | min_align_of | needs_drop | type_id | type_name | variant_count, () {
@@ -882,7 +1052,7 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
         };
 
         caller_location, () {
-            let caller_location = fx.get_caller_location(span);
+            let caller_location = fx.get_caller_location(source_info);
             ret.write_cvalue(fx, caller_location);
         };
 
@@ -1058,29 +1228,55 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
             ret.write_cvalue(fx, old);
         };
 
-        minnumf32, (v a, v b) {
-            let val = fx.bcx.ins().fmin(a, b);
-            let val = CValue::by_val(val, fx.layout_of(fx.tcx.types.f32));
-            ret.write_cvalue(fx, val);
+        // Cranelift lowers the following rounding instructions to libcalls by itself when the
+        // target doesn't support them, like x86_64 without SSE 4.1.
+        floorf32 | floorf64, (v flt) {
+            let res = fx.bcx.ins().floor(flt);
+            ret.write_cvalue(fx, CValue::by_val(res, ret.layout()));
         };
-        minnumf64, (v a, v b) {
-            let val = fx.bcx.ins().fmin(a, b);
-            let val = CValue::by_val(val, fx.layout_of(fx.tcx.types.f64));
-            ret.write_cvalue(fx, val);
+        ceilf32 | ceilf64, (v flt) {
+            let res = fx.bcx.ins().ceil(flt);
+            ret.write_cvalue(fx, CValue::by_val(res, ret.layout()));
         };
-        maxnumf32, (v a, v b) {
-            let val = fx.bcx.ins().fmax(a, b);
-            let val = CValue::by_val(val, fx.layout_of(fx.tcx.types.f32));
-            ret.write_cvalue(fx, val);
+        truncf32 | truncf64, (v flt) {
+            let res = fx.bcx.ins().trunc(flt);
+            ret.write_cvalue(fx, CValue::by_val(res, ret.layout()));
         };
-        maxnumf64, (v a, v b) {
-            let val = fx.bcx.ins().fmax(a, b);
-            let val = CValue::by_val(val, fx.layout_of(fx.tcx.types.f64));
-            ret.write_cvalue(fx, val);
+        // The default floating point environment rounds to nearest, ties to even and the
+        // inexact exception can't be observed from Rust, so `rint` and `nearbyint` are equivalent.
+        rintf32 | rintf64 | nearbyintf32 | nearbyintf64, (v flt) {
+            let res = fx.bcx.ins().nearest(flt);
+            ret.write_cvalue(fx, CValue::by_val(res, ret.layout()));
+        };
+
+        powif32 | powif64, (v a, o x) {
+            let val = crate::num::codegen_float_powi(fx, a, x);
+            ret.write_cvalue(fx, CValue::by_val(val, ret.layout()));
+        };
+
+        minnumf32 | minnumf64 | maxnumf32 | maxnumf64, (v a, v b) {
+            let val = if intrinsic == sym::minnumf32 || intrinsic == sym::minnumf64 {
+                fx.bcx.ins().fmin(a, b)
+            } else {
+                fx.bcx.ins().fmax(a, b)
+            };
+
+            // `fmin` and `fmax` return NaN when either input is NaN, while `minnum` and `maxnum`
+            // must return the other operand in that case.
+            let a_is_nan = fx.bcx.ins().fcmp(FloatCC::Unordered, a, a);
+            let b_is_nan = fx.bcx.ins().fcmp(FloatCC::Unordered, b, b);
+            let val = fx.bcx.ins().select(b_is_nan, a, val);
+            let val = fx.bcx.ins().select(a_is_nan, b, val);
+
+            ret.write_cvalue(fx, CValue::by_val(val, ret.layout()));
         };
 
         kw.Try, (v f, v data, v _catch_fn) {
-            // FIXME once unwinding is supported, change this to actually catch panics
+            // FIXME once unwinding is supported, change this to actually catch panics. The unwind
+            // tables of jitted functions are already registered with the host unwinder, but
+            // Cranelift doesn't support exception edges yet, so there is no landing pad to call
+            // `catch_fn` from. Until then `catch_unwind` and the test harness only work with
+            // `-Cpanic=abort`, both in AOT and in JIT mode.
             let f_sig = fx.bcx.func.import_signature(Signature {
                 call_conv: CallConv::triple_default(fx.triple()),
                 params: vec![AbiParam::new(fx.bcx.func.dfg.value_type(data))],
@@ -1095,6 +1291,23 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
         };
 
         fadd_fast | fsub_fast | fmul_fast | fdiv_fast | frem_fast, (c x, c y) {
+            if !matches!(x.layout().ty.kind(), ty::Float(_)) {
+                fx.tcx.sess.span_err(
+                    span,
+                    &format!(
+                        "invalid monomorphization of `{}` intrinsic: expected basic float type, found `{}`",
+                        intrinsic, x.layout().ty,
+                    ),
+                );
+                // Prevent verifier error
+                crate::trap::trap_unreachable(fx, "compilation should not have succeeded");
+                return;
+            }
+
+            // Cranelift doesn't have fast-math flags, so the fast variants are lowered to the
+            // regular IEEE operations. This is a valid refinement, as the fast variants merely
+            // allow the result to deviate from IEEE semantics. `fadd`, `fsub`, `fmul` and `fdiv`
+            // are emitted inline, while `frem` still needs a libcall.
             let res = crate::num::codegen_float_binop(fx, match intrinsic {
                 sym::fadd_fast => BinOp::Add,
                 sym::fsub_fast => BinOp::Sub,
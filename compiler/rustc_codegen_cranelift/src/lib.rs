@@ -1,4 +1,5 @@
 #![feature(rustc_private, decl_macro, never_type, hash_drain_filter, vec_into_raw_parts)]
+#![feature(once_cell)]
 #![warn(rust_2018_idioms)]
 #![warn(unused_lifetimes)]
 #![warn(unreachable_pub)]
@@ -30,13 +31,17 @@
 use rustc_middle::dep_graph::{WorkProduct, WorkProductId};
 use rustc_middle::middle::cstore::{EncodedMetadata, MetadataLoader};
 use rustc_middle::ty::query::Providers;
-use rustc_session::config::OutputFilenames;
+use rustc_session::config::{OutputFilenames, OutputType};
 use rustc_session::Session;
 
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::settings::{self, Configurable};
 
 pub use crate::config::*;
+#[cfg(feature = "jit")]
+pub use crate::driver::jit::{
+    jit_crate, jit_crate_with_symbol_resolver, JitCrate, JitSymbolResolver,
+};
 use crate::prelude::*;
 
 mod abi;
@@ -127,6 +132,12 @@ struct CodegenCx<'tcx> {
     cached_context: Context,
     debug_context: Option<DebugContext<'tcx>>,
     unwind_context: UnwindContext,
+    dump_abi: bool,
+    emulated_tls: crate::constant::EmulatedTls,
+    /// Every function defined so far if a perf map needs to be written.
+    perf_map_entries: Option<Vec<crate::debuginfo::PerfMapEntry>>,
+    /// The disassembly of every function defined so far if `--emit asm` was passed.
+    asm_output: Option<String>,
 }
 
 impl<'tcx> CodegenCx<'tcx> {
@@ -140,13 +151,69 @@ fn new(
 
         let unwind_context =
             UnwindContext::new(tcx, isa, matches!(backend_config.codegen_mode, CodegenMode::Aot));
-        let debug_context = if debug_info { Some(DebugContext::new(tcx, isa)) } else { None };
+        // FIXME emit CodeView debuginfo for MSVC targets. The MSVC linker doesn't understand DWARF.
+        let debug_context = if debug_info && !tcx.sess.target.is_like_msvc {
+            Some(DebugContext::new(tcx, isa, backend_config.dwarf_version))
+        } else {
+            None
+        };
         CodegenCx {
             tcx,
             global_asm: String::new(),
             cached_context: Context::new(),
             debug_context,
             unwind_context,
+            dump_abi: backend_config.dump_abi,
+            emulated_tls: if backend_config.emulated_tls || crate::constant::uses_emutls(tcx) {
+                crate::constant::EmulatedTls::All
+            } else if !matches!(backend_config.codegen_mode, CodegenMode::Aot) {
+                // cranelift-jit doesn't support any of the native TLS relocations. Thread locals
+                // of jitted code can't be part of the static TLS block of the host anyway, as it
+                // has already been allocated for every running thread when the code is jitted.
+                // Emulated TLS allocates them on first use per thread instead. Dependencies are
+                // loaded from dylibs using native TLS, so their thread locals are left alone.
+                // FIXME this needs `__emutls_get_address` from libgcc to be loaded in the host,
+                // which isn't the case on macOS.
+                crate::constant::EmulatedTls::Local
+            } else {
+                crate::constant::EmulatedTls::Native
+            },
+            perf_map_entries: if backend_config.jit_perf_map
+                && !matches!(backend_config.codegen_mode, CodegenMode::Aot)
+            {
+                Some(vec![])
+            } else {
+                None
+            },
+            asm_output: if tcx.sess.opts.output_types.contains_key(&OutputType::Assembly)
+                && matches!(backend_config.codegen_mode, CodegenMode::Aot)
+            {
+                Some(String::new())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Adjust the session options to the configuration passed in using `-Cllvm-args`. This has to be
+/// called by the driver before the session is created.
+pub fn configure_session_options(opts: &mut rustc_session::config::Options) {
+    // Invalid options are reported once the backend is used.
+    let config = match BackendConfig::from_opts(&opts.cg.llvm_args) {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+
+    if config.cross_cgu_inline && opts.optimize != rustc_session::config::OptLevel::No {
+        // Cranelift doesn't inline, so use the MIR inliner instead. It runs before the crate is
+        // partitioned into CGUs and has access to the MIR of `#[inline]` functions of other
+        // crates. Explicitly passed `-Zinline-mir` options take precedence.
+        let debugging_opts = &mut opts.debugging_opts;
+        debugging_opts.inline_mir.get_or_insert(true);
+        if let Some(threshold) = config.cross_cgu_inline_threshold {
+            debugging_opts.inline_mir_threshold.get_or_insert(threshold);
+            debugging_opts.inline_mir_hint_threshold.get_or_insert(threshold * 2);
         }
     }
 }
@@ -171,8 +238,33 @@ fn metadata_loader(&self) -> Box<dyn MetadataLoader + Sync> {
     fn provide(&self, _providers: &mut Providers) {}
     fn provide_extern(&self, _providers: &mut Providers) {}
 
-    fn target_features(&self, _sess: &Session) -> Vec<rustc_span::Symbol> {
-        vec![]
+    fn target_features(&self, sess: &Session) -> Vec<rustc_span::Symbol> {
+        use rustc_span::Symbol;
+
+        if crate::target_triple(sess).architecture != target_lexicon::Architecture::X86_64 {
+            return vec![];
+        }
+
+        let config = if let Some(config) = self.config.clone() {
+            config
+        } else {
+            BackendConfig::from_opts(&sess.opts.cg.llvm_args).unwrap_or_else(|err| sess.fatal(&err))
+        };
+        let isa = build_isa(sess, &config);
+
+        // `sse` and `sse2` are part of the x86_64 baseline, so Cranelift always uses them.
+        let mut features = vec![Symbol::intern("sse"), Symbol::intern("sse2")];
+        for value in isa.isa_flags() {
+            if value.as_bool() != Some(true) {
+                continue;
+            }
+            if let Some(&(feature, _)) =
+                X86_64_TARGET_FEATURES.iter().find(|&&(_, flag)| flag == value.name)
+            {
+                features.push(Symbol::intern(feature));
+            }
+        }
+        features
     }
 
     fn codegen_crate(
@@ -230,7 +322,35 @@ fn link(
 }
 
 fn target_triple(sess: &Session) -> target_lexicon::Triple {
-    sess.target.llvm_target.parse().unwrap()
+    sess.target.llvm_target.parse().unwrap_or_else(|err| {
+        sess.fatal(&format!(
+            "Cranelift doesn't recognize the target `{}`: {}",
+            sess.target.llvm_target, err
+        ))
+    })
+}
+
+/// Whether the target passes floats in integer registers and lacks float instructions.
+fn uses_soft_float(sess: &Session) -> bool {
+    let target = &sess.target;
+    let soft_float_feature = target
+        .features
+        .split(',')
+        .chain(sess.opts.cg.target_feature.split(','))
+        .fold(false, |soft_float, feature| match feature {
+            "+soft-float" | "-fp-armv8" => true,
+            "-soft-float" | "+fp-armv8" => false,
+            _ => soft_float,
+        });
+    match &*target.arch {
+        // Only the `eabihf` targets use the hard-float calling convention.
+        "arm" => soft_float_feature || !target.llvm_target.ends_with("hf"),
+        // Without the F or D extension the default `ilp32` and `lp64` ABIs are used.
+        "riscv32" | "riscv64" => {
+            !(target.llvm_abiname.ends_with('f') || target.llvm_abiname.ends_with('d'))
+        }
+        _ => soft_float_feature,
+    }
 }
 
 fn build_isa(sess: &Session, backend_config: &BackendConfig) -> Box<dyn isa::TargetIsa + 'static> {
@@ -238,13 +358,66 @@ fn build_isa(sess: &Session, backend_config: &BackendConfig) -> Box<dyn isa::Tar
 
     let target_triple = crate::target_triple(sess);
 
+    // Custom target specs may use an `llvm-target` that doesn't match the rest of the spec. Reject
+    // them rather than silently miscompiling.
+    let triple_pointer_width = target_triple.pointer_width().map(|width| width.bits());
+    if triple_pointer_width != Ok(sess.target.pointer_width as u8) {
+        sess.fatal(&format!(
+            "The pointer width of `{}` doesn't match the target pointer width of {} bits",
+            target_triple, sess.target.pointer_width
+        ));
+    }
+    let triple_is_big_endian = target_triple.endianness() == Ok(target_lexicon::Endianness::Big);
+    if triple_is_big_endian != (sess.target.endian == rustc_target::abi::Endian::Big) {
+        sess.fatal(&format!(
+            "The endianness of `{}` doesn't match the target endianness",
+            target_triple
+        ));
+    }
+
+    if uses_soft_float(sess) {
+        // FIXME implement this by passing floats in integer registers and lowering all float
+        // operations to compiler-builtins calls.
+        sess.fatal("Soft-float targets are not yet supported by Cranelift");
+    }
+
     let mut flags_builder = settings::builder();
-    flags_builder.enable("is_pic").unwrap();
+    // Bare-metal targets often use the static relocation model to avoid the need for a dynamic
+    // loader or self-relocation. Position independent executables are produced by the linker from
+    // PIC objects, so `-Crelocation-model=pie` doesn't exist.
+    use rustc_target::spec::RelocModel;
+    let is_pic = match sess.relocation_model() {
+        RelocModel::Pic => true,
+        RelocModel::Static | RelocModel::DynamicNoPic => false,
+        reloc_model @ (RelocModel::Ropi | RelocModel::Rwpi | RelocModel::RopiRwpi) => {
+            sess.fatal(&format!(
+                "The `{:?}` relocation model is not supported by Cranelift",
+                reloc_model
+            ));
+        }
+    };
+    flags_builder.set("is_pic", if is_pic { "true" } else { "false" }).unwrap();
+
+    // Cranelift always uses 32bit pc-relative calls and either GOT or 64bit absolute relocations
+    // for data, which is valid for all code models that only require the code to fit in 2GB.
+    if sess.code_model() == Some(rustc_target::spec::CodeModel::Large) {
+        // FIXME call functions through a register for the large code model once Cranelift
+        // supports it.
+        sess.fatal("The large code model is not supported by Cranelift");
+    }
+
     flags_builder.set("enable_probestack", "false").unwrap(); // __cranelift_probestack is not provided
+    // FIXME call `__chkstk` for stack frames bigger than a page on Windows. Without it accessing a
+    // big stack frame may skip over the guard page, causing an access violation.
     let enable_verifier = if backend_config.enable_verifier { "true" } else { "false" };
     flags_builder.set("enable_verifier", enable_verifier).unwrap();
 
     let tls_model = match target_triple.binary_format {
+        // Cranelift only supports the general dynamic model on ELF. This also works for fully
+        // static executables like those for musl targets with `+crt-static`, as the linker relaxes
+        // it to the local exec model and static musl provides `__tls_get_addr`.
+        // FIXME use the local exec model directly for static executables once Cranelift supports
+        // it.
         BinaryFormat::Elf => "elf_gd",
         BinaryFormat::Macho => "macho",
         BinaryFormat::Coff => "coff",
@@ -271,30 +444,128 @@ fn build_isa(sess: &Session, backend_config: &BackendConfig) -> Box<dyn isa::Tar
 
     let variant = cranelift_codegen::isa::BackendVariant::MachInst;
 
-    let isa_builder = match sess.opts.cg.target_cpu.as_deref() {
+    let is_x86_64 = target_triple.architecture == target_lexicon::Architecture::X86_64;
+    let mut isa_builder = match sess.opts.cg.target_cpu.as_deref() {
         Some("native") => {
-            let builder = cranelift_native::builder_with_options(variant, true).unwrap();
-            builder
+            // The features of the host are probed using `cpuid`, which is meaningless when
+            // compiling for another architecture.
+            if target_triple.architecture != target_lexicon::HOST.architecture {
+                sess.fatal(&format!(
+                    "`-Ctarget-cpu=native` is not supported when compiling for `{}` on a `{}` host",
+                    target_triple.architecture,
+                    target_lexicon::HOST.architecture,
+                ));
+            }
+            cranelift_native::builder_with_options(variant, true).unwrap_or_else(|err| {
+                sess.fatal(&format!("Failed to detect the features of the host cpu: {}", err))
+            })
         }
         Some(value) => {
-            let mut builder = cranelift_codegen::isa::lookup_variant(target_triple, variant).unwrap();
+            let mut builder = lookup_isa(sess, target_triple, variant);
             if let Err(_) = builder.enable(value) {
                 sess.fatal("The specified target cpu isn't currently supported by Cranelift.");
             }
             builder
         }
         None => {
-            let mut builder = cranelift_codegen::isa::lookup_variant(target_triple, variant).unwrap();
-            // Don't use "haswell" as the default, as it implies `has_lzcnt`.
-            // macOS CI is still at Ivy Bridge EP, so `lzcnt` is interpreted as `bsr`.
-            builder.enable("nehalem").unwrap();
+            let mut builder = lookup_isa(sess, target_triple, variant);
+            if is_x86_64 {
+                // Don't use "haswell" as the default, as it implies `has_lzcnt`.
+                // macOS CI is still at Ivy Bridge EP, so `lzcnt` is interpreted as `bsr`.
+                builder.enable("nehalem").unwrap();
+            }
             builder
         }
     };
-    
+
+    if is_x86_64 {
+        apply_x86_64_target_features(sess, &mut isa_builder);
+    }
+
     isa_builder.finish(flags)
 }
 
+/// The target features of x86_64 which have an equivalent ISA flag in the x64 backend of Cranelift.
+const X86_64_TARGET_FEATURES: &[(&str, &str)] = &[
+    ("sse3", "has_sse3"),
+    ("ssse3", "has_ssse3"),
+    ("sse4.1", "has_sse41"),
+    ("sse4.2", "has_sse42"),
+    ("popcnt", "has_popcnt"),
+    ("avx", "has_avx"),
+    ("avx2", "has_avx2"),
+    ("avx512f", "has_avx512f"),
+    ("avx512dq", "has_avx512dq"),
+    ("avx512vl", "has_avx512vl"),
+    ("bmi1", "has_bmi1"),
+    ("bmi2", "has_bmi2"),
+    ("lzcnt", "has_lzcnt"),
+];
+
+/// Map the target features of the target spec and `-Ctarget-feature` onto the ISA flags of the
+/// x64 backend of Cranelift. Unknown features of the target spec are ignored, while unknown
+/// features passed using `-Ctarget-feature` produce a warning.
+fn apply_x86_64_target_features(sess: &Session, isa_builder: &mut isa::Builder) {
+    let target_spec_features = sess.target.features.split(',').map(|feature| (feature, false));
+    let cli_features = sess.opts.cg.target_feature.split(',').map(|feature| (feature, true));
+    for (feature, from_cli) in target_spec_features.chain(cli_features) {
+        let (enable, name) = if let Some(name) = feature.strip_prefix('+') {
+            ("true", name)
+        } else if let Some(name) = feature.strip_prefix('-') {
+            ("false", name)
+        } else {
+            continue;
+        };
+        if let Some(&(_, flag)) = X86_64_TARGET_FEATURES.iter().find(|&&(f, _)| f == name) {
+            isa_builder.set(flag, enable).unwrap();
+        } else if from_cli && !matches!(name, "crt-static" | "fxsr" | "sse" | "sse2") {
+            // `crt-static` is handled by rustc itself and the rest is part of the baseline.
+            sess.warn(&format!(
+                "target feature `{}` is not supported by Cranelift and will be ignored",
+                feature
+            ));
+        }
+    }
+}
+
+fn lookup_isa(
+    sess: &Session,
+    target_triple: target_lexicon::Triple,
+    variant: isa::BackendVariant,
+) -> isa::Builder {
+    let arch = target_triple.architecture;
+    match arch {
+        target_lexicon::Architecture::Riscv64(_) => {
+            // FIXME the RISC-V backend of Cranelift only exists as an incomplete legacy backend.
+            // Wire it up once it has been ported to the new backend framework with support for the
+            // LP64D calling convention, atomics and TLS relocations.
+            sess.fatal("The `riscv64` architecture isn't supported by Cranelift yet");
+        }
+        target_lexicon::Architecture::X86_32(_) => {
+            // FIXME the x64 backend of Cranelift only supports 64bit mode. Only the legacy x86
+            // backend, which cg_clif no longer uses, supports 32bit mode. Supporting i686 needs
+            // 32bit pointers in the x64 backend, 64bit integer legalization and SSE based float
+            // handling to avoid x87.
+            sess.fatal("32bit x86 isn't supported by Cranelift yet");
+        }
+        target_lexicon::Architecture::Arm(_) => {
+            // FIXME the arm32 backend of Cranelift only supports a small subset of 32bit integer
+            // operations. It lacks 64bit integers, floats, atomics and the AAPCS rules for passing
+            // arguments, all of which are needed to compile libcore.
+            sess.fatal("32bit ARM isn't supported by Cranelift yet");
+        }
+        target_lexicon::Architecture::Wasm32 => {
+            // FIXME Cranelift can only consume wasm, not produce it. Emitting wasm objects for
+            // `wasm-ld` would require a separate backend.
+            sess.fatal("Cranelift can't emit WebAssembly");
+        }
+        _ => {}
+    }
+    isa::lookup_variant(target_triple, variant).unwrap_or_else(|err| {
+        sess.fatal(&format!("The `{}` architecture isn't supported by Cranelift: {}", arch, err))
+    })
+}
+
 /// This is the entrypoint for a hot plugged rustc_codegen_cranelift
 #[no_mangle]
 pub fn __rustc_codegen_backend() -> Box<dyn CodegenBackend> {
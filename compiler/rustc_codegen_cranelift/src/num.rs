@@ -435,3 +435,75 @@ pub(crate) fn codegen_ptr_binop<'tcx>(
         CValue::by_val(fx.bcx.ins().bint(types::I8, res), fx.layout_of(fx.tcx.types.bool))
     }
 }
+
+/// Raise `base` to the integer power `exp` using exponentiation by squaring. This avoids a
+/// dependency on `__powisf2` and `__powidf2`, which are not available on all platforms. When the
+/// exponent is a constant the multiplications are emitted inline, otherwise a loop is emitted.
+pub(crate) fn codegen_float_powi<'tcx>(
+    fx: &mut FunctionCx<'_, '_, 'tcx>,
+    base: Value,
+    exp: &Operand<'tcx>,
+) -> Value {
+    let ty = fx.bcx.func.dfg.value_type(base);
+    let one = match ty {
+        types::F32 => fx.bcx.ins().f32const(1.0),
+        types::F64 => fx.bcx.ins().f64const(1.0),
+        _ => unreachable!("powi on {}", ty),
+    };
+
+    let const_exp = crate::constant::mir_operand_get_const_val(fx, exp)
+        .and_then(|exp| exp.try_to_bits(Size::from_bytes(4)))
+        .map(|exp| exp as u32 as i32);
+
+    if let Some(const_exp) = const_exp {
+        let mut acc = None;
+        let mut square = base;
+        let mut remaining = const_exp.unsigned_abs();
+        while remaining != 0 {
+            if remaining & 1 != 0 {
+                acc = Some(match acc {
+                    Some(acc) => fx.bcx.ins().fmul(acc, square),
+                    None => square,
+                });
+            }
+            remaining >>= 1;
+            if remaining != 0 {
+                square = fx.bcx.ins().fmul(square, square);
+            }
+        }
+        let res = acc.unwrap_or(one);
+        return if const_exp < 0 { fx.bcx.ins().fdiv(one, res) } else { res };
+    }
+
+    let exp = codegen_operand(fx, exp).load_scalar(fx);
+    let is_neg = fx.bcx.ins().icmp_imm(IntCC::SignedLessThan, exp, 0);
+    let neg_exp = fx.bcx.ins().ineg(exp);
+    // `i32::MIN` stays `i32::MIN` when negated, which is correct when interpreted as unsigned.
+    let abs_exp = fx.bcx.ins().select(is_neg, neg_exp, exp);
+
+    let loop_block = fx.bcx.create_block();
+    let body_block = fx.bcx.create_block();
+    let done_block = fx.bcx.create_block();
+    let acc = fx.bcx.append_block_param(loop_block, ty);
+    let square = fx.bcx.append_block_param(loop_block, ty);
+    let remaining = fx.bcx.append_block_param(loop_block, types::I32);
+    let res = fx.bcx.append_block_param(done_block, ty);
+
+    fx.bcx.ins().jump(loop_block, &[one, base, abs_exp]);
+
+    fx.bcx.switch_to_block(loop_block);
+    fx.bcx.ins().brz(remaining, done_block, &[acc]);
+    fx.bcx.ins().jump(body_block, &[]);
+
+    fx.bcx.switch_to_block(body_block);
+    let bit = fx.bcx.ins().band_imm(remaining, 1);
+    let mul = fx.bcx.ins().fmul(acc, square);
+    let new_acc = fx.bcx.ins().select(bit, mul, acc);
+    let new_square = fx.bcx.ins().fmul(square, square);
+    let new_remaining = fx.bcx.ins().ushr_imm(remaining, 1);
+    fx.bcx.ins().jump(loop_block, &[new_acc, new_square, new_remaining]);
+
+    fx.bcx.switch_to_block(done_block);
+    let recip = fx.bcx.ins().fdiv(one, res);
+    fx.bcx.ins().select(is_neg, recip, res)
+}
@@ -19,20 +19,43 @@ pub(crate) fn get_toolchain_binary(sess: &Session, tool: &str) -> PathBuf {
             linker.set_file_name(tool)
         }
     } else {
-        let tool_file_name = linker_file_name
+        let mut tool_file_name = linker_file_name
             .replace("ld", tool)
             .replace("gcc", tool)
             .replace("clang", tool)
             .replace("cc", tool);
 
+        if sess.target.os == "android" {
+            // The NDK linker wrappers contain the API level, like `aarch64-linux-android21-clang`,
+            // but the binutils don't, like `aarch64-linux-android-as`.
+            tool_file_name = strip_android_api_level(&tool_file_name);
+        }
+
+        if matches!(&*sess.target.os, "illumos" | "solaris") && tool_file_name == "as" {
+            // The system assembler uses a different syntax. The GNU assembler is installed as
+            // `gas` instead.
+            tool_file_name = "gas".to_string();
+        }
+
         linker.set_file_name(tool_file_name)
     }
 
     linker
 }
 
+fn strip_android_api_level(tool_file_name: &str) -> String {
+    if let Some(idx) = tool_file_name.find("android") {
+        let (prefix, rest) = tool_file_name.split_at(idx + "android".len());
+        let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        format!("{}{}", prefix, rest)
+    } else {
+        tool_file_name.to_string()
+    }
+}
+
+/// Returns the linker of the session and its flavor.
 // Adapted from https://github.com/rust-lang/rust/blob/5db778affee7c6600c8e7a177c48282dab3f6292/src/librustc_codegen_ssa/back/link.rs#L848-L931
-fn linker_and_flavor(sess: &Session) -> (PathBuf, LinkerFlavor) {
+pub(crate) fn linker_and_flavor(sess: &Session) -> (PathBuf, LinkerFlavor) {
     fn infer_from(
         sess: &Session,
         linker: Option<PathBuf>,
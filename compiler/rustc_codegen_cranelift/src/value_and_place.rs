@@ -2,6 +2,7 @@
 
 use crate::prelude::*;
 
+use cranelift_codegen::entity::EntityRef;
 use cranelift_codegen::ir::immediates::Offset32;
 
 fn codegen_field<'tcx>(
@@ -49,7 +50,7 @@ fn codegen_field<'tcx>(
     }
 }
 
-fn scalar_pair_calculate_b_offset(
+pub(crate) fn scalar_pair_calculate_b_offset(
     tcx: TyCtxt<'_>,
     a_scalar: &Scalar,
     b_scalar: &Scalar,
@@ -299,11 +300,30 @@ pub(crate) fn new_stack_slot(
             return CPlace::no_place(layout);
         }
 
+        let size = u32::try_from(layout.size.bytes()).unwrap();
+        let align = u32::try_from(layout.align.abi.bytes()).unwrap();
+        if align > 16 {
+            // Rounding the size up to a multiple of 16 bytes doesn't give a big enough alignment.
+            // Over-allocate and realign the address at runtime instead. This is for example
+            // necessary for `#[repr(align(32))]` values passed indirectly, as the callee expects
+            // its argument to be properly aligned.
+            let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
+                kind: StackSlotKind::ExplicitSlot,
+                size: size + align,
+                offset: None,
+            });
+            let base_ptr = fx.bcx.ins().stack_addr(fx.pointer_type, stack_slot, 0);
+            let misalign_offset = fx.bcx.ins().urem_imm(base_ptr, i64::from(align));
+            let realign_offset = fx.bcx.ins().irsub_imm(misalign_offset, i64::from(align));
+            let ptr = fx.bcx.ins().iadd(base_ptr, realign_offset);
+            return CPlace { inner: CPlaceInner::Addr(Pointer::new(ptr), None), layout };
+        }
+
         let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
             kind: StackSlotKind::ExplicitSlot,
             // FIXME Don't force the size to a multiple of 16 bytes once Cranelift gets a way to
             // specify stack slot alignment.
-            size: (u32::try_from(layout.size.bytes()).unwrap() + 15) / 16 * 16,
+            size: (size + 15) / 16 * 16,
             offset: None,
         });
         CPlace { inner: CPlaceInner::Addr(Pointer::stack_slot(stack_slot), None), layout }
@@ -353,19 +373,19 @@ pub(crate) fn to_cvalue(self, fx: &mut FunctionCx<'_, '_, 'tcx>) -> CValue<'tcx>
         match self.inner {
             CPlaceInner::Var(_local, var) => {
                 let val = fx.bcx.use_var(var);
-                //fx.bcx.set_val_label(val, cranelift_codegen::ir::ValueLabel::new(var.index()));
+                fx.bcx.set_val_label(val, cranelift_codegen::ir::ValueLabel::new(var.index()));
                 CValue::by_val(val, layout)
             }
             CPlaceInner::VarPair(_local, var1, var2) => {
                 let val1 = fx.bcx.use_var(var1);
-                //fx.bcx.set_val_label(val1, cranelift_codegen::ir::ValueLabel::new(var1.index()));
+                fx.bcx.set_val_label(val1, cranelift_codegen::ir::ValueLabel::new(var1.index()));
                 let val2 = fx.bcx.use_var(var2);
-                //fx.bcx.set_val_label(val2, cranelift_codegen::ir::ValueLabel::new(var2.index()));
+                fx.bcx.set_val_label(val2, cranelift_codegen::ir::ValueLabel::new(var2.index()));
                 CValue::by_val_pair(val1, val2, layout)
             }
             CPlaceInner::VarLane(_local, var, lane) => {
                 let val = fx.bcx.use_var(var);
-                //fx.bcx.set_val_label(val, cranelift_codegen::ir::ValueLabel::new(var.index()));
+                fx.bcx.set_val_label(val, cranelift_codegen::ir::ValueLabel::new(var.index()));
                 let val = fx.bcx.ins().extractlane(val, lane);
                 CValue::by_val(val, layout)
             }
@@ -455,7 +475,7 @@ fn transmute_value<'tcx>(
                 }
                 _ => unreachable!("write_cvalue_transmute: {:?} -> {:?}", src_ty, dst_ty),
             };
-            //fx.bcx.set_val_label(data, cranelift_codegen::ir::ValueLabel::new(var.index()));
+            fx.bcx.set_val_label(data, cranelift_codegen::ir::ValueLabel::new(var.index()));
             fx.bcx.def_var(var, data);
         }
 
@@ -500,13 +520,13 @@ fn transmute_value<'tcx>(
 
                 // First get the old vector
                 let vector = fx.bcx.use_var(var);
-                //fx.bcx.set_val_label(vector, cranelift_codegen::ir::ValueLabel::new(var.index()));
+                fx.bcx.set_val_label(vector, cranelift_codegen::ir::ValueLabel::new(var.index()));
 
                 // Next insert the written lane into the vector
                 let vector = fx.bcx.ins().insertlane(vector, data, lane);
 
                 // Finally write the new vector
-                //fx.bcx.set_val_label(vector, cranelift_codegen::ir::ValueLabel::new(var.index()));
+                fx.bcx.set_val_label(vector, cranelift_codegen::ir::ValueLabel::new(var.index()));
                 fx.bcx.def_var(var, vector);
 
                 return;
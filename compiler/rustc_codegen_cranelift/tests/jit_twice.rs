@@ -0,0 +1,91 @@
+//! Test that the same crate can be jitted twice in one process using `jit_crate`, with every
+//! `JitCrate` having its own copy of the statics of the crate.
+
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+extern crate rustc_interface;
+
+use rustc_codegen_cranelift::{jit_crate, BackendConfig, CraneliftCodegenBackend, JitCrate};
+use rustc_interface::{interface, Queries};
+
+const SOURCE: &str = r#"
+static mut COUNTER: u32 = 0;
+
+#[no_mangle]
+extern "C" fn jit_twice_increment() -> u32 {
+    unsafe {
+        COUNTER += 1;
+        COUNTER
+    }
+}
+
+fn main() {}
+"#;
+
+#[derive(Default)]
+struct JitCallbacks {
+    jit_crate: Option<JitCrate>,
+}
+
+// The compiler runs on a separate thread. The `JitCrate` is only used once that thread has exited.
+unsafe impl Send for JitCallbacks {}
+
+impl rustc_driver::Callbacks for JitCallbacks {
+    fn after_analysis<'tcx>(
+        &mut self,
+        _compiler: &interface::Compiler,
+        queries: &'tcx Queries<'tcx>,
+    ) -> rustc_driver::Compilation {
+        queries.global_ctxt().unwrap().peek_mut().enter(|tcx| {
+            self.jit_crate = Some(jit_crate(tcx, BackendConfig::default()));
+        });
+        rustc_driver::Compilation::Stop
+    }
+}
+
+fn jit(source_file: &std::path::Path, sysroot: &str) -> JitCrate {
+    let args = [
+        "rustc".to_string(),
+        source_file.display().to_string(),
+        "--sysroot".to_string(),
+        sysroot.to_string(),
+        "-Cpanic=abort".to_string(),
+        "-Cprefer-dynamic".to_string(),
+    ];
+    let mut callbacks = JitCallbacks::default();
+    let mut run_compiler = rustc_driver::RunCompiler::new(&args, &mut callbacks);
+    run_compiler.set_make_codegen_backend(Some(Box::new(|_| {
+        Box::new(CraneliftCodegenBackend { config: None })
+    })));
+    run_compiler.run().unwrap();
+    callbacks.jit_crate.unwrap()
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn jit_same_crate_twice() {
+    let output =
+        std::process::Command::new("rustc").args(&["--print", "sysroot"]).output().unwrap();
+    let sysroot = String::from_utf8(output.stdout).unwrap();
+
+    let source_file =
+        std::env::temp_dir().join(format!("cg_clif_jit_twice_{}.rs", std::process::id()));
+    std::fs::write(&source_file, SOURCE).unwrap();
+
+    let first = jit(&source_file, sysroot.trim());
+    let second = jit(&source_file, sysroot.trim());
+    std::fs::remove_file(&source_file).unwrap();
+
+    assert_eq!(first.exported_symbols(), ["jit_twice_increment"]);
+    unsafe {
+        let first_increment =
+            first.get_function::<extern "C" fn() -> u32>("jit_twice_increment").unwrap();
+        let second_increment =
+            second.get_function::<extern "C" fn() -> u32>("jit_twice_increment").unwrap();
+        assert_eq!(first_increment(), 1);
+        assert_eq!(first_increment(), 2);
+        assert_eq!(second_increment(), 1);
+        assert_eq!(first_increment(), 3);
+    }
+}